@@ -0,0 +1,218 @@
+// plan の各歩と `/explore` の結果 (観測) の対応
+//
+// 観測 0 は最初の部屋のラベルで、i 歩目 (0-indexed) のあとの観測が i + 1 番目。
+// `[d]` も観測を1つ生むが部屋は動かないので、ドアの移動とラベルの書き込みは分けて扱う。
+use std::fmt;
+
+use crate::plan::{Plan, Step};
+
+/// plan の1歩と、その前後の観測の位置
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AlignedStep {
+    pub step: Step,
+    pub before: usize,
+    pub after: usize,
+}
+
+/// 観測 `from` の部屋からドア `door` を通ると、観測 `to` の部屋に着く
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Transition {
+    pub from: usize,
+    pub door: usize,
+    pub to: usize,
+}
+
+/// 観測 `before` の部屋に `label` を書き、その部屋を観測 `after` で見る
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LabelWrite {
+    pub before: usize,
+    pub label: usize,
+    pub after: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AlignmentError {
+    /// 結果の長さが plan の歩数 + 1 になっていない
+    LengthMismatch { expected: usize, found: usize },
+    /// ラベルが 0..=3 にない
+    LabelOutOfRange { observation: usize, label: usize },
+    /// `[d]` を書いた直後の観測が d になっていない
+    MarkNotObserved {
+        step: usize,
+        label: usize,
+        found: usize,
+    },
+}
+
+impl fmt::Display for AlignmentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AlignmentError::LengthMismatch { expected, found } => write!(
+                f,
+                "expected {} observations (steps + 1) but got {}",
+                expected, found
+            ),
+            AlignmentError::LabelOutOfRange { observation, label } => write!(
+                f,
+                "observation {} is label {} (must be 0..=3)",
+                observation, label
+            ),
+            AlignmentError::MarkNotObserved { step, label, found } => write!(
+                f,
+                "step {} writes [{}] but observation {} right after it is {}",
+                step,
+                label,
+                step + 1,
+                found
+            ),
+        }
+    }
+}
+
+impl std::error::Error for AlignmentError {}
+
+/// plan の各歩の前後の観測の位置。結果がなくても決まる
+pub fn aligned_steps(plan: &Plan) -> impl Iterator<Item = AlignedStep> + '_ {
+    plan.steps()
+        .iter()
+        .enumerate()
+        .map(|(i, &step)| AlignedStep {
+            step,
+            before: i,
+            after: i + 1,
+        })
+}
+
+/// plan のドアの移動だけ。結果がなくても決まる
+pub fn transitions(plan: &Plan) -> impl Iterator<Item = Transition> + '_ {
+    aligned_steps(plan).filter_map(|aligned| match aligned.step {
+        Step::Door(door) => Some(Transition {
+            from: aligned.before,
+            door,
+            to: aligned.after,
+        }),
+        Step::Mark(_) => None,
+    })
+}
+
+/// 1つの plan とその結果
+#[derive(Debug, Clone, Copy)]
+pub struct Alignment<'a> {
+    plan: &'a Plan,
+    results: &'a [usize],
+}
+
+impl<'a> Alignment<'a> {
+    /// 結果が plan と矛盾しないか確かめる (長さ・ラベルの範囲・`[d]` の直後のラベル)
+    pub fn new(plan: &'a Plan, results: &'a [usize]) -> Result<Self, AlignmentError> {
+        if results.len() != plan.result_len() {
+            return Err(AlignmentError::LengthMismatch {
+                expected: plan.result_len(),
+                found: results.len(),
+            });
+        }
+        if let Some((observation, &label)) = results.iter().enumerate().find(|(_, l)| **l > 3) {
+            return Err(AlignmentError::LabelOutOfRange { observation, label });
+        }
+        let alignment = Alignment { plan, results };
+        if let Some(write) = alignment
+            .label_writes()
+            .find(|write| results[write.after] != write.label)
+        {
+            return Err(AlignmentError::MarkNotObserved {
+                step: write.before,
+                label: write.label,
+                found: results[write.after],
+            });
+        }
+        Ok(alignment)
+    }
+
+    pub fn plan(&self) -> &'a Plan {
+        self.plan
+    }
+
+    pub fn results(&self) -> &'a [usize] {
+        self.results
+    }
+
+    /// 観測 `observation` で見えたラベル
+    pub fn label(&self, observation: usize) -> usize {
+        self.results[observation]
+    }
+
+    pub fn steps(&self) -> impl Iterator<Item = AlignedStep> + 'a {
+        aligned_steps(self.plan)
+    }
+
+    /// ドアの移動だけ
+    pub fn transitions(&self) -> impl Iterator<Item = Transition> + 'a {
+        transitions(self.plan)
+    }
+
+    /// `[d]` だけ
+    pub fn label_writes(&self) -> impl Iterator<Item = LabelWrite> + 'a {
+        self.steps().filter_map(|aligned| match aligned.step {
+            Step::Mark(label) => Some(LabelWrite {
+                before: aligned.before,
+                label,
+                after: aligned.after,
+            }),
+            Step::Door(_) => None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transitions_and_label_writes() {
+        let plan: Plan = "0[2]1[3]".parse().unwrap();
+        let results = [0, 1, 2, 3, 3];
+        let alignment = Alignment::new(&plan, &results).unwrap();
+        assert_eq!(
+            alignment.transitions().collect::<Vec<_>>(),
+            [
+                Transition {
+                    from: 0,
+                    door: 0,
+                    to: 1
+                },
+                Transition {
+                    from: 2,
+                    door: 1,
+                    to: 3
+                },
+            ]
+        );
+        let writes: Vec<_> = alignment.label_writes().collect();
+        assert_eq!(writes.len(), 2);
+        assert_eq!(alignment.label(writes[0].after), 2);
+        assert_eq!((writes[1].before, writes[1].after), (3, 4));
+
+        assert_eq!(
+            Alignment::new(&plan, &results[..4]).unwrap_err(),
+            AlignmentError::LengthMismatch {
+                expected: 5,
+                found: 4
+            }
+        );
+        assert_eq!(
+            Alignment::new(&plan, &[0, 4, 2, 3, 3]).unwrap_err(),
+            AlignmentError::LabelOutOfRange {
+                observation: 1,
+                label: 4
+            }
+        );
+        assert_eq!(
+            Alignment::new(&plan, &[0, 1, 2, 3, 1]).unwrap_err(),
+            AlignmentError::MarkNotObserved {
+                step: 3,
+                label: 3,
+                found: 1
+            }
+        );
+    }
+}
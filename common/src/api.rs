@@ -0,0 +1,440 @@
+use std::{fmt, sync::Mutex, thread, time::Duration};
+
+use reqwest::blocking::Client;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+
+use crate::alignment::{Alignment, AlignmentError};
+use crate::budget::BudgetTracker;
+use crate::cassette::{Recorder, Replayer, Session, strip_team_id};
+use crate::config::ClientConfig;
+pub use crate::error::ApiError;
+use crate::plan::{Plan, PlanError, Step};
+use crate::problem::Problem;
+use crate::retry::Endpoint;
+pub use crate::retry::RetryPolicy;
+
+// /select
+#[derive(Serialize)]
+pub struct SelectRequest<'a> {
+    pub(crate) id: &'a str,
+    #[serde(rename = "problemName")]
+    pub(crate) problem_name: &'a str,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SelectResponse {
+    #[serde(rename = "problemName")]
+    pub problem_name: String,
+}
+
+// /explore
+#[derive(Serialize)]
+pub struct ExploreRequest<'a> {
+    pub(crate) id: &'a str,
+    pub(crate) plans: &'a [String],
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ExploreResponse {
+    pub results: Vec<Vec<usize>>,
+    #[serde(rename = "queryCount")]
+    pub query_count: usize,
+}
+
+// /guess
+#[derive(Serialize, Debug)]
+pub struct GuessRequest<'a> {
+    pub(crate) id: &'a str,
+    pub(crate) map: Map,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Map {
+    pub rooms: Vec<usize>,
+    #[serde(rename = "startingRoom")]
+    pub starting_room: usize,
+    pub connections: Vec<Connection>,
+}
+
+impl Map {
+    /// このマップ上で plan を実行したときに観測されるラベル列 (`/explore` と同じ)
+    ///
+    /// `[d]` は今いる部屋のラベルを d に書き換え、書き換えた値を観測する。
+    /// plan は `Plan` として厳密に parse し、おかしければそのエラーを返す。
+    pub fn explore(&self, plan: &str) -> Result<Vec<usize>, PlanError> {
+        let plan: Plan = plan.parse()?;
+        let mut next = vec![[None; 6]; self.rooms.len()];
+        for c in &self.connections {
+            next[c.from.room][c.from.door] = Some(c.to.room);
+            next[c.to.room][c.to.door] = Some(c.from.room);
+        }
+        let mut labels = self.rooms.clone();
+        let mut room = self.starting_room;
+        let mut results = vec![labels[room]];
+        for step in plan.steps() {
+            match *step {
+                Step::Mark(label) => labels[room] = label,
+                Step::Door(door) => {
+                    room = next[room][door].unwrap_or_else(|| {
+                        panic!("door {} of room {} is not connected", door, room)
+                    });
+                }
+            }
+            results.push(labels[room]);
+        }
+        Ok(results)
+    }
+
+    /// `explore` と同じ plan をたどったときの各観測の部屋 (`[d]` では動かない)
+    pub fn walk(&self, plan: &Plan) -> Vec<usize> {
+        let mut next = vec![[None; 6]; self.rooms.len()];
+        for c in &self.connections {
+            next[c.from.room][c.from.door] = Some(c.to.room);
+            next[c.to.room][c.to.door] = Some(c.from.room);
+        }
+        let mut room = self.starting_room;
+        let mut rooms = vec![room];
+        for step in plan.steps() {
+            if let Step::Door(door) = *step {
+                room = next[room][door]
+                    .unwrap_or_else(|| panic!("door {} of room {} is not connected", door, room));
+            }
+            rooms.push(room);
+        }
+        rooms
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Connection {
+    pub from: RoomAndDoor,
+    pub to: RoomAndDoor,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Hash, Clone, Copy)]
+pub struct RoomAndDoor {
+    pub room: usize,
+    pub door: usize,
+}
+// デバッグ出力用にfmt::Debugを実装
+impl fmt::Debug for RoomAndDoor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "R{}.D{}", self.room, self.door)
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct GuessResponse {
+    pub correct: bool,
+}
+
+// --- APIクライアント ---
+pub struct ApiClient {
+    client: Client,
+    state: ClientState,
+}
+
+impl Default for ApiClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ApiClient {
+    /// 環境変数・設定ファイルから接続先を決めてクライアントを作る
+    pub fn new() -> Self {
+        let config = ClientConfig::load().expect("failed to load client config");
+        Self::with_config(config)
+    }
+
+    /// `config.record` / `config.replay` が指定されていれば記録・リプレイも有効にする
+    pub fn with_config(config: ClientConfig) -> Self {
+        ApiClient {
+            client: Client::builder()
+                .timeout(Duration::from_secs(30))
+                .build()
+                .unwrap(),
+            state: ClientState::new(config),
+        }
+    }
+
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.state.retry_policy = retry_policy;
+        self
+    }
+
+    /// 成功したやり取りをセッションファイルに追記する
+    pub fn with_recorder(mut self, recorder: Recorder) -> Self {
+        self.state.session.recorder = Some(recorder);
+        self
+    }
+
+    /// サーバーに繋がず、セッションファイルの記録から応答する
+    pub fn with_replayer(mut self, replayer: Replayer) -> Self {
+        self.state.session.replayer = Some(replayer);
+        self
+    }
+
+    /// クエリ数を数え、予算を超える `/explore` を止める
+    pub fn with_budget(mut self, budget: BudgetTracker) -> Self {
+        self.state.budget = Some(budget);
+        self
+    }
+
+    pub fn budget(&self) -> Option<&BudgetTracker> {
+        self.state.budget.as_ref()
+    }
+
+    pub fn config(&self) -> &ClientConfig {
+        &self.state.config
+    }
+
+    fn post<Req: Serialize, Res: DeserializeOwned>(
+        &self,
+        endpoint: Endpoint,
+        request_body: &Req,
+    ) -> Result<Res, ApiError> {
+        let (request, replayed) = self.state.replay(endpoint, request_body);
+        let response = match replayed {
+            Some(response) => response?,
+            None => self.post_with_retry(endpoint, request_body)?,
+        };
+        self.state.session.finish(endpoint, &request, response)
+    }
+
+    /// リトライポリシーに従って送信する
+    fn post_with_retry<Req: Serialize>(
+        &self,
+        endpoint: Endpoint,
+        request_body: &Req,
+    ) -> Result<serde_json::Value, ApiError> {
+        let mut attempt = 1;
+        loop {
+            let result = self.post_once(endpoint, request_body);
+            let Some(wait) = self.state.retry_wait(endpoint, attempt, &result) else {
+                return result;
+            };
+            thread::sleep(wait);
+            attempt += 1;
+        }
+    }
+
+    fn post_once<Req: Serialize>(
+        &self,
+        endpoint: Endpoint,
+        request_body: &Req,
+    ) -> Result<serde_json::Value, ApiError> {
+        let response = self
+            .client
+            .post(self.state.url(endpoint))
+            .json(request_body)
+            .send()
+            .map_err(ApiError::from_reqwest)?;
+        let status = response.status();
+        let body = response.text().map_err(ApiError::from_reqwest)?;
+        parse_body(status.as_u16(), body)
+    }
+
+    pub fn select_problem(&self, problem: &str) -> Result<SelectResponse, ApiError> {
+        let request_body = self.state.select_request(problem);
+        let response = self.post(Endpoint::Select, &request_body)?;
+        self.state.selected(&response);
+        Ok(response)
+    }
+
+    pub fn explore(&self, plans: &[String]) -> Result<ExploreResponse, ApiError> {
+        self.state.check_plan_lengths(plans)?;
+        if let Some(budget) = &self.state.budget {
+            budget.check_explore(plans.len())?;
+        }
+        let request_body = self.state.explore_request(plans);
+        let response = self.post(Endpoint::Explore, &request_body)?;
+        self.state.explored(plans, &response)?;
+        Ok(response)
+    }
+
+    pub fn guess(&self, map: Map) -> Result<GuessResponse, ApiError> {
+        let request_body = self.state.guess_request(map);
+        self.post(Endpoint::Guess, &request_body)
+    }
+}
+
+/// `ApiClient` と `AsyncApiClient` で共通の設定と、送る前後の処理
+///
+/// 送信と待ち時間 (`thread::sleep` / `tokio::time::sleep`) だけを各クライアントが持つ。
+pub(crate) struct ClientState {
+    pub(crate) config: ClientConfig,
+    pub(crate) retry_policy: RetryPolicy,
+    pub(crate) session: Session,
+    pub(crate) budget: Option<BudgetTracker>,
+    /// 選択中の問題 (plan の長さの確認に使う)
+    problem: Mutex<Option<Problem>>,
+}
+
+impl ClientState {
+    pub(crate) fn new(config: ClientConfig) -> Self {
+        ClientState {
+            session: Session::from_config(&config),
+            config,
+            retry_policy: RetryPolicy::default(),
+            budget: None,
+            problem: Mutex::new(None),
+        }
+    }
+
+    pub(crate) fn url(&self, endpoint: Endpoint) -> String {
+        format!("{}/{}", self.config.base_url, endpoint.path())
+    }
+
+    /// 記録用のリクエスト (チームIDはセッションファイルに残さない) と、リプレイ中ならその応答
+    pub(crate) fn replay<Req: Serialize>(
+        &self,
+        endpoint: Endpoint,
+        request_body: &Req,
+    ) -> (
+        serde_json::Value,
+        Option<Result<serde_json::Value, ApiError>>,
+    ) {
+        let request = strip_team_id(serde_json::to_value(request_body).unwrap());
+        let replayed = self.session.replay(endpoint, &request);
+        (request, replayed)
+    }
+
+    /// `attempt` 回目 (1始まり) の送信が失敗していて、もう一度送るなら待つ時間
+    pub(crate) fn retry_wait(
+        &self,
+        endpoint: Endpoint,
+        attempt: u32,
+        result: &Result<serde_json::Value, ApiError>,
+    ) -> Option<Duration> {
+        let error = result.as_ref().err()?;
+        if !self.retry_policy.should_retry(endpoint, attempt, error) {
+            return None;
+        }
+        let wait = self.retry_policy.backoff(attempt, &mut rand::thread_rng());
+        println!(
+            "/{} failed (attempt {}/{}): {}. Retrying in {:?}...",
+            endpoint.path(),
+            attempt,
+            self.retry_policy.max_attempts,
+            error,
+            wait
+        );
+        Some(wait)
+    }
+
+    pub(crate) fn select_request<'a>(&'a self, problem_name: &'a str) -> SelectRequest<'a> {
+        SelectRequest {
+            id: &self.config.team_id,
+            problem_name,
+        }
+    }
+
+    /// `/select` が成功したら呼ぶ
+    pub(crate) fn selected(&self, response: &SelectResponse) {
+        if let Some(budget) = &self.budget {
+            budget.start_problem(&response.problem_name);
+        }
+        *self.problem.lock().unwrap() = Problem::find(&response.problem_name);
+    }
+
+    /// 選択中の問題が分かっていれば、plan の長さを確かめる
+    pub(crate) fn check_plan_lengths(&self, plans: &[String]) -> Result<(), ApiError> {
+        match *self.problem.lock().unwrap() {
+            Some(problem) => check_plan_lengths(&problem, plans),
+            None => Ok(()),
+        }
+    }
+
+    pub(crate) fn explore_request<'a>(&'a self, plans: &'a [String]) -> ExploreRequest<'a> {
+        ExploreRequest {
+            id: &self.config.team_id,
+            plans,
+        }
+    }
+
+    /// `/explore` が成功したら呼ぶ
+    pub(crate) fn explored(
+        &self,
+        plans: &[String],
+        response: &ExploreResponse,
+    ) -> Result<(), ApiError> {
+        // 結果がおかしくてもクエリは消費されているので先に数える
+        if let Some(budget) = &self.budget {
+            budget.record_explore(plans.len(), response.query_count);
+        }
+        check_results(plans, &response.results)
+    }
+
+    pub(crate) fn guess_request(&self, map: Map) -> GuessRequest<'_> {
+        let request_body = GuessRequest {
+            id: &self.config.team_id,
+            map,
+        };
+        println!(
+            "Guessing with map: {:?}",
+            serde_json::to_string(&request_body).unwrap_or_default()
+        );
+        request_body
+    }
+}
+
+/// ステータスとレスポンス本体から JSON を取り出す
+pub(crate) fn parse_body(status: u16, body: String) -> Result<serde_json::Value, ApiError> {
+    if !(200..300).contains(&status) {
+        return Err(ApiError::from_status(status, &body));
+    }
+    serde_json::from_str(&body).map_err(|e| ApiError::MalformedResponse {
+        reason: e.to_string(),
+        body,
+    })
+}
+
+/// 送る前に、各 plan のドアの数が問題の上限を超えていないか確認する
+/// 文字列として不正な plan はサーバーにエラーを返させる
+pub(crate) fn check_plan_lengths(problem: &Problem, plans: &[String]) -> Result<(), ApiError> {
+    for (plan_idx, plan) in plans.iter().enumerate() {
+        let Ok(plan) = plan.parse::<Plan>() else {
+            continue;
+        };
+        problem
+            .check_plan(&plan)
+            .map_err(|error| ApiError::PlanTooLong { plan_idx, error })?;
+    }
+    Ok(())
+}
+
+/// 各 plan の結果が plan と矛盾しないか確認する (`Alignment::new`)
+/// 読めない plan は長さだけ見る。ドア移動も `[d]` も1歩で、どちらも数字を1文字だけ含む
+pub(crate) fn check_results(plans: &[String], results: &[Vec<usize>]) -> Result<(), ApiError> {
+    if plans.len() != results.len() {
+        return Err(ApiError::ResultCountMismatch {
+            expected: plans.len(),
+            actual: results.len(),
+        });
+    }
+    for (plan_idx, (plan, result)) in plans.iter().zip(results).enumerate() {
+        let error = match plan.parse::<Plan>() {
+            Ok(plan) => Alignment::new(&plan, result).err(),
+            Err(_) => {
+                let expected = plan.chars().filter(|c| c.is_ascii_digit()).count() + 1;
+                (result.len() != expected).then_some(AlignmentError::LengthMismatch {
+                    expected,
+                    found: result.len(),
+                })
+            }
+        };
+        match error {
+            None => {}
+            Some(AlignmentError::LengthMismatch { expected, found }) => {
+                return Err(ApiError::ResultLengthMismatch {
+                    plan_idx,
+                    expected,
+                    actual: found,
+                });
+            }
+            Some(error) => return Err(ApiError::InconsistentResult { plan_idx, error }),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,142 @@
+use std::time::Duration;
+
+use reqwest::Client;
+use serde::{Serialize, de::DeserializeOwned};
+
+use crate::api::{ClientState, ExploreResponse, GuessResponse, Map, SelectResponse, parse_body};
+use crate::budget::BudgetTracker;
+use crate::cassette::{Recorder, Replayer};
+use crate::config::ClientConfig;
+use crate::error::ApiError;
+use crate::retry::{Endpoint, RetryPolicy};
+
+/// `ApiClient` の非同期版 (tokio)
+///
+/// 設定・リトライ・記録/リプレイの挙動は同期版と同じ (`ClientState` を共有する)。
+/// `Arc` に包んで複数のタスクから同時に使える。
+pub struct AsyncApiClient {
+    client: Client,
+    state: ClientState,
+}
+
+impl Default for AsyncApiClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AsyncApiClient {
+    /// 環境変数・設定ファイルから接続先を決めてクライアントを作る
+    pub fn new() -> Self {
+        let config = ClientConfig::load().expect("failed to load client config");
+        Self::with_config(config)
+    }
+
+    pub fn with_config(config: ClientConfig) -> Self {
+        AsyncApiClient {
+            client: Client::builder()
+                .timeout(Duration::from_secs(30))
+                .build()
+                .unwrap(),
+            state: ClientState::new(config),
+        }
+    }
+
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.state.retry_policy = retry_policy;
+        self
+    }
+
+    pub fn with_recorder(mut self, recorder: Recorder) -> Self {
+        self.state.session.recorder = Some(recorder);
+        self
+    }
+
+    pub fn with_replayer(mut self, replayer: Replayer) -> Self {
+        self.state.session.replayer = Some(replayer);
+        self
+    }
+
+    /// クエリ数を数え、予算を超える `/explore` を止める
+    pub fn with_budget(mut self, budget: BudgetTracker) -> Self {
+        self.state.budget = Some(budget);
+        self
+    }
+
+    pub fn budget(&self) -> Option<&BudgetTracker> {
+        self.state.budget.as_ref()
+    }
+
+    pub fn config(&self) -> &ClientConfig {
+        &self.state.config
+    }
+
+    async fn post<Req: Serialize, Res: DeserializeOwned>(
+        &self,
+        endpoint: Endpoint,
+        request_body: &Req,
+    ) -> Result<Res, ApiError> {
+        let (request, replayed) = self.state.replay(endpoint, request_body);
+        let response = match replayed {
+            Some(response) => response?,
+            None => self.post_with_retry(endpoint, request_body).await?,
+        };
+        self.state.session.finish(endpoint, &request, response)
+    }
+
+    async fn post_with_retry<Req: Serialize>(
+        &self,
+        endpoint: Endpoint,
+        request_body: &Req,
+    ) -> Result<serde_json::Value, ApiError> {
+        let mut attempt = 1;
+        loop {
+            let result = self.post_once(endpoint, request_body).await;
+            let Some(wait) = self.state.retry_wait(endpoint, attempt, &result) else {
+                return result;
+            };
+            tokio::time::sleep(wait).await;
+            attempt += 1;
+        }
+    }
+
+    async fn post_once<Req: Serialize>(
+        &self,
+        endpoint: Endpoint,
+        request_body: &Req,
+    ) -> Result<serde_json::Value, ApiError> {
+        let response = self
+            .client
+            .post(self.state.url(endpoint))
+            .json(request_body)
+            .send()
+            .await
+            .map_err(ApiError::from_reqwest)?;
+        let status = response.status();
+        let body = response.text().await.map_err(ApiError::from_reqwest)?;
+        parse_body(status.as_u16(), body)
+    }
+
+    pub async fn select_problem(&self, problem: &str) -> Result<SelectResponse, ApiError> {
+        let request_body = self.state.select_request(problem);
+        let response = self.post(Endpoint::Select, &request_body).await?;
+        self.state.selected(&response);
+        Ok(response)
+    }
+
+    pub async fn explore(&self, plans: &[String]) -> Result<ExploreResponse, ApiError> {
+        self.state.check_plan_lengths(plans)?;
+        if let Some(budget) = &self.state.budget {
+            budget.check_explore_async(plans.len()).await?;
+        }
+        let request_body = self.state.explore_request(plans);
+        let response = self.post(Endpoint::Explore, &request_body).await?;
+        self.state.explored(plans, &response)?;
+        Ok(response)
+    }
+
+    pub async fn guess(&self, map: Map) -> Result<GuessResponse, ApiError> {
+        let request_body = self.state.guess_request(map);
+        self.post(Endpoint::Guess, &request_body).await
+    }
+}
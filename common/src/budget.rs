@@ -0,0 +1,304 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    io::{self, BufRead, Write},
+    sync::Mutex,
+};
+
+use crate::error::ApiError;
+
+/// クエリをどの段階で使ったか
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Phase {
+    /// 焼きなましで基本構造を決めるための explore
+    Identification,
+    /// 階層 (layer) を解決するための explore
+    LayerResolution,
+    /// 候補マップを確かめるための explore
+    Verification,
+}
+
+impl Phase {
+    pub const ALL: [Phase; 3] = [
+        Phase::Identification,
+        Phase::LayerResolution,
+        Phase::Verification,
+    ];
+
+    fn index(self) -> usize {
+        match self {
+            Phase::Identification => 0,
+            Phase::LayerResolution => 1,
+            Phase::Verification => 2,
+        }
+    }
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Phase::Identification => "SA identification",
+            Phase::LayerResolution => "layer resolution",
+            Phase::Verification => "verification",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// 予算を超えそうなときの動作
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverBudget {
+    /// 送らずに `ApiError::BudgetExceeded` を返す
+    Refuse,
+    /// 標準入力で確認し、y なら送る
+    Confirm,
+}
+
+/// 1回の `/explore` にかかるクエリ数の予測。plan 数 + 1
+pub fn predict_cost(num_plans: usize) -> usize {
+    num_plans + 1
+}
+
+/// 問題ごとのクエリ数を数え、予算を超える `/explore` を止める
+///
+/// `/select` のたびに新しい問題のセッションとして数え直す。
+/// サーバーの `queryCount` は累計の場合と1回分の場合 (ローカルの simulator) があるので、
+/// 手元で数えた値と大きい方を使用済みとみなす。
+pub struct BudgetTracker {
+    budgets: HashMap<String, usize>,
+    default_budget: Option<usize>,
+    over_budget: OverBudget,
+    state: Mutex<BudgetState>,
+}
+
+#[derive(Default)]
+struct BudgetState {
+    problem_name: Option<String>,
+    /// i 番目の plan の段階。足りない分は最後の段階。空なら全部 `Identification`
+    phases: Vec<Phase>,
+    spent: usize,
+    per_phase: [usize; 3],
+    server_count: Option<usize>,
+}
+
+/// 1問分のクエリ使用状況
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BudgetReport {
+    pub problem_name: Option<String>,
+    pub budget: Option<usize>,
+    /// 手元で数えた使用数
+    pub spent: usize,
+    per_phase: [usize; 3],
+    /// 最後にサーバーが返した queryCount
+    pub server_count: Option<usize>,
+}
+
+impl Default for BudgetTracker {
+    fn default() -> Self {
+        Self::new(OverBudget::Refuse)
+    }
+}
+
+impl BudgetTracker {
+    /// 予算なし (数えるだけ) で作る
+    pub fn new(over_budget: OverBudget) -> Self {
+        BudgetTracker {
+            budgets: HashMap::new(),
+            default_budget: None,
+            over_budget,
+            state: Mutex::new(BudgetState::default()),
+        }
+    }
+
+    pub fn with_budget(mut self, problem_name: &str, budget: usize) -> Self {
+        self.budgets.insert(problem_name.to_string(), budget);
+        self
+    }
+
+    /// 個別に指定していない問題の予算
+    pub fn with_default_budget(mut self, budget: usize) -> Self {
+        self.default_budget = Some(budget);
+        self
+    }
+
+    /// これ以降の `/explore` をどの段階として数えるか
+    pub fn set_phase(&self, phase: Phase) {
+        self.set_phases(&[phase]);
+    }
+
+    /// 1回の `/explore` に段階の違う plan を混ぜるとき、i 番目の plan を `phases[i]` として数える
+    ///
+    /// 足りない分は最後の段階。plan によらない1クエリは最初の plan の段階に数える
+    pub fn set_phases(&self, phases: &[Phase]) {
+        self.state.lock().unwrap().phases = phases.to_vec();
+    }
+
+    pub fn report(&self) -> BudgetReport {
+        let state = self.state.lock().unwrap();
+        BudgetReport {
+            problem_name: state.problem_name.clone(),
+            budget: self.budget_for(state.problem_name.as_deref()),
+            spent: state.spent,
+            per_phase: state.per_phase,
+            server_count: state.server_count,
+        }
+    }
+
+    fn budget_for(&self, problem_name: Option<&str>) -> Option<usize> {
+        problem_name
+            .and_then(|name| self.budgets.get(name).copied())
+            .or(self.default_budget)
+    }
+
+    /// `/select` が成功したら呼ぶ。前の問題のレポートを出して数え直す
+    pub(crate) fn start_problem(&self, problem_name: &str) {
+        let mut state = self.state.lock().unwrap();
+        if state.problem_name.is_some() {
+            drop(state);
+            println!("{}", self.report());
+            state = self.state.lock().unwrap();
+        }
+        *state = BudgetState {
+            problem_name: Some(problem_name.to_string()),
+            ..Default::default()
+        };
+    }
+
+    /// `/explore` を送る前に呼ぶ。予算を超えるなら Err
+    pub(crate) fn check_explore(&self, num_plans: usize) -> Result<(), ApiError> {
+        match self.exceeded(num_plans) {
+            Some(error) if !(self.over_budget == OverBudget::Confirm && confirm(&error)) => {
+                Err(error)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// `check_explore` の非同期版。標準入力は別スレッドで待ち、その間も他のタスクを止めない
+    pub(crate) async fn check_explore_async(&self, num_plans: usize) -> Result<(), ApiError> {
+        let Some(error) = self.exceeded(num_plans) else {
+            return Ok(());
+        };
+        if self.over_budget == OverBudget::Refuse {
+            return Err(error);
+        }
+        let (confirmed, error) = tokio::task::spawn_blocking(move || (confirm(&error), error))
+            .await
+            .expect("budget confirmation panicked");
+        if confirmed { Ok(()) } else { Err(error) }
+    }
+
+    /// `num_plans` 個の plan を送ると予算を超えるなら、そのエラー
+    fn exceeded(&self, num_plans: usize) -> Option<ApiError> {
+        let state = self.state.lock().unwrap();
+        let budget = self.budget_for(state.problem_name.as_deref())?;
+        let spent = state.spent.max(state.server_count.unwrap_or(0));
+        let cost = predict_cost(num_plans);
+        (spent + cost > budget).then_some(ApiError::BudgetExceeded {
+            budget,
+            spent,
+            cost,
+        })
+    }
+
+    /// `/explore` が成功したら呼ぶ
+    pub(crate) fn record_explore(&self, num_plans: usize, server_count: usize) {
+        let mut state = self.state.lock().unwrap();
+        let phase_of = |state: &BudgetState, plan_idx: usize| {
+            state
+                .phases
+                .get(plan_idx)
+                .or(state.phases.last())
+                .copied()
+                .unwrap_or(Phase::Identification)
+        };
+        let base = phase_of(&state, 0);
+        state.per_phase[base.index()] += predict_cost(0);
+        for plan_idx in 0..num_plans {
+            let phase = phase_of(&state, plan_idx);
+            state.per_phase[phase.index()] += 1;
+        }
+        state.spent += predict_cost(num_plans);
+        state.server_count = Some(server_count);
+    }
+}
+
+fn confirm(error: &ApiError) -> bool {
+    print!("{}. Send anyway? [y/N] ", error);
+    io::stdout().flush().ok();
+    let mut line = String::new();
+    if io::stdin().lock().read_line(&mut line).is_err() {
+        return false;
+    }
+    matches!(line.trim(), "y" | "Y" | "yes")
+}
+
+impl BudgetReport {
+    /// その段階で使ったクエリ数
+    pub fn per_phase(&self, phase: Phase) -> usize {
+        self.per_phase[phase.index()]
+    }
+}
+
+impl fmt::Display for BudgetReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Query report for {}: {} queries",
+            self.problem_name.as_deref().unwrap_or("(no problem)"),
+            self.spent
+        )?;
+        if let Some(budget) = self.budget {
+            write!(f, " / budget {}", budget)?;
+        }
+        if let Some(server_count) = self.server_count {
+            write!(f, " (server queryCount: {})", server_count)?;
+        }
+        for phase in Phase::ALL {
+            write!(f, "\n  {}: {}", phase, self.per_phase(phase))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_refuses_batch_over_budget() {
+        let tracker = BudgetTracker::new(OverBudget::Refuse).with_budget("probatio", 6);
+        tracker.start_problem("probatio");
+        assert!(tracker.check_explore(2).is_ok());
+        tracker.record_explore(2, 3);
+        tracker.set_phase(Phase::LayerResolution);
+        assert!(tracker.check_explore(2).is_ok());
+        tracker.record_explore(2, 6);
+        assert!(matches!(
+            tracker.check_explore(1),
+            Err(ApiError::BudgetExceeded {
+                budget: 6,
+                spent: 6,
+                cost: 2
+            })
+        ));
+
+        let report = tracker.report();
+        assert_eq!(report.per_phase(Phase::Identification), 3);
+        assert_eq!(report.per_phase(Phase::LayerResolution), 3);
+        assert_eq!(report.per_phase(Phase::Verification), 0);
+
+        // 別の問題は数え直し、予算も別
+        tracker.start_problem("primus");
+        assert_eq!(tracker.report().spent, 0);
+        assert!(tracker.check_explore(100).is_ok());
+
+        // plan ごとに段階を分ける。基本の1クエリは最初の plan の段階
+        tracker.set_phases(&[Phase::Identification, Phase::LayerResolution]);
+        tracker.record_explore(3, 4);
+        let report = tracker.report();
+        assert_eq!(report.spent, 4);
+        assert_eq!(report.per_phase(Phase::Identification), 2);
+        assert_eq!(report.per_phase(Phase::LayerResolution), 2);
+    }
+}
@@ -0,0 +1,292 @@
+use std::{
+    error::Error,
+    fs::{File, OpenOptions},
+    io::Write,
+    path::Path,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde_json::Value;
+
+use crate::{config::ClientConfig, error::ApiError, retry::Endpoint};
+
+/// セッションファイル (JSONL) の1行。1リクエスト・1レスポンスに対応する
+///
+/// `request` からはチームIDの `id` を取り除いてある。
+///
+/// ```json
+/// {"timestamp_ms":1757000000000,"problem_name":"probatio","endpoint":"explore","request":{"plans":["012"]},"response":{"results":[[0,1,2,3]],"queryCount":2}}
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CassetteEntry {
+    /// UNIX 時刻 (ミリ秒)
+    pub timestamp_ms: u64,
+    /// 直前の `/select` で選んだ問題。select 前なら None
+    pub problem_name: Option<String>,
+    pub endpoint: Endpoint,
+    pub request: Value,
+    pub response: Value,
+}
+
+/// セッションファイルを全部読む
+pub fn load_session(path: &Path) -> Result<Vec<CassetteEntry>, Box<dyn Error>> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| format!("failed to read session file {}: {}", path.display(), e))?;
+    let mut entries = vec![];
+    for (line_idx, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry = serde_json::from_str(line).map_err(|e| {
+            format!(
+                "failed to parse session file {} line {}: {}",
+                path.display(),
+                line_idx + 1,
+                e
+            )
+        })?;
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// 記録・照合用にリクエストから `id` を落とす
+pub(crate) fn strip_team_id(mut request: Value) -> Value {
+    if let Value::Object(fields) = &mut request {
+        fields.remove("id");
+    }
+    request
+}
+
+/// 成功したリクエストとレスポンスをセッションファイルに追記していく
+pub struct Recorder {
+    state: Mutex<RecorderState>,
+}
+
+struct RecorderState {
+    file: File,
+    problem_name: Option<String>,
+}
+
+impl Recorder {
+    /// ファイルがなければ作り、あれば末尾に追記する
+    pub fn create(path: &Path) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Recorder {
+            state: Mutex::new(RecorderState {
+                file,
+                problem_name: None,
+            }),
+        })
+    }
+
+    pub(crate) fn record(
+        &self,
+        endpoint: Endpoint,
+        request: &Value,
+        response: &Value,
+    ) -> std::io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if endpoint == Endpoint::Select {
+            state.problem_name = request["problemName"].as_str().map(str::to_string);
+        }
+        let entry = CassetteEntry {
+            timestamp_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or(0),
+            problem_name: state.problem_name.clone(),
+            endpoint,
+            request: request.clone(),
+            response: response.clone(),
+        };
+        let mut line = serde_json::to_string(&entry)?;
+        line.push('\n');
+        // 途中で落ちても行が混ざらないように1回の write で書く
+        state.file.write_all(line.as_bytes())?;
+        state.file.flush()
+    }
+}
+
+/// セッションファイルに記録されたレスポンスを返す
+///
+/// エンドポイントとリクエスト本体 (`id` 以外) が一致する記録を先頭から順に使う。
+/// 同じ plans を2回 explore した場合は、記録された順に別々のレスポンスが返る。
+/// `/select` 以外は、最後にリプレイした `/select` の問題で記録されたものだけを使う。
+pub struct Replayer {
+    entries: Vec<CassetteEntry>,
+    state: Mutex<ReplayerState>,
+}
+
+struct ReplayerState {
+    used: Vec<bool>,
+    /// 最後にリプレイした `/select` の問題
+    problem_name: Option<String>,
+}
+
+impl Replayer {
+    pub fn open(path: &Path) -> Result<Self, Box<dyn Error>> {
+        Ok(Self::from_entries(load_session(path)?))
+    }
+
+    pub fn from_entries(entries: Vec<CassetteEntry>) -> Self {
+        let state = Mutex::new(ReplayerState {
+            used: vec![false; entries.len()],
+            problem_name: None,
+        });
+        Replayer { entries, state }
+    }
+
+    pub fn entries(&self) -> &[CassetteEntry] {
+        &self.entries
+    }
+
+    pub(crate) fn answer(&self, endpoint: Endpoint, request: &Value) -> Result<Value, ApiError> {
+        let mut state = self.state.lock().unwrap();
+        let idx = (0..self.entries.len())
+            .find(|&i| {
+                let entry = &self.entries[i];
+                !state.used[i]
+                    && entry.endpoint == endpoint
+                    && entry.request == *request
+                    && (endpoint == Endpoint::Select || entry.problem_name == state.problem_name)
+            })
+            .ok_or(ApiError::NotRecorded {
+                endpoint: endpoint.path(),
+            })?;
+        state.used[idx] = true;
+        if endpoint == Endpoint::Select {
+            state.problem_name = request["problemName"].as_str().map(str::to_string);
+        }
+        Ok(self.entries[idx].response.clone())
+    }
+}
+
+/// クライアントが持つ記録・リプレイの状態。同期版と非同期版で共有する
+#[derive(Default)]
+pub(crate) struct Session {
+    pub(crate) recorder: Option<Recorder>,
+    pub(crate) replayer: Option<Replayer>,
+}
+
+impl Session {
+    /// `config.record` / `config.replay` が指定されていれば開く
+    pub(crate) fn from_config(config: &ClientConfig) -> Self {
+        let recorder = config.record.as_ref().map(|path| {
+            Recorder::create(path)
+                .unwrap_or_else(|e| panic!("failed to open {}: {}", path.display(), e))
+        });
+        let replayer = config.replay.as_ref().map(|path| {
+            Replayer::open(path).unwrap_or_else(|e| panic!("failed to load session: {}", e))
+        });
+        Session { recorder, replayer }
+    }
+
+    /// リプレイ中なら記録から応答する。None ならサーバーに送る
+    pub(crate) fn replay(
+        &self,
+        endpoint: Endpoint,
+        request: &Value,
+    ) -> Option<Result<Value, ApiError>> {
+        self.replayer
+            .as_ref()
+            .map(|replayer| replayer.answer(endpoint, request))
+    }
+
+    /// レスポンスを記録してから型に変換する
+    pub(crate) fn finish<Res: DeserializeOwned>(
+        &self,
+        endpoint: Endpoint,
+        request: &Value,
+        response: Value,
+    ) -> Result<Res, ApiError> {
+        if let Some(recorder) = &self.recorder {
+            // 記録に失敗してもクエリは消費済みなので、結果は返す
+            if let Err(e) = recorder.record(endpoint, request, &response) {
+                eprintln!("failed to record /{}: {}", endpoint.path(), e);
+            }
+        }
+        let body = response.to_string();
+        serde_json::from_value(response).map_err(|e| ApiError::MalformedResponse {
+            reason: e.to_string(),
+            body,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn select(problem: &str) -> CassetteEntry {
+        CassetteEntry {
+            timestamp_ms: 0,
+            problem_name: Some(problem.to_string()),
+            endpoint: Endpoint::Select,
+            request: json!({ "problemName": problem }),
+            response: json!({ "problemName": problem }),
+        }
+    }
+
+    fn explore(problem: &str, plans: Value, results: Value) -> CassetteEntry {
+        CassetteEntry {
+            timestamp_ms: 0,
+            problem_name: Some(problem.to_string()),
+            endpoint: Endpoint::Explore,
+            request: json!({ "plans": plans }),
+            response: json!({ "results": results, "queryCount": 2 }),
+        }
+    }
+
+    #[test]
+    fn test_replay_answers_in_recorded_order() {
+        let replayer = Replayer::from_entries(vec![
+            select("probatio"),
+            explore("probatio", json!(["0"]), json!([[0, 1]])),
+            explore("probatio", json!(["1"]), json!([[0, 2]])),
+            explore("probatio", json!(["0"]), json!([[0, 3]])),
+        ]);
+        let select_request = json!({ "problemName": "probatio" });
+        replayer.answer(Endpoint::Select, &select_request).unwrap();
+
+        let request = strip_team_id(json!({ "id": "someone", "plans": ["0"] }));
+        let first = replayer.answer(Endpoint::Explore, &request).unwrap();
+        assert_eq!(first["results"], json!([[0, 1]]));
+        let second = replayer.answer(Endpoint::Explore, &request).unwrap();
+        assert_eq!(second["results"], json!([[0, 3]]));
+        assert!(matches!(
+            replayer.answer(Endpoint::Explore, &request),
+            Err(ApiError::NotRecorded { .. })
+        ));
+        assert!(replayer.answer(Endpoint::Guess, &json!({})).is_err());
+    }
+
+    #[test]
+    fn test_replay_keeps_problems_apart() {
+        // 2つの問題で同じ plan を送ったセッション
+        let replayer = Replayer::from_entries(vec![
+            select("probatio"),
+            explore("probatio", json!(["0"]), json!([[0, 1]])),
+            select("primus"),
+            explore("primus", json!(["0"]), json!([[0, 2]])),
+        ]);
+        let request = json!({ "plans": ["0"] });
+        // select する前の explore は記録にない
+        assert!(replayer.answer(Endpoint::Explore, &request).is_err());
+
+        let primus = json!({ "problemName": "primus" });
+        replayer.answer(Endpoint::Select, &primus).unwrap();
+        let response = replayer.answer(Endpoint::Explore, &request).unwrap();
+        assert_eq!(response["results"], json!([[0, 2]]));
+        // primus の分は使い切ったので、probatio の記録は返さない
+        assert!(replayer.answer(Endpoint::Explore, &request).is_err());
+
+        let probatio = json!({ "problemName": "probatio" });
+        replayer.answer(Endpoint::Select, &probatio).unwrap();
+        let response = replayer.answer(Endpoint::Explore, &request).unwrap();
+        assert_eq!(response["results"], json!([[0, 1]]));
+    }
+}
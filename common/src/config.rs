@@ -0,0 +1,108 @@
+use std::{
+    env,
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+
+const DEFAULT_BASE_URL: &str = "http://localhost:5000";
+const DEFAULT_CONFIG_FILE: &str = "icfpc.json";
+
+const ENV_CONFIG_FILE: &str = "ICFPC_CONFIG";
+const ENV_BASE_URL: &str = "ICFPC_BASE_URL";
+const ENV_TEAM_ID: &str = "ICFPC_TEAM_ID";
+const ENV_RECORD: &str = "ICFPC_RECORD";
+const ENV_REPLAY: &str = "ICFPC_REPLAY";
+
+/// APIクライアントの接続先設定
+///
+/// 優先順位は 環境変数 > 設定ファイル > デフォルト値。
+/// 設定ファイルは `ICFPC_CONFIG` で指定したパス、なければカレントディレクトリの `icfpc.json`。
+///
+/// ```json
+/// {
+///   "base_url": "https://31pwr5t6ij.execute-api.eu-west-2.amazonaws.com",
+///   "team_id": "..."
+/// }
+/// ```
+///
+/// `record` (`ICFPC_RECORD`) を指定するとやり取りをすべてそのファイルに JSONL で追記する。
+/// `replay` (`ICFPC_REPLAY`) を指定するとサーバーには繋がず、記録したファイルから応答する。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientConfig {
+    pub base_url: String,
+    pub team_id: String,
+    pub record: Option<PathBuf>,
+    pub replay: Option<PathBuf>,
+}
+
+#[derive(Deserialize, Default)]
+struct ConfigFile {
+    base_url: Option<String>,
+    team_id: Option<String>,
+    record: Option<PathBuf>,
+    replay: Option<PathBuf>,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        ClientConfig {
+            base_url: DEFAULT_BASE_URL.to_string(),
+            team_id: String::new(),
+            record: None,
+            replay: None,
+        }
+    }
+}
+
+impl ClientConfig {
+    /// 環境変数と設定ファイルから設定を読み込む
+    pub fn load() -> Result<Self, Box<dyn Error>> {
+        let file = match env::var(ENV_CONFIG_FILE) {
+            Ok(path) => Some(read_config_file(Path::new(&path))?),
+            Err(_) if Path::new(DEFAULT_CONFIG_FILE).exists() => {
+                Some(read_config_file(Path::new(DEFAULT_CONFIG_FILE))?)
+            }
+            Err(_) => None,
+        };
+        let config = Self::resolve(
+            file.unwrap_or_default(),
+            env::var(ENV_BASE_URL).ok(),
+            env::var(ENV_TEAM_ID).ok(),
+            env::var_os(ENV_RECORD).map(PathBuf::from),
+            env::var_os(ENV_REPLAY).map(PathBuf::from),
+        );
+        if config.record.is_some() && config.replay.is_some() {
+            return Err("record and replay cannot be enabled at the same time".into());
+        }
+        Ok(config)
+    }
+
+    fn resolve(
+        file: ConfigFile,
+        env_base_url: Option<String>,
+        env_team_id: Option<String>,
+        env_record: Option<PathBuf>,
+        env_replay: Option<PathBuf>,
+    ) -> Self {
+        let default = ClientConfig::default();
+        let base_url = env_base_url.or(file.base_url).unwrap_or(default.base_url);
+        ClientConfig {
+            // 末尾の '/' があると "//select" になってしまうので落としておく
+            base_url: base_url.trim_end_matches('/').to_string(),
+            team_id: env_team_id.or(file.team_id).unwrap_or(default.team_id),
+            record: env_record.or(file.record),
+            replay: env_replay.or(file.replay),
+        }
+    }
+}
+
+fn read_config_file(path: &Path) -> Result<ConfigFile, Box<dyn Error>> {
+    let text = fs::read_to_string(path)
+        .map_err(|e| format!("failed to read config file {}: {}", path.display(), e))?;
+    let file = serde_json::from_str(&text)
+        .map_err(|e| format!("failed to parse config file {}: {}", path.display(), e))?;
+    Ok(file)
+}
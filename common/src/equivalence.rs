@@ -0,0 +1,255 @@
+// 2つのマップが explore で区別できるかの厳密な判定
+//
+// `[d]` でラベルを書き換えられるので、開始部屋から辿れる部分がドア番号込みで同型でなければ区別できる。
+// 区別できる場合は、印 (`[d]`) を高々1回使う plan の中で最短のものを返す。
+//
+// 印1回で足りる理由: 印なしでも印1回でも区別できないなら、ペア (a, b) から door d で (a', b') に進んだあと
+// A で a に戻るドアを使うと B でも b に戻る (戻らなければ a に印をつけて往復すれば区別できる)。
+// よって到達できるペアの集合は強連結で、同じ a に別々の b が対応することもない (対応すれば印で区別できる)。
+// つまり部屋の対応は全単射で、ラベルもドアの行き先も保つ。
+use std::collections::VecDeque;
+
+use crate::api::Map;
+
+/// 2つのマップを区別する plan。`hidden` と `candidate` で結果が変わる
+///
+/// 区別できない (開始部屋から見て同じ) なら `None`。部屋数は比べない。
+/// 長さはドアと印の個数の合計で数え、印を高々1回使う plan の中で最短。
+pub fn distinguishing_plan(hidden: &Map, candidate: &Map) -> Option<String> {
+    let product = Product::new(hidden, candidate);
+    // 印なしで到達できるペアへの最短経路
+    let (dist, parent) = product.bfs(product.start());
+
+    let mut best: Option<(usize, String)> = None;
+    // 印なしでラベルが違うペア
+    if let Some(state) = (0..product.len())
+        .filter(|&s| dist[s] != usize::MAX && !product.same_label(s))
+        .min_by_key(|&s| dist[s])
+    {
+        best = Some((dist[state], doors_to(&parent, state)));
+    }
+
+    // 印をつけるペアを近い順に試す
+    let mut marked: Vec<usize> = (0..product.len())
+        .filter(|&s| dist[s] != usize::MAX && product.same_label(s))
+        .collect();
+    marked.sort_by_key(|&s| dist[s]);
+    for mark in marked {
+        if best.as_ref().is_some_and(|(len, _)| dist[mark] + 1 >= *len) {
+            break;
+        }
+        let (ma, mb) = product.rooms(mark);
+        let (after_dist, after_parent) = product.bfs(mark);
+        // 片方だけが印をつけた部屋にいるペア
+        let Some(end) = (0..product.len())
+            .filter(|&s| {
+                let (a, b) = product.rooms(s);
+                after_dist[s] != usize::MAX && (a == ma) != (b == mb)
+            })
+            .min_by_key(|&s| after_dist[s])
+        else {
+            continue;
+        };
+        let len = dist[mark] + 1 + after_dist[end];
+        if best.as_ref().is_none_or(|(best_len, _)| len < *best_len) {
+            // 印を見ていない側のラベルと違う値を書く
+            let (a, b) = product.rooms(end);
+            let other = if a == ma {
+                candidate.rooms[b]
+            } else {
+                hidden.rooms[a]
+            };
+            let label = (other + 1) % 4;
+            let plan = format!(
+                "{}[{}]{}",
+                doors_to(&parent, mark),
+                label,
+                doors_to(&after_parent, end)
+            );
+            best = Some((len, plan));
+        }
+    }
+    best.map(|(_, plan)| plan)
+}
+
+impl Map {
+    /// どんな plan (印を含む) でも `other` と同じ結果になるか。部屋数は比べない
+    pub fn is_equivalent(&self, other: &Map) -> bool {
+        distinguishing_plan(self, other).is_none()
+    }
+
+    /// 手元の観測 (`plans[i]` の結果が `results[i]`) と全部合うか。guess の前に確かめる
+    ///
+    /// 合わなければ、壊れたマップか最初に食い違った plan と観測の位置を返す。
+    pub fn check_observations<P: AsRef<str>, R: AsRef<[usize]>>(
+        &self,
+        plans: &[P],
+        results: &[R],
+    ) -> Result<(), String> {
+        self.validate().map_err(|e| format!("invalid map: {}", e))?;
+        for (plan_idx, (plan, expected)) in plans.iter().zip(results).enumerate() {
+            let actual = self
+                .explore(plan.as_ref())
+                .map_err(|e| format!("plan {} is invalid: {}", plan_idx, e))?;
+            let expected = expected.as_ref();
+            if let Some(obs) =
+                (0..actual.len().max(expected.len())).find(|&i| actual.get(i) != expected.get(i))
+            {
+                return Err(format!("plan {} differs at observation {}", plan_idx, obs));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 2つのマップを同時に歩くときの状態 (a, b) = a * n_b + b
+struct Product<'a> {
+    hidden: &'a Map,
+    candidate: &'a Map,
+    next_hidden: Vec<[usize; 6]>,
+    next_candidate: Vec<[usize; 6]>,
+}
+
+impl<'a> Product<'a> {
+    fn new(hidden: &'a Map, candidate: &'a Map) -> Self {
+        let next = |map: &Map| -> Vec<[usize; 6]> {
+            map.door_table()
+                .iter()
+                .map(|row| row.map(|to| to.room))
+                .collect()
+        };
+        Product {
+            hidden,
+            candidate,
+            next_hidden: next(hidden),
+            next_candidate: next(candidate),
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.hidden.rooms.len() * self.candidate.rooms.len()
+    }
+
+    fn state(&self, a: usize, b: usize) -> usize {
+        a * self.candidate.rooms.len() + b
+    }
+
+    fn rooms(&self, state: usize) -> (usize, usize) {
+        let n = self.candidate.rooms.len();
+        (state / n, state % n)
+    }
+
+    fn start(&self) -> usize {
+        self.state(self.hidden.starting_room, self.candidate.starting_room)
+    }
+
+    fn same_label(&self, state: usize) -> bool {
+        let (a, b) = self.rooms(state);
+        self.hidden.rooms[a] == self.candidate.rooms[b]
+    }
+
+    /// from からの距離と、(直前の状態, ドア)
+    fn bfs(&self, from: usize) -> (Vec<usize>, Vec<Option<(usize, usize)>>) {
+        let mut dist = vec![usize::MAX; self.len()];
+        let mut parent = vec![None; self.len()];
+        let mut queue = VecDeque::from([from]);
+        dist[from] = 0;
+        while let Some(state) = queue.pop_front() {
+            let (a, b) = self.rooms(state);
+            for door in 0..6 {
+                let next = self.state(self.next_hidden[a][door], self.next_candidate[b][door]);
+                if dist[next] == usize::MAX {
+                    dist[next] = dist[state] + 1;
+                    parent[next] = Some((state, door));
+                    queue.push_back(next);
+                }
+            }
+        }
+        (dist, parent)
+    }
+}
+
+/// BFS の始点から state までのドア列
+fn doors_to(parent: &[Option<(usize, usize)>], mut state: usize) -> String {
+    let mut doors = vec![];
+    while let Some((prev, door)) = parent[state] {
+        doors.push(door);
+        state = prev;
+    }
+    doors
+        .iter()
+        .rev()
+        .map(|&door| char::from_digit(door as u32, 10).unwrap())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::generate_graph;
+    use crate::map_file::parse_map;
+    use crate::plan::PlanError;
+    use rand::{SeedableRng, rngs::StdRng};
+
+    #[test]
+    fn test_distinguishing_plan() {
+        // 全ドアが自己ループの1部屋と、全ドアで行き来する同じラベルの2部屋は印がないと区別できない
+        let one = parse_map(
+            r#"{"rooms": [0], "startingRoom": 0, "connections": [
+                {"from": {"room": 0, "door": 0}, "to": {"room": 0, "door": 1}},
+                {"from": {"room": 0, "door": 2}, "to": {"room": 0, "door": 3}},
+                {"from": {"room": 0, "door": 4}, "to": {"room": 0, "door": 5}}]}"#,
+        )
+        .unwrap();
+        let two = parse_map(
+            r#"{"rooms": [0, 0], "startingRoom": 0, "connections": [
+                {"from": {"room": 0, "door": 0}, "to": {"room": 1, "door": 0}},
+                {"from": {"room": 0, "door": 1}, "to": {"room": 1, "door": 1}},
+                {"from": {"room": 0, "door": 2}, "to": {"room": 1, "door": 2}},
+                {"from": {"room": 0, "door": 3}, "to": {"room": 1, "door": 3}},
+                {"from": {"room": 0, "door": 4}, "to": {"room": 1, "door": 4}},
+                {"from": {"room": 0, "door": 5}, "to": {"room": 1, "door": 5}}]}"#,
+        )
+        .unwrap();
+        let plan = distinguishing_plan(&one, &two).unwrap();
+        assert_eq!(plan, "[1]0");
+        assert_ne!(one.explore(&plan).unwrap(), two.explore(&plan).unwrap());
+
+        let mut rng = StdRng::seed_from_u64(1);
+        let a = generate_graph(6, 2, &mut rng);
+        let b = generate_graph(6, 2, &mut rng);
+        // 部屋番号を付け替えただけなら同じ
+        let n = a.rooms.len();
+        let mut permuted = a.clone();
+        permuted.rooms.reverse();
+        permuted.starting_room = n - 1 - a.starting_room;
+        for c in &mut permuted.connections {
+            c.from.room = n - 1 - c.from.room;
+            c.to.room = n - 1 - c.to.room;
+        }
+        assert!(a.is_equivalent(&permuted));
+        let plan = distinguishing_plan(&a, &b).unwrap();
+        assert_ne!(a.explore(&plan).unwrap(), b.explore(&plan).unwrap());
+
+        // 区別する plan の観測は片方としか合わない
+        let results = vec![a.explore(&plan).unwrap()];
+        assert!(permuted.check_observations(&[&plan], &results).is_ok());
+        assert!(b.check_observations(&[&plan], &results).is_err());
+        assert!(one.check_observations(&["0"], &[vec![0, 1]]).is_err());
+
+        // 範囲外のドアや壊れた印は panic せずエラーになる
+        assert_eq!(one.explore("0[1]2").unwrap(), vec![0, 0, 1, 1]);
+        assert!(matches!(
+            one.explore("06"),
+            Err(PlanError::DoorOutOfRange {
+                position: 1,
+                door: 6
+            })
+        ));
+        assert!(matches!(
+            one.explore("[1"),
+            Err(PlanError::MalformedMark { position: 0 })
+        ));
+        assert!(one.check_observations(&["9"], &[vec![0, 0]]).is_err());
+    }
+}
@@ -0,0 +1,157 @@
+use std::fmt;
+
+use serde::Deserialize;
+
+use crate::alignment::AlignmentError;
+use crate::plan::PlanTooLong;
+
+/// APIクライアントのエラー
+///
+/// ソルバー側で「リトライする / selectし直す / 諦める」を判断できるように、
+/// 失敗の種類ごとに分けておく。
+#[derive(Debug)]
+pub enum ApiError {
+    /// 接続できなかった・途中で切れた等
+    Transport(reqwest::Error),
+    /// クライアント側のタイムアウト
+    Timeout,
+    /// サーバーが 2xx 以外を返した。`message` はレスポンスの `{"error": ...}`
+    Status {
+        status: u16,
+        message: Option<String>,
+    },
+    /// 2xx だったが期待した JSON ではなかった
+    MalformedResponse { reason: String, body: String },
+    /// results の数が送った plans の数と合わない
+    ResultCountMismatch { expected: usize, actual: usize },
+    /// results[plan_idx] の長さが plan の歩数 + 1 と合わない
+    ResultLengthMismatch {
+        plan_idx: usize,
+        expected: usize,
+        actual: usize,
+    },
+    /// results[plan_idx] のラベルが範囲外か、`[d]` で書いたラベルと合わない
+    InconsistentResult {
+        plan_idx: usize,
+        error: AlignmentError,
+    },
+    /// リプレイ中に、セッションファイルに記録されていないリクエストが来た
+    NotRecorded { endpoint: &'static str },
+    /// plans[plan_idx] のドアの数が問題の上限を超えているので送らなかった
+    PlanTooLong { plan_idx: usize, error: PlanTooLong },
+    /// 送ると問題ごとのクエリ予算を超えるので送らなかった
+    BudgetExceeded {
+        budget: usize,
+        spent: usize,
+        cost: usize,
+    },
+}
+
+impl ApiError {
+    /// 同じリクエストを送り直せば成功する見込みがあるか
+    pub fn is_transient(&self) -> bool {
+        match self {
+            ApiError::Transport(_) | ApiError::Timeout => true,
+            ApiError::Status { status, .. } => *status == 429 || *status >= 500,
+            _ => false,
+        }
+    }
+
+    /// サーバーに届いて処理された可能性があるか
+    ///
+    /// 接続自体に失敗した場合と 429 はサーバー側で何も起きていないので false
+    pub fn is_ambiguous(&self) -> bool {
+        match self {
+            ApiError::Transport(e) => !e.is_connect(),
+            ApiError::Timeout => true,
+            ApiError::Status { status, .. } => *status >= 500,
+            _ => false,
+        }
+    }
+
+    /// サーバーから返ってきたエラーメッセージ
+    pub fn server_message(&self) -> Option<&str> {
+        match self {
+            ApiError::Status { message, .. } => message.as_deref(),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn from_reqwest(e: reqwest::Error) -> Self {
+        if e.is_timeout() {
+            ApiError::Timeout
+        } else {
+            ApiError::Transport(e)
+        }
+    }
+
+    pub(crate) fn from_status(status: u16, body: &str) -> Self {
+        #[derive(Deserialize)]
+        struct ErrorBody {
+            error: String,
+        }
+        let message = serde_json::from_str::<ErrorBody>(body)
+            .ok()
+            .map(|b| b.error);
+        ApiError::Status { status, message }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::Transport(e) => write!(f, "transport error: {}", e),
+            ApiError::Timeout => write!(f, "request timed out"),
+            ApiError::Status {
+                status,
+                message: Some(message),
+            } => write!(f, "server returned {}: {}", status, message),
+            ApiError::Status {
+                status,
+                message: None,
+            } => write!(f, "server returned {}", status),
+            ApiError::MalformedResponse { reason, body } => {
+                write!(f, "malformed response ({}): {}", reason, body)
+            }
+            ApiError::ResultCountMismatch { expected, actual } => {
+                write!(f, "expected {} results but got {}", expected, actual)
+            }
+            ApiError::ResultLengthMismatch {
+                plan_idx,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "result for plan {} has length {} but the plan expects {}",
+                plan_idx, actual, expected
+            ),
+            ApiError::InconsistentResult { plan_idx, error } => {
+                write!(f, "result for plan {} is inconsistent: {}", plan_idx, error)
+            }
+            ApiError::NotRecorded { endpoint } => {
+                write!(f, "no recorded response for this /{} request", endpoint)
+            }
+            ApiError::PlanTooLong { plan_idx, error } => {
+                write!(f, "plan {} is too long: {}", plan_idx, error)
+            }
+            ApiError::BudgetExceeded {
+                budget,
+                spent,
+                cost,
+            } => write!(
+                f,
+                "query budget exceeded: {} spent + {} for this batch > {}",
+                spent, cost, budget
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ApiError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ApiError::Transport(e) => Some(e),
+            _ => None,
+        }
+    }
+}
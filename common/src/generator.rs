@@ -0,0 +1,160 @@
+use rand::{Rng, seq::SliceRandom};
+
+use crate::api::{Connection, Map, RoomAndDoor};
+
+/// `doors[room][door]` = (行き先の部屋, 行き先のドア)
+pub type Doors = Vec<[(usize, usize); 6]>;
+
+/// simulator の `Graph._generate_graph` と同じ手順でマップを作る
+///
+/// 1. `n` 部屋の 6 ドアをランダムにペアにする (連結になるまでやり直す)
+/// 2. それを `repeat` 枚複製し、各ドアについて確率 0.2 で隣の層と行き先を入れ替える
+///
+/// ラベルは `(room % n) % 4`、開始部屋は 0。
+/// simulator と違い、2. でドアの対応が壊れたマップは作り直す。
+pub fn generate_graph(n: usize, repeat: usize, rng: &mut impl Rng) -> Map {
+    let labels: Vec<usize> = (0..n).map(|room| room % 4).collect();
+    to_map(&generate_doors(n, repeat, rng), &labels)
+}
+
+/// `generate_graph` のドアの部分だけ。たくさん作って使い捨てるときはこちらが速い
+pub fn generate_doors(n: usize, repeat: usize, rng: &mut impl Rng) -> Doors {
+    loop {
+        let base = random_pairing(n, rng);
+        if let Some(doors) = layered_cover(&base, repeat, 0.2, rng) {
+            return doors;
+        }
+    }
+}
+
+/// 1. の部分。`n` 部屋の 6 ドアをランダムにペアにした連結なグラフ
+pub fn random_pairing(n: usize, rng: &mut impl Rng) -> Doors {
+    loop {
+        let mut doors: Doors = vec![[(0, 0); 6]; n];
+        let mut unassigned: Vec<(usize, usize)> =
+            (0..n).flat_map(|i| (0..6).map(move |j| (i, j))).collect();
+        unassigned.shuffle(rng);
+        while !unassigned.is_empty() {
+            let (room1, door1) = unassigned[0];
+            let loc2 = rng.gen_range(0..unassigned.len());
+            let (room2, door2) = unassigned[loc2];
+            doors[room1][door1] = (room2, door2);
+            doors[room2][door2] = (room1, door1);
+            if loc2 > 0 {
+                unassigned.remove(loc2);
+            }
+            unassigned.remove(0);
+        }
+        if is_connected(&doors) {
+            return doors;
+        }
+    }
+}
+
+/// 2. の部分。`base` を `repeat` 枚複製して、各ドアを確率 `swap_prob` で隣の層と入れ替える
+///
+/// 連結でなくなったか、ドアの対応が壊れた場合は `None`
+/// (simulator の swap は隣の層とつながるドアで対応が崩れることがある)。
+pub fn layered_cover(
+    base: &Doors,
+    repeat: usize,
+    swap_prob: f64,
+    rng: &mut impl Rng,
+) -> Option<Doors> {
+    let n = base.len();
+    let num_rooms = n * repeat;
+    let mut doors = base.clone();
+    if repeat == 1 {
+        return Some(doors);
+    }
+
+    // 複製して、辺をswapする
+    for i in n..num_rooms {
+        let mut row = doors[i - n];
+        for (to_room, _) in row.iter_mut() {
+            *to_room += n;
+        }
+        doors.push(row);
+    }
+    for i1 in 0..num_rooms {
+        for from_door in 0..6 {
+            let (to_room1, to_door) = doors[i1][from_door];
+            let i2 = (i1 + n) % num_rooms;
+            let (to_room2, to_door2) = doors[i2][from_door];
+            debug_assert_eq!(to_door, to_door2);
+            if rng.r#gen::<f64>() >= swap_prob {
+                continue;
+            }
+            doors[i1][from_door] = (to_room2, to_door);
+            doors[to_room2][to_door] = (i1, from_door);
+            doors[i2][from_door] = (to_room1, to_door);
+            doors[to_room1][to_door] = (i2, from_door);
+        }
+    }
+    (is_symmetric(&doors) && is_connected(&doors)).then_some(doors)
+}
+
+fn is_symmetric(doors: &Doors) -> bool {
+    doors.iter().enumerate().all(|(room, row)| {
+        row.iter()
+            .enumerate()
+            .all(|(door, &(to_room, to_door))| doors[to_room][to_door] == (room, door))
+    })
+}
+
+pub fn is_connected(doors: &Doors) -> bool {
+    let mut visited = vec![false; doors.len()];
+    let mut stack = vec![0];
+    visited[0] = true;
+    while let Some(room) = stack.pop() {
+        for &(to_room, _) in &doors[room] {
+            if !visited[to_room] {
+                visited[to_room] = true;
+                stack.push(to_room);
+            }
+        }
+    }
+    visited.iter().all(|&v| v)
+}
+
+/// 開始部屋 0 のマップにする。部屋 `room` のラベルは `labels[room % labels.len()]`
+pub fn to_map(doors: &Doors, labels: &[usize]) -> Map {
+    let mut connections = vec![];
+    for (room, row) in doors.iter().enumerate() {
+        for (door, &(to_room, to_door)) in row.iter().enumerate() {
+            // 同じ接続を2回書かない
+            if (room, door) <= (to_room, to_door) {
+                connections.push(Connection {
+                    from: RoomAndDoor { room, door },
+                    to: RoomAndDoor {
+                        room: to_room,
+                        door: to_door,
+                    },
+                });
+            }
+        }
+    }
+    Map {
+        rooms: (0..doors.len())
+            .map(|room| labels[room % labels.len()])
+            .collect(),
+        starting_room: 0,
+        connections,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{SeedableRng, rngs::StdRng};
+
+    #[test]
+    fn test_generated_maps_are_valid() {
+        let mut rng = StdRng::seed_from_u64(0);
+        for (n, repeat) in [(3, 1), (6, 2), (6, 3), (30, 3)] {
+            let map = generate_graph(n, repeat, &mut rng);
+            assert_eq!(map.rooms.len(), n * repeat);
+            map.validate().unwrap();
+        }
+    }
+}
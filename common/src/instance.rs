@@ -0,0 +1,274 @@
+// ソルバーの苦手なケースを狙ったマップの生成
+//
+// `Instance` は `<family>/<problem>/<seed>` (例: `self-loops/aleph/42`) の短い id で表せて、
+// 同じ id からは常に同じマップができる。落ちたケースは id だけ控えれば再現できる。
+use std::{fmt, str::FromStr};
+
+use rand::{Rng, SeedableRng, rngs::StdRng, seq::SliceRandom};
+
+use crate::api::Map;
+use crate::generator::{Doors, is_connected, layered_cover, random_pairing, to_map};
+use crate::problem::Problem;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Family {
+    /// 本番と同じ生成方法
+    Contest,
+    /// 同じ部屋のドア同士をつなぎやすくして、自己ループを増やす
+    SelfLoops,
+    /// すでにつながっている部屋同士をまたつなぎやすくして、平行なドアを増やす
+    ParallelDoors,
+    /// 巡回グラフ (部屋 i のドアは i + s に行く) で、ラベルも回転で変わらないようにする。
+    /// 印を使わないと部屋の signature がほとんど区別できない
+    Symmetric,
+    /// ラベルの 7 割が 0
+    SkewedLabels,
+    /// 層の複製で全部のドアを隣の層につなぎ替える
+    FullSwap,
+}
+
+impl Family {
+    pub const ALL: [Family; 6] = [
+        Family::Contest,
+        Family::SelfLoops,
+        Family::ParallelDoors,
+        Family::Symmetric,
+        Family::SkewedLabels,
+        Family::FullSwap,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Family::Contest => "contest",
+            Family::SelfLoops => "self-loops",
+            Family::ParallelDoors => "parallel-doors",
+            Family::Symmetric => "symmetric",
+            Family::SkewedLabels => "skewed-labels",
+            Family::FullSwap => "full-swap",
+        }
+    }
+}
+
+impl fmt::Display for Family {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Family {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Family::ALL
+            .into_iter()
+            .find(|family| family.name() == s)
+            .ok_or_else(|| format!("unknown family: {}", s))
+    }
+}
+
+/// 生成方法・大きさ・seed の組
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Instance {
+    pub family: Family,
+    pub problem: Problem,
+    pub seed: u64,
+}
+
+impl fmt::Display for Instance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}/{}", self.family, self.problem.name, self.seed)
+    }
+}
+
+impl FromStr for Instance {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.split('/').collect();
+        let [family, problem, seed] = parts[..] else {
+            return Err(format!("expected <family>/<problem>/<seed>: {}", s));
+        };
+        Ok(Instance {
+            family: family.parse()?,
+            problem: Problem::find(problem)
+                .ok_or_else(|| format!("unknown problem: {}", problem))?,
+            seed: seed
+                .parse()
+                .map_err(|e| format!("invalid seed {:?}: {}", seed, e))?,
+        })
+    }
+}
+
+impl Instance {
+    pub fn generate(&self) -> Map {
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let n = self.problem.rooms_per_layer;
+        loop {
+            let (base, labels) = match self.family {
+                Family::Contest | Family::FullSwap => {
+                    (random_pairing(n, &mut rng), contest_labels(n))
+                }
+                Family::SelfLoops => (
+                    biased_pairing(n, 0.5, |_, room1, room2| room1 == room2, &mut rng),
+                    contest_labels(n),
+                ),
+                Family::ParallelDoors => (
+                    biased_pairing(n, 0.6, is_neighbor, &mut rng),
+                    contest_labels(n),
+                ),
+                Family::Symmetric => circulant(n, &mut rng),
+                Family::SkewedLabels => {
+                    let labels = (0..n)
+                        .map(|_| {
+                            if rng.gen_bool(0.7) {
+                                0
+                            } else {
+                                rng.gen_range(1..4)
+                            }
+                        })
+                        .collect();
+                    (random_pairing(n, &mut rng), labels)
+                }
+            };
+            let doors = match self.family {
+                Family::FullSwap => shifted_cover(&base, self.problem.layers),
+                _ => layered_cover(&base, self.problem.layers, 0.2, &mut rng),
+            };
+            if let Some(doors) = doors {
+                return to_map(&doors, &labels);
+            }
+        }
+    }
+}
+
+fn contest_labels(n: usize) -> Vec<usize> {
+    (0..n).map(|room| room % 4).collect()
+}
+
+const NONE: (usize, usize) = (usize::MAX, usize::MAX);
+
+/// random_pairing と同じようにドアをペアにするが、確率 `bias` で相手を
+/// `is_near(doors, 部屋, 相手の部屋)` が true になる部屋の空いているドアから選ぶ
+fn biased_pairing(
+    n: usize,
+    bias: f64,
+    is_near: impl Fn(&Doors, usize, usize) -> bool,
+    rng: &mut impl Rng,
+) -> Doors {
+    loop {
+        let mut doors: Doors = vec![[NONE; 6]; n];
+        let mut unassigned: Vec<(usize, usize)> =
+            (0..n).flat_map(|i| (0..6).map(move |j| (i, j))).collect();
+        unassigned.shuffle(rng);
+        while !unassigned.is_empty() {
+            let (room1, door1) = unassigned[0];
+            let near: Vec<usize> = (0..unassigned.len())
+                .filter(|&i| is_near(&doors, room1, unassigned[i].0))
+                .collect();
+            let loc2 = if !near.is_empty() && rng.gen_bool(bias) {
+                *near.choose(rng).unwrap()
+            } else {
+                rng.gen_range(0..unassigned.len())
+            };
+            let (room2, door2) = unassigned[loc2];
+            doors[room1][door1] = (room2, door2);
+            doors[room2][door2] = (room1, door1);
+            if loc2 > 0 {
+                unassigned.remove(loc2);
+            }
+            unassigned.remove(0);
+        }
+        if is_connected(&doors) {
+            return doors;
+        }
+    }
+}
+
+/// room1 のどれかのドアがすでに room2 (≠ room1) につながっている
+fn is_neighbor(doors: &Doors, room1: usize, room2: usize) -> bool {
+    room1 != room2 && doors[room1].iter().any(|&(to_room, _)| to_room == room2)
+}
+
+/// 部屋 i のドア `forward[k]` が部屋 i + steps[k] のドア `backward[k]` につながる巡回グラフ
+///
+/// ラベルは `i % gcd(n, 4)` なので、部屋番号を gcd(n, 4) ずらしてもマップは変わらない。
+fn circulant(n: usize, rng: &mut impl Rng) -> (Doors, Vec<usize>) {
+    loop {
+        let steps: Vec<usize> = (0..3).map(|_| rng.gen_range(0..n)).collect();
+        let mut door_order = [0, 1, 2, 3, 4, 5];
+        door_order.shuffle(rng);
+        let mut doors: Doors = vec![[NONE; 6]; n];
+        for (k, &step) in steps.iter().enumerate() {
+            let (forward, backward) = (door_order[2 * k], door_order[2 * k + 1]);
+            for room in 0..n {
+                let to = (room + step) % n;
+                doors[room][forward] = (to, backward);
+                doors[to][backward] = (room, forward);
+            }
+        }
+        if is_connected(&doors) {
+            let period = gcd(n, 4);
+            return (doors, (0..n).map(|room| room % period).collect());
+        }
+    }
+}
+
+/// `base` を `layers` 枚複製し、全部のドアを隣の層につなぐ
+///
+/// base の (u, d) - (v, e) は、層 L の (u, d) と層 L + 1 の (v, e) をつなぐ。
+/// 同じドアの自己ループだけは層の中に残す。連結にならなければ `None`。
+fn shifted_cover(base: &Doors, layers: usize) -> Option<Doors> {
+    let n = base.len();
+    let num_rooms = n * layers;
+    let mut doors: Doors = vec![[NONE; 6]; num_rooms];
+    for layer in 0..layers {
+        let next_layer = (layer + 1) % layers;
+        for (room, row) in base.iter().enumerate() {
+            for (door, &(to_room, to_door)) in row.iter().enumerate() {
+                let from = layer * n + room;
+                if (room, door) == (to_room, to_door) {
+                    doors[from][door] = (from, door);
+                } else if (room, door) < (to_room, to_door) {
+                    let to = next_layer * n + to_room;
+                    doors[from][door] = (to, to_door);
+                    doors[to][to_door] = (from, door);
+                }
+            }
+        }
+    }
+    is_connected(&doors).then_some(doors)
+}
+
+fn gcd(a: usize, b: usize) -> usize {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::problem::PROBLEMS;
+
+    #[test]
+    fn test_every_family_is_valid_and_reproducible() {
+        for family in Family::ALL {
+            for problem in PROBLEMS {
+                let instance = Instance {
+                    family,
+                    problem,
+                    seed: 7,
+                };
+                let map = instance.generate();
+                map.validate()
+                    .unwrap_or_else(|e| panic!("{}: {}", instance, e));
+                assert_eq!(map.rooms.len(), problem.num_rooms());
+
+                let parsed: Instance = instance.to_string().parse().unwrap();
+                assert_eq!(parsed, instance);
+                assert_eq!(
+                    serde_json::to_string(&parsed.generate()).unwrap(),
+                    serde_json::to_string(&map).unwrap()
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,22 @@
+pub mod alignment;
+pub mod api;
+pub mod async_api;
+pub mod budget;
+pub mod cassette;
+pub mod config;
+pub mod equivalence;
+pub mod error;
+pub mod generator;
+pub mod instance;
+pub mod map_file;
+pub mod observation_trie;
+pub mod observations;
+pub mod oracle;
+pub mod plan;
+pub mod plan_builder;
+pub mod plan_generator;
+pub mod problem;
+pub mod query_optimizer;
+pub mod refinement;
+pub mod retry;
+pub mod signature;
@@ -0,0 +1,184 @@
+use std::{fmt, fs, path::Path};
+
+use serde_json::Value;
+
+use crate::api::{Map, RoomAndDoor};
+
+/// マップの読み込み・検証のエラー
+#[derive(Debug)]
+pub enum MapError {
+    Io(String),
+    Json(serde_json::Error),
+    NoRooms,
+    /// ラベルは 0..=3
+    BadLabel {
+        room: usize,
+        label: usize,
+    },
+    BadStartingRoom {
+        starting_room: usize,
+        num_rooms: usize,
+    },
+    /// 存在しない部屋か、0..=5 以外のドア
+    BadDoor(RoomAndDoor),
+    /// 同じドアが別々の相手につながっている
+    ConflictingDoor {
+        door: RoomAndDoor,
+        first: RoomAndDoor,
+        second: RoomAndDoor,
+    },
+    UnconnectedDoor(RoomAndDoor),
+}
+
+impl fmt::Display for MapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MapError::Io(message) => write!(f, "{}", message),
+            MapError::Json(e) => write!(f, "invalid map JSON: {}", e),
+            MapError::NoRooms => write!(f, "map has no rooms"),
+            MapError::BadLabel { room, label } => {
+                write!(f, "room {} has label {} (must be 0..=3)", room, label)
+            }
+            MapError::BadStartingRoom {
+                starting_room,
+                num_rooms,
+            } => write!(
+                f,
+                "starting room {} is out of range for {} rooms",
+                starting_room, num_rooms
+            ),
+            MapError::BadDoor(door) => write!(f, "connection refers to invalid door {:?}", door),
+            MapError::ConflictingDoor {
+                door,
+                first,
+                second,
+            } => write!(
+                f,
+                "door {:?} is connected to both {:?} and {:?}",
+                door, first, second
+            ),
+            MapError::UnconnectedDoor(door) => write!(f, "door {:?} is not connected", door),
+        }
+    }
+}
+
+impl std::error::Error for MapError {}
+
+/// マップの JSON を読んで検証する
+///
+/// `{rooms, startingRoom, connections}` そのものと、`/guess` に送る `{id, map: {...}}` の
+/// どちらの形でも読める。
+pub fn load_map(path: &Path) -> Result<Map, MapError> {
+    let text = fs::read_to_string(path)
+        .map_err(|e| MapError::Io(format!("failed to read {}: {}", path.display(), e)))?;
+    parse_map(&text)
+}
+
+pub fn parse_map(text: &str) -> Result<Map, MapError> {
+    let mut value: Value = serde_json::from_str(text).map_err(MapError::Json)?;
+    if let Some(map) = value.get_mut("map") {
+        value = map.take();
+    }
+    let map: Map = serde_json::from_value(value).map_err(MapError::Json)?;
+    map.validate()?;
+    Ok(map)
+}
+
+impl Map {
+    /// 公式の判定と同じ条件を満たすか確認する
+    ///
+    /// 同じ接続が向きを変えて2回書かれているのは許す (solver の出力によくある)。
+    pub fn validate(&self) -> Result<(), MapError> {
+        self.build_door_table().map(|_| ())
+    }
+
+    /// `door_table()[room][door]` = そのドアの先の部屋とドア
+    ///
+    /// 検証済みのマップに対して使う。
+    pub fn door_table(&self) -> Vec<[RoomAndDoor; 6]> {
+        self.build_door_table()
+            .unwrap_or_else(|e| panic!("invalid map: {}", e))
+    }
+
+    fn build_door_table(&self) -> Result<Vec<[RoomAndDoor; 6]>, MapError> {
+        let num_rooms = self.rooms.len();
+        if num_rooms == 0 {
+            return Err(MapError::NoRooms);
+        }
+        for (room, &label) in self.rooms.iter().enumerate() {
+            if label > 3 {
+                return Err(MapError::BadLabel { room, label });
+            }
+        }
+        if self.starting_room >= num_rooms {
+            return Err(MapError::BadStartingRoom {
+                starting_room: self.starting_room,
+                num_rooms,
+            });
+        }
+
+        let mut table: Vec<[Option<RoomAndDoor>; 6]> = vec![[None; 6]; num_rooms];
+        for c in &self.connections {
+            for (door, other) in [(c.from, c.to), (c.to, c.from)] {
+                if door.room >= num_rooms || door.door >= 6 {
+                    return Err(MapError::BadDoor(door));
+                }
+                match table[door.room][door.door] {
+                    Some(first) if first != other => {
+                        return Err(MapError::ConflictingDoor {
+                            door,
+                            first,
+                            second: other,
+                        });
+                    }
+                    _ => table[door.room][door.door] = Some(other),
+                }
+            }
+        }
+
+        let mut doors = Vec::with_capacity(num_rooms);
+        for (room, row) in table.iter().enumerate() {
+            let mut connected = [RoomAndDoor { room: 0, door: 0 }; 6];
+            for (door, to) in row.iter().enumerate() {
+                connected[door] =
+                    to.ok_or(MapError::UnconnectedDoor(RoomAndDoor { room, door }))?;
+            }
+            doors.push(connected);
+        }
+        Ok(doors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_maps_in_repo() {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("..");
+        for (file, num_rooms) in [
+            ("moririn/golang/data/guess.json", 12),
+            ("moririn/golang/data/graph.json", 12),
+            ("moririn/simulator/graph_data/template/probatio.json", 3),
+        ] {
+            let map = load_map(&root.join(file)).unwrap();
+            assert_eq!(map.rooms.len(), num_rooms);
+        }
+    }
+
+    #[test]
+    fn test_rejects_conflicting_doors() {
+        let bare = r#"{
+            "rooms": [0],
+            "startingRoom": 0,
+            "connections": [
+                {"from": {"room": 0, "door": 0}, "to": {"room": 0, "door": 1}},
+                {"from": {"room": 0, "door": 0}, "to": {"room": 0, "door": 2}}
+            ]
+        }"#;
+        assert!(matches!(
+            parse_map(bare),
+            Err(MapError::ConflictingDoor { .. })
+        ));
+    }
+}
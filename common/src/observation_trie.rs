@@ -0,0 +1,269 @@
+// 複数の plan の観測をまとめた木
+//
+// どの plan も同じ部屋から始まるので、歩き方が途中まで同じ plan は、そこまで同じ部屋を通る。
+// 共通の接頭辞を1つの頂点にまとめれば、ソルバーは重複のない木の上で部屋を割り当てられ、
+// 「同じ接頭辞なら同じ部屋」「スタートは全 plan で同じ部屋」が自動的に満たされる。
+// 同じ歩き方なら観測も同じはずなので、枝は歩き (ドア / `[d]`) で分け、ラベルが食い違えばエラーにする。
+use std::fmt;
+
+use crate::alignment::{Alignment, AlignmentError, LabelWrite, Transition};
+use crate::observations::Observations;
+use crate::plan::{Plan, Step};
+
+/// 木の頂点 = 1つの観測。頂点 0 がスタート
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrieNode {
+    pub label: usize,
+    /// (親, 親からの1歩)
+    pub parent: Option<(usize, Step)>,
+    pub children: Vec<(Step, usize)>,
+    /// スタートからの歩数
+    pub depth: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TrieError {
+    /// 結果が plan と合わない
+    Invalid {
+        plan_idx: usize,
+        error: AlignmentError,
+    },
+    /// 同じ接頭辞なのに、前に入れた plan と違うラベルが観測された
+    Conflict {
+        plan_idx: usize,
+        observation: usize,
+        expected: usize,
+        found: usize,
+    },
+}
+
+impl fmt::Display for TrieError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrieError::Invalid { plan_idx, error } => {
+                write!(f, "result for plan {} is invalid: {}", plan_idx, error)
+            }
+            TrieError::Conflict {
+                plan_idx,
+                observation,
+                expected,
+                found,
+            } => write!(
+                f,
+                "plan {} observes label {} at {} but an earlier plan with the same prefix observed {}",
+                plan_idx, found, observation, expected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TrieError {}
+
+#[derive(Debug, Clone, Default)]
+pub struct ObservationTrie {
+    nodes: Vec<TrieNode>,
+    /// paths[plan_idx][observation] = 頂点
+    paths: Vec<Vec<usize>>,
+}
+
+impl ObservationTrie {
+    pub fn new() -> Self {
+        ObservationTrie::default()
+    }
+
+    pub fn from_observations(
+        observations: &Observations,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut trie = ObservationTrie::new();
+        for (plan_idx, (plan, result)) in observations.iter().enumerate() {
+            let plan: Plan = plan
+                .parse()
+                .map_err(|e| format!("plan {}: {}", plan_idx, e))?;
+            trie.insert(&plan, result)?;
+        }
+        Ok(trie)
+    }
+
+    /// plan とその結果を足し、その plan の番号を返す。エラーなら何も変えない
+    pub fn insert(&mut self, plan: &Plan, results: &[usize]) -> Result<usize, TrieError> {
+        let plan_idx = self.paths.len();
+        let alignment = Alignment::new(plan, results)
+            .map_err(|error| TrieError::Invalid { plan_idx, error })?;
+        // 先に全部たどって食い違いがないか確かめる
+        let mut node = self.nodes.first().map(|_| 0);
+        let mut steps = alignment.steps();
+        for (observation, &label) in results.iter().enumerate() {
+            let Some(current) = node else { break };
+            if self.nodes[current].label != label {
+                return Err(TrieError::Conflict {
+                    plan_idx,
+                    observation,
+                    expected: self.nodes[current].label,
+                    found: label,
+                });
+            }
+            node = steps
+                .next()
+                .and_then(|aligned| self.child(current, aligned.step));
+        }
+
+        if self.nodes.is_empty() {
+            self.push_node(results[0], None);
+        }
+        let mut path = vec![0];
+        for aligned in alignment.steps() {
+            let current = *path.last().unwrap();
+            let next = match self.child(current, aligned.step) {
+                Some(next) => next,
+                None => self.push_node(results[aligned.after], Some((current, aligned.step))),
+            };
+            path.push(next);
+        }
+        self.paths.push(path);
+        Ok(plan_idx)
+    }
+
+    fn push_node(&mut self, label: usize, parent: Option<(usize, Step)>) -> usize {
+        let id = self.nodes.len();
+        let depth = parent.map_or(0, |(p, _)| self.nodes[p].depth + 1);
+        if let Some((p, step)) = parent {
+            self.nodes[p].children.push((step, id));
+        }
+        self.nodes.push(TrieNode {
+            label,
+            parent,
+            children: vec![],
+            depth,
+        });
+        id
+    }
+
+    /// `node` から `step` で進んだ頂点
+    pub fn child(&self, node: usize, step: Step) -> Option<usize> {
+        self.nodes[node]
+            .children
+            .iter()
+            .find(|(s, _)| *s == step)
+            .map(|&(_, child)| child)
+    }
+
+    pub fn nodes(&self) -> &[TrieNode] {
+        &self.nodes
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn num_plans(&self) -> usize {
+        self.paths.len()
+    }
+
+    /// `plan_idx` 番目の plan の `observation` 番目の観測がどの頂点か
+    pub fn node_of(&self, plan_idx: usize, observation: usize) -> usize {
+        self.paths[plan_idx][observation]
+    }
+
+    /// plan ごとの頂点の列
+    pub fn paths(&self) -> &[Vec<usize>] {
+        &self.paths
+    }
+
+    /// まとめる前の観測の数の合計
+    pub fn num_observations(&self) -> usize {
+        self.paths.iter().map(Vec::len).sum()
+    }
+
+    /// plan ごとの不等式 (plan_idx1, obs_idx1, plan_idx2, obs_idx2) を頂点の組にする。
+    /// 同じ頂点どうしの組は落とし、重複はまとめる
+    pub fn node_inequalities(
+        &self,
+        inequalities: &[(usize, usize, usize, usize)],
+    ) -> Vec<(usize, usize)> {
+        let mut pairs: Vec<(usize, usize)> = inequalities
+            .iter()
+            .map(|&(p1, o1, p2, o2)| (self.node_of(p1, o1), self.node_of(p2, o2)))
+            .filter(|(a, b)| a != b)
+            .map(|(a, b)| (a.min(b), a.max(b)))
+            .collect();
+        pairs.sort();
+        pairs.dedup();
+        pairs
+    }
+
+    /// 木の全てのドアの移動。`from` / `to` は頂点
+    pub fn transitions(&self) -> impl Iterator<Item = Transition> + '_ {
+        self.edges().filter_map(|(from, step, to)| match step {
+            Step::Door(door) => Some(Transition { from, door, to }),
+            Step::Mark(_) => None,
+        })
+    }
+
+    /// 木の全ての `[d]`。`before` / `after` は頂点
+    pub fn label_writes(&self) -> impl Iterator<Item = LabelWrite> + '_ {
+        self.edges().filter_map(|(before, step, after)| match step {
+            Step::Mark(label) => Some(LabelWrite {
+                before,
+                label,
+                after,
+            }),
+            Step::Door(_) => None,
+        })
+    }
+
+    fn edges(&self) -> impl Iterator<Item = (usize, Step, usize)> + '_ {
+        self.nodes.iter().enumerate().flat_map(|(from, node)| {
+            node.children
+                .iter()
+                .map(move |&(step, to)| (from, step, to))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shared_prefixes() {
+        let mut trie = ObservationTrie::new();
+        trie.insert(&"012".parse().unwrap(), &[0, 1, 2, 3]).unwrap();
+        trie.insert(&"013".parse().unwrap(), &[0, 1, 2, 0]).unwrap();
+        trie.insert(&"0[3]1".parse().unwrap(), &[0, 1, 3, 2])
+            .unwrap();
+        // スタート + 0 + 01 + 012 + 013 + 0[3] + 0[3]1
+        assert_eq!(trie.len(), 7);
+        assert_eq!(trie.num_observations(), 12);
+        assert_eq!(trie.node_of(0, 2), trie.node_of(1, 2));
+        assert_eq!(trie.node_of(0, 1), trie.node_of(2, 1));
+        assert_ne!(trie.node_of(0, 2), trie.node_of(2, 2));
+        assert!(trie.paths().iter().all(|path| path[0] == 0));
+        assert_eq!(trie.transitions().count(), 5);
+        assert_eq!(trie.label_writes().count(), 1);
+        assert_eq!(trie.nodes()[trie.node_of(2, 3)].depth, 3);
+        // 同じ頂点どうしは落ち、向きと重複はまとまる
+        assert_eq!(
+            trie.node_inequalities(&[(0, 2, 1, 2), (0, 3, 1, 3), (1, 3, 0, 3), (0, 0, 2, 2)]),
+            vec![(0, 5), (3, 4)]
+        );
+
+        assert_eq!(
+            trie.insert(&"01".parse().unwrap(), &[0, 1, 3]),
+            Err(TrieError::Conflict {
+                plan_idx: 3,
+                observation: 2,
+                expected: 2,
+                found: 3
+            })
+        );
+        assert_eq!(trie.num_plans(), 3);
+        assert!(matches!(
+            trie.insert(&"01".parse().unwrap(), &[0, 1]),
+            Err(TrieError::Invalid { plan_idx: 3, .. })
+        ));
+    }
+}
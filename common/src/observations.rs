@@ -0,0 +1,156 @@
+use std::{collections::BTreeMap, error::Error, fs, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::alignment::Alignment;
+use crate::api::ExploreResponse;
+use crate::cassette::{CassetteEntry, load_session};
+use crate::plan::Plan;
+use crate::retry::Endpoint;
+
+/// 1つの問題 (1回の select) について送った plan と返ってきた結果の組
+///
+/// `results[i]` が `plans[i]` の結果。plan には `[d]` が含まれていてもよい。
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Observations {
+    #[serde(default)]
+    pub problem_name: Option<String>,
+    pub plans: Vec<String>,
+    pub results: Vec<Vec<usize>>,
+}
+
+impl Observations {
+    pub fn new(problem_name: Option<String>) -> Self {
+        Observations {
+            problem_name,
+            ..Default::default()
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.plans.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.plans.is_empty()
+    }
+
+    pub fn push(&mut self, plan: String, result: Vec<usize>) {
+        self.plans.push(plan);
+        self.results.push(result);
+    }
+
+    /// 1回の `/explore` の plans とレスポンスを追加する
+    pub fn push_response(&mut self, plans: &[String], response: &ExploreResponse) {
+        self.plans.extend_from_slice(plans);
+        self.results.extend_from_slice(&response.results);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &[usize])> {
+        self.plans
+            .iter()
+            .zip(&self.results)
+            .map(|(plan, result)| (plan.as_str(), result.as_slice()))
+    }
+
+    /// `[d]` を含むか
+    pub fn has_marks(&self, idx: usize) -> bool {
+        self.plans[idx].contains('[')
+    }
+
+    /// 結果を "0123..." の形の文字列にする (SimulatedAnnealingSolver::new などの入力用)
+    pub fn results_str(&self, idx: usize) -> String {
+        self.results[idx]
+            .iter()
+            .map(|&x| std::char::from_digit(x as u32, 10).unwrap())
+            .collect()
+    }
+
+    /// plan と結果の数が合い、それぞれの結果が plan と矛盾しないか (`Alignment::new`)
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        if self.plans.len() != self.results.len() {
+            return Err(format!(
+                "{} plans but {} results",
+                self.plans.len(),
+                self.results.len()
+            )
+            .into());
+        }
+        for (idx, (plan, result)) in self.iter().enumerate() {
+            let plan: Plan = plan.parse().map_err(|e| format!("plan {}: {}", idx, e))?;
+            Alignment::new(&plan, result).map_err(|e| format!("result {}: {}", idx, e))?;
+        }
+        Ok(())
+    }
+}
+
+/// simulator が書き出す `explore_history.json` を読む。キーは `/explore` の id
+///
+/// ```json
+/// {"exploration_data": {"<id>": {"plans": [...], "results": [[...], ...]}}, ...}
+/// ```
+pub fn load_explore_history(path: &Path) -> Result<BTreeMap<String, Observations>, Box<dyn Error>> {
+    #[derive(Deserialize)]
+    struct ExploreHistory {
+        exploration_data: BTreeMap<String, Observations>,
+    }
+    let text = fs::read_to_string(path)
+        .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+    let history: ExploreHistory = serde_json::from_str(&text)
+        .map_err(|e| format!("failed to parse {}: {}", path.display(), e))?;
+    for (id, observations) in &history.exploration_data {
+        observations
+            .validate()
+            .map_err(|e| format!("{} (id {:?}): {}", path.display(), id, e))?;
+    }
+    Ok(history.exploration_data)
+}
+
+/// 記録したセッションを、select ごとの Observations に分ける
+pub fn observations_from_session(
+    entries: &[CassetteEntry],
+) -> Result<Vec<Observations>, Box<dyn Error>> {
+    let mut sessions: Vec<Observations> = vec![];
+    for entry in entries {
+        match entry.endpoint {
+            Endpoint::Select => sessions.push(Observations::new(entry.problem_name.clone())),
+            Endpoint::Explore => {
+                let plans: Vec<String> = serde_json::from_value(entry.request["plans"].clone())?;
+                let response: ExploreResponse = serde_json::from_value(entry.response.clone())?;
+                if sessions.is_empty() {
+                    // select より前の記録 (途中から記録を始めた場合など)
+                    sessions.push(Observations::new(entry.problem_name.clone()));
+                }
+                let observations = sessions.last_mut().unwrap();
+                observations.push_response(&plans, &response);
+                observations.validate()?;
+            }
+            Endpoint::Guess => {}
+        }
+    }
+    Ok(sessions)
+}
+
+pub fn load_session_observations(path: &Path) -> Result<Vec<Observations>, Box<dyn Error>> {
+    observations_from_session(&load_session(path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_explore_history_in_repo() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("../moririn/golang/data/explore_history.json");
+        let history = load_explore_history(&path).unwrap();
+        let observations = &history[""];
+        assert_eq!(observations.len(), 2);
+        assert!(!observations.has_marks(0));
+        assert!(observations.has_marks(1));
+        assert_eq!(
+            observations.results[0].len(),
+            observations.plans[0].len() + 1
+        );
+    }
+}
@@ -0,0 +1,211 @@
+use std::sync::Mutex;
+
+use crate::api::{
+    ApiClient, ExploreResponse, GuessResponse, Map, SelectResponse, check_plan_lengths,
+    check_results,
+};
+use crate::budget::Phase;
+use crate::cassette::Replayer;
+use crate::error::ApiError;
+use crate::map_file::MapError;
+use crate::plan::Plan;
+use crate::problem::Problem;
+use crate::retry::Endpoint;
+
+/// ソルバーから見た「サーバー」
+///
+/// ソルバーは `ApiClient` を自分で作らずにこれを引数で受け取る。
+/// - `ApiClient`: 本物のサーバー (や simulator) に HTTP で問い合わせる
+/// - `Replayer`: 記録したセッションから応答する
+/// - `SimulatedOracle`: 手元のマップで応答する (テスト・ベンチマーク用)
+pub trait Oracle {
+    fn select(&self, problem_name: &str) -> Result<SelectResponse, ApiError>;
+    fn explore(&self, plans: &[String]) -> Result<ExploreResponse, ApiError>;
+    fn guess(&self, map: Map) -> Result<GuessResponse, ApiError>;
+
+    /// これ以降の explore の plan をどの段階として数えるか (`BudgetTracker::set_phases`)
+    ///
+    /// クエリ数を数えていなければ何もしない。
+    fn set_phases(&self, _phases: &[Phase]) {}
+}
+
+impl Oracle for ApiClient {
+    fn select(&self, problem_name: &str) -> Result<SelectResponse, ApiError> {
+        self.select_problem(problem_name)
+    }
+
+    fn explore(&self, plans: &[String]) -> Result<ExploreResponse, ApiError> {
+        ApiClient::explore(self, plans)
+    }
+
+    fn guess(&self, map: Map) -> Result<GuessResponse, ApiError> {
+        ApiClient::guess(self, map)
+    }
+
+    fn set_phases(&self, phases: &[Phase]) {
+        if let Some(budget) = self.budget() {
+            budget.set_phases(phases);
+        }
+    }
+}
+
+impl Oracle for Replayer {
+    fn select(&self, problem_name: &str) -> Result<SelectResponse, ApiError> {
+        let request = serde_json::json!({ "problemName": problem_name });
+        decode(self.answer(Endpoint::Select, &request)?)
+    }
+
+    fn explore(&self, plans: &[String]) -> Result<ExploreResponse, ApiError> {
+        let request = serde_json::json!({ "plans": plans });
+        let response: ExploreResponse = decode(self.answer(Endpoint::Explore, &request)?)?;
+        check_results(plans, &response.results)?;
+        Ok(response)
+    }
+
+    fn guess(&self, map: Map) -> Result<GuessResponse, ApiError> {
+        let request = serde_json::json!({ "map": map });
+        decode(self.answer(Endpoint::Guess, &request)?)
+    }
+}
+
+fn decode<Res: serde::de::DeserializeOwned>(response: serde_json::Value) -> Result<Res, ApiError> {
+    let body = response.to_string();
+    serde_json::from_value(response).map_err(|e| ApiError::MalformedResponse {
+        reason: e.to_string(),
+        body,
+    })
+}
+
+/// 手元のマップに対して explore / guess を答える
+///
+/// 本番サーバーと同じく、select してから guess するまでの間だけ explore でき、
+/// queryCount は select からの累計を返す。
+pub struct SimulatedOracle {
+    map: Map,
+    state: Mutex<SimulatedState>,
+}
+
+#[derive(Default)]
+struct SimulatedState {
+    selected: bool,
+    query_count: usize,
+    /// 登録されている問題名で select したときだけ plan の長さを確認する
+    problem: Option<Problem>,
+}
+
+impl SimulatedOracle {
+    pub fn new(map: Map) -> Result<Self, MapError> {
+        map.validate()?;
+        Ok(SimulatedOracle {
+            map,
+            state: Mutex::new(SimulatedState::default()),
+        })
+    }
+
+    pub fn map(&self) -> &Map {
+        &self.map
+    }
+
+    /// select してから使ったクエリ数
+    pub fn query_count(&self) -> usize {
+        self.state.lock().unwrap().query_count
+    }
+}
+
+fn bad_request(message: String) -> ApiError {
+    ApiError::Status {
+        status: 400,
+        message: Some(message),
+    }
+}
+
+/// plan が `/explore` で受け付けられる形か (ドアは 0..=5、`[d]` の d は 0..=3)
+pub fn check_plan(plan: &str) -> Result<(), String> {
+    plan.parse::<Plan>().map(|_| ()).map_err(|e| e.to_string())
+}
+
+impl Oracle for SimulatedOracle {
+    fn select(&self, problem_name: &str) -> Result<SelectResponse, ApiError> {
+        *self.state.lock().unwrap() = SimulatedState {
+            selected: true,
+            query_count: 0,
+            problem: Problem::find(problem_name),
+        };
+        Ok(SelectResponse {
+            problem_name: problem_name.to_string(),
+        })
+    }
+
+    fn explore(&self, plans: &[String]) -> Result<ExploreResponse, ApiError> {
+        let mut state = self.state.lock().unwrap();
+        if !state.selected {
+            return Err(bad_request("No problem selected".to_string()));
+        }
+        for (i, plan) in plans.iter().enumerate() {
+            check_plan(plan).map_err(|e| bad_request(format!("Invalid plan {}: {}", i, e)))?;
+        }
+        if let Some(problem) = &state.problem {
+            check_plan_lengths(problem, plans)?;
+        }
+        let results: Vec<Vec<usize>> = plans
+            .iter()
+            .map(|plan| self.map.explore(plan))
+            .collect::<Result<_, _>>()
+            .map_err(|e| bad_request(format!("Invalid plan: {}", e)))?;
+        // ApiClient と同じく、結果が plan と合うか確かめてから返す
+        check_results(plans, &results)?;
+        state.query_count += plans.len() + 1;
+        Ok(ExploreResponse {
+            results,
+            query_count: state.query_count,
+        })
+    }
+
+    fn guess(&self, map: Map) -> Result<GuessResponse, ApiError> {
+        let mut state = self.state.lock().unwrap();
+        if !state.selected {
+            return Err(bad_request("No problem selected".to_string()));
+        }
+        map.validate()
+            .map_err(|e| bad_request(format!("Invalid map: {}", e)))?;
+        // 本番と同じく guess したら選択が外れる
+        state.selected = false;
+        Ok(GuessResponse {
+            correct: self.map.rooms.len() == map.rooms.len() && self.map.is_equivalent(&map),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map_file::load_map;
+    use std::path::Path;
+
+    #[test]
+    fn test_simulated_oracle_follows_server_rules() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("../moririn/simulator/graph_data/template/probatio.json");
+        let map = load_map(&path).unwrap();
+        let oracle = SimulatedOracle::new(map.clone()).unwrap();
+
+        assert!(oracle.explore(&["0".to_string()]).is_err());
+        oracle.select("probatio").unwrap();
+        let response = oracle
+            .explore(&["012".to_string(), "[1]0".to_string()])
+            .unwrap();
+        assert_eq!(response.query_count, 3);
+        assert_eq!(response.results[0].len(), 4);
+        assert_eq!(response.results[1][1], 1);
+        assert!(oracle.explore(&["6".to_string()]).is_err());
+        // probatio は 3 部屋なので 54 歩まで
+        assert!(matches!(
+            oracle.explore(&["0".repeat(55)]),
+            Err(ApiError::PlanTooLong { plan_idx: 0, .. })
+        ));
+
+        assert!(oracle.guess(map.clone()).unwrap().correct);
+        // guess したら select し直すまで使えない
+        assert!(oracle.guess(map).is_err());
+    }
+}
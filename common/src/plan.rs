@@ -0,0 +1,305 @@
+use std::{fmt, str::FromStr};
+
+/// plan の1歩
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Step {
+    /// ドア 0..=5 を通る
+    Door(usize),
+    /// 今いる部屋のラベルを 0..=3 に書き換える (`[d]`)
+    Mark(usize),
+}
+
+/// `/explore` に送る plan。`"01[2]3"` のような文字列と相互に変換できる
+///
+/// 文字列から作るときは厳密にチェックする。`Step` を直接積む場合は範囲をチェックしないので、
+/// 送る前に `to_string().parse()` で確かめること。
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Plan {
+    steps: Vec<Step>,
+}
+
+/// plan の文字列のエラー。position は何文字目 (0-indexed) か
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PlanError {
+    InvalidCharacter {
+        position: usize,
+        found: char,
+    },
+    DoorOutOfRange {
+        position: usize,
+        door: u32,
+    },
+    LabelOutOfRange {
+        position: usize,
+        label: u32,
+    },
+    /// `[d]` の形になっていない
+    MalformedMark {
+        position: usize,
+    },
+}
+
+impl fmt::Display for PlanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PlanError::InvalidCharacter { position, found } => {
+                write!(f, "invalid character {:?} at position {}", found, position)
+            }
+            PlanError::DoorOutOfRange { position, door } => write!(
+                f,
+                "door {} at position {} is out of range (must be 0..=5)",
+                door, position
+            ),
+            PlanError::LabelOutOfRange { position, label } => write!(
+                f,
+                "label {} at position {} is out of range (must be 0..=3)",
+                label, position
+            ),
+            PlanError::MalformedMark { position } => {
+                write!(
+                    f,
+                    "charcoal mark at position {} is not of the form [d]",
+                    position
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for PlanError {}
+
+/// plan のドアの数が上限を超えている。`[d]` は上限に数えない
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlanTooLong {
+    pub door_steps: usize,
+    pub max_door_steps: usize,
+    pub marks: usize,
+}
+
+impl PlanTooLong {
+    /// 上限を何歩超えているか
+    pub fn excess(&self) -> usize {
+        self.door_steps - self.max_door_steps
+    }
+}
+
+impl fmt::Display for PlanTooLong {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} door steps exceed the limit of {} by {} ({} charcoal marks are not counted)",
+            self.door_steps,
+            self.max_door_steps,
+            self.excess(),
+            self.marks
+        )
+    }
+}
+
+impl std::error::Error for PlanTooLong {}
+
+impl Plan {
+    pub fn new() -> Self {
+        Plan::default()
+    }
+
+    pub fn steps(&self) -> &[Step] {
+        &self.steps
+    }
+
+    pub fn push(&mut self, step: Step) {
+        self.steps.push(step);
+    }
+
+    pub fn len(&self) -> usize {
+        self.steps.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    /// ドアを通る回数
+    pub fn door_steps(&self) -> usize {
+        self.doors().count()
+    }
+
+    /// `[d]` の個数
+    pub fn mark_count(&self) -> usize {
+        self.len() - self.door_steps()
+    }
+
+    /// `/explore` の結果の長さ (最初の部屋 + 1歩ごとに1つ)
+    pub fn result_len(&self) -> usize {
+        self.len() + 1
+    }
+
+    pub fn doors(&self) -> impl Iterator<Item = usize> + '_ {
+        self.steps.iter().filter_map(|step| match step {
+            Step::Door(door) => Some(*door),
+            Step::Mark(_) => None,
+        })
+    }
+
+    /// ドアの数が `max_door_steps` 以下か
+    pub fn check_door_steps(&self, max_door_steps: usize) -> Result<(), PlanTooLong> {
+        let door_steps = self.door_steps();
+        if door_steps <= max_door_steps {
+            return Ok(());
+        }
+        Err(PlanTooLong {
+            door_steps,
+            max_door_steps,
+            marks: self.mark_count(),
+        })
+    }
+
+    /// ドアが `max_door_steps` 個になるように後ろを切り捨て、切り捨てたドアの数を返す
+    ///
+    /// 最後に残したドアの直後の `[d]` は残す
+    pub fn truncate_door_steps(&mut self, max_door_steps: usize) -> usize {
+        let removed = self.door_steps().saturating_sub(max_door_steps);
+        if removed > 0 {
+            let mut doors = 0;
+            let end = self
+                .steps
+                .iter()
+                .position(|step| {
+                    doors += matches!(step, Step::Door(_)) as usize;
+                    doors > max_door_steps
+                })
+                .unwrap();
+            self.steps.truncate(end);
+        }
+        removed
+    }
+
+    /// `[d]` を取り除いたドアだけの plan
+    pub fn without_marks(&self) -> Plan {
+        self.doors().map(Step::Door).collect()
+    }
+}
+
+impl FromStr for Plan {
+    type Err = PlanError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let chars: Vec<char> = s.chars().collect();
+        let mut steps = vec![];
+        let mut i = 0;
+        while i < chars.len() {
+            match chars[i] {
+                '[' => {
+                    let (Some(label), Some(']')) = (chars.get(i + 1), chars.get(i + 2)) else {
+                        return Err(PlanError::MalformedMark { position: i });
+                    };
+                    let Some(label) = label.to_digit(10) else {
+                        return Err(PlanError::MalformedMark { position: i });
+                    };
+                    if label > 3 {
+                        return Err(PlanError::LabelOutOfRange {
+                            position: i + 1,
+                            label,
+                        });
+                    }
+                    steps.push(Step::Mark(label as usize));
+                    i += 3;
+                }
+                c => {
+                    let Some(door) = c.to_digit(10) else {
+                        return Err(PlanError::InvalidCharacter {
+                            position: i,
+                            found: c,
+                        });
+                    };
+                    if door > 5 {
+                        return Err(PlanError::DoorOutOfRange { position: i, door });
+                    }
+                    steps.push(Step::Door(door as usize));
+                    i += 1;
+                }
+            }
+        }
+        Ok(Plan { steps })
+    }
+}
+
+impl fmt::Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for step in &self.steps {
+            match step {
+                Step::Door(door) => write!(f, "{}", door)?,
+                Step::Mark(label) => write!(f, "[{}]", label)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromIterator<Step> for Plan {
+    fn from_iter<I: IntoIterator<Item = Step>>(iter: I) -> Self {
+        Plan {
+            steps: iter.into_iter().collect(),
+        }
+    }
+}
+
+impl From<Vec<Step>> for Plan {
+    fn from(steps: Vec<Step>) -> Self {
+        Plan { steps }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_print() {
+        let plan: Plan = "01[2]5[0]".parse().unwrap();
+        assert_eq!(plan.to_string(), "01[2]5[0]");
+        assert_eq!(plan.door_steps(), 3);
+        assert_eq!(plan.mark_count(), 2);
+        assert_eq!(plan.result_len(), 6);
+        assert_eq!(plan.without_marks().to_string(), "015");
+
+        assert_eq!(
+            "016".parse::<Plan>(),
+            Err(PlanError::DoorOutOfRange {
+                position: 2,
+                door: 6
+            })
+        );
+        assert_eq!(
+            "0[4]".parse::<Plan>(),
+            Err(PlanError::LabelOutOfRange {
+                position: 2,
+                label: 4
+            })
+        );
+        assert_eq!(
+            "0[1".parse::<Plan>(),
+            Err(PlanError::MalformedMark { position: 1 })
+        );
+        assert_eq!(
+            "0 1".parse::<Plan>(),
+            Err(PlanError::InvalidCharacter {
+                position: 1,
+                found: ' '
+            })
+        );
+    }
+
+    #[test]
+    fn test_door_step_limit() {
+        let mut plan: Plan = "[0]0[1]12[2]34".parse().unwrap();
+        assert_eq!(plan.check_door_steps(5), Ok(()));
+        let error = plan.check_door_steps(3).unwrap_err();
+        assert_eq!(error.excess(), 2);
+        assert_eq!(error.marks, 3);
+
+        assert_eq!(plan.truncate_door_steps(3), 2);
+        assert_eq!(plan.to_string(), "[0]0[1]12[2]");
+        assert_eq!(plan.truncate_door_steps(3), 0);
+    }
+}
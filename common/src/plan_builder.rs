@@ -0,0 +1,117 @@
+// 区間に名前をつけて plan を組み立てる
+//
+// 「スタートからの経路 + signature + signature」のような plan を作り、
+// `/explore` の結果から各区間に対応する部分を名前で取り出す。
+// `[d]` も結果を1つ返すので、文字列の長さで切るとずれるが、ここでは歩数で数える。
+use crate::plan::{Plan, Step};
+
+/// plan の中の1区間。`start` は区間の最初の歩が plan の何歩目か
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+    pub name: String,
+    pub start: usize,
+    pub len: usize,
+}
+
+impl Segment {
+    /// `/explore` の結果のうち、この区間の始まる部屋から最後の歩までの部分 (`len + 1` 個)
+    pub fn results<'a>(&self, results: &'a [usize]) -> Option<&'a [usize]> {
+        results.get(self.start..=self.start + self.len)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PlanBuilder {
+    plan: Plan,
+    segments: Vec<Segment>,
+}
+
+impl PlanBuilder {
+    pub fn new() -> Self {
+        PlanBuilder::default()
+    }
+
+    /// `steps` を名前 `name` の区間として後ろにつなげる。名前が重複したら panic する
+    pub fn segment(mut self, name: &str, steps: &Plan) -> Self {
+        assert!(
+            self.segments.iter().all(|segment| segment.name != name),
+            "duplicate segment name: {}",
+            name
+        );
+        self.segments.push(Segment {
+            name: name.to_string(),
+            start: self.plan.len(),
+            len: steps.len(),
+        });
+        for &step in steps.steps() {
+            self.plan.push(step);
+        }
+        self
+    }
+
+    /// スタートから目的の部屋までの経路
+    pub fn route(self, name: &str, route: &Plan) -> Self {
+        self.segment(name, route)
+    }
+
+    /// 部屋を見分けるための signature。同じものを何度つなげてもよい
+    pub fn probe(self, name: &str, signature: &Plan) -> Self {
+        self.segment(name, signature)
+    }
+
+    /// 今いる部屋に `[label]` を書く
+    pub fn mark(self, name: &str, label: usize) -> Self {
+        self.segment(name, &Plan::from(vec![Step::Mark(label)]))
+    }
+
+    pub fn build(self) -> ComposedPlan {
+        ComposedPlan {
+            plan: self.plan,
+            segments: self.segments,
+        }
+    }
+}
+
+/// 組み立てた plan と、各区間の位置
+#[derive(Debug, Clone)]
+pub struct ComposedPlan {
+    pub plan: Plan,
+    pub segments: Vec<Segment>,
+}
+
+impl ComposedPlan {
+    pub fn segment(&self, name: &str) -> Option<&Segment> {
+        self.segments.iter().find(|segment| segment.name == name)
+    }
+
+    /// 区間 `name` に対応する結果 (`Segment::results`)。区間がないか結果が短ければ None
+    pub fn results<'a>(&self, name: &str, results: &'a [usize]) -> Option<&'a [usize]> {
+        self.segment(name)?.results(results)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_segments_with_marks() {
+        let composed = PlanBuilder::new()
+            .route("route", &"0[1]2".parse().unwrap())
+            .mark("mark", 3)
+            .probe("first", &"45".parse().unwrap())
+            .probe("second", &"45".parse().unwrap())
+            .build();
+        assert_eq!(composed.plan.to_string(), "0[1]2[3]4545");
+        assert_eq!(composed.segment("first").unwrap().start, 4);
+
+        // 最初の部屋 + 8歩
+        let results = [0, 1, 1, 2, 3, 0, 2, 0, 2];
+        assert_eq!(composed.results("route", &results), Some(&[0, 1, 1, 2][..]));
+        assert_eq!(composed.results("mark", &results), Some(&[2, 3][..]));
+        assert_eq!(composed.results("first", &results), Some(&[3, 0, 2][..]));
+        assert_eq!(composed.results("second", &results), Some(&[2, 0, 2][..]));
+        assert_eq!(composed.results("missing", &results), None);
+        assert_eq!(composed.results("second", &results[..8]), None);
+    }
+}
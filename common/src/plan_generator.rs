@@ -0,0 +1,294 @@
+// plan の作り方
+//
+// ソルバーは `Strategy` を名前 (`random` / `random-marks` / `de-bruijn` / `euler-tour`) で選び、
+// `build` でできた `PlanGenerator` に部屋数とドアの歩数を渡して plan を作る。
+use std::{fmt, str::FromStr};
+
+use rand::{Rng, RngCore};
+
+use crate::api::Map;
+use crate::map_file::MapError;
+use crate::plan::{Plan, Step};
+
+/// 問題の大きさと予算から plan を作る
+pub trait PlanGenerator: Send + Sync {
+    /// ドアをちょうど `door_steps` 回通る plan を作る (`[d]` は歩数に数えない)
+    ///
+    /// `num_rooms` は部屋数。使わない作り方もある
+    fn generate(&self, num_rooms: usize, door_steps: usize, rng: &mut dyn RngCore) -> Plan;
+}
+
+/// ドアを一様ランダムに選ぶ
+pub struct UniformRandom;
+
+impl PlanGenerator for UniformRandom {
+    fn generate(&self, _num_rooms: usize, door_steps: usize, rng: &mut dyn RngCore) -> Plan {
+        (0..door_steps)
+            .map(|_| Step::Door(rng.gen_range(0..6)))
+            .collect()
+    }
+}
+
+/// ドアの前に毎回ランダムなラベルの `[d]` を書く
+pub struct RandomMarks;
+
+impl PlanGenerator for RandomMarks {
+    fn generate(&self, _num_rooms: usize, door_steps: usize, rng: &mut dyn RngCore) -> Plan {
+        let mut plan = Plan::new();
+        for _ in 0..door_steps {
+            plan.push(Step::Mark(rng.gen_range(0..4)));
+            plan.push(Step::Door(rng.gen_range(0..6)));
+        }
+        plan
+    }
+}
+
+/// 長さ k のドアの並びを全部1回ずつ含む de Bruijn 列
+///
+/// k は歩数に収まる最大にして、始まる位置はランダムに回す。余った歩数はランダムなドアで埋める
+pub struct DeBruijn;
+
+impl PlanGenerator for DeBruijn {
+    fn generate(&self, _num_rooms: usize, door_steps: usize, rng: &mut dyn RngCore) -> Plan {
+        // 巡回列の長さは 6^k、直線にすると k - 1 だけ長くなる
+        let mut order = 1;
+        while 6usize.pow(order as u32 + 1) + order <= door_steps {
+            order += 1;
+        }
+        let mut sequence = de_bruijn(order);
+        let shift = rng.gen_range(0..sequence.len());
+        sequence.rotate_left(shift);
+        sequence.extend_from_within(..order - 1);
+        sequence.truncate(door_steps);
+        while sequence.len() < door_steps {
+            sequence.push(rng.gen_range(0..6));
+        }
+        sequence.into_iter().map(Step::Door).collect()
+    }
+}
+
+/// ドア 0..=5 の de Bruijn 列 (巡回, 長さ 6^order)。FKM アルゴリズム
+fn de_bruijn(order: usize) -> Vec<usize> {
+    fn extend(t: usize, p: usize, order: usize, a: &mut [usize], sequence: &mut Vec<usize>) {
+        if t > order {
+            if order.is_multiple_of(p) {
+                sequence.extend_from_slice(&a[1..=p]);
+            }
+            return;
+        }
+        a[t] = a[t - p];
+        extend(t + 1, p, order, a, sequence);
+        for door in a[t - p] + 1..6 {
+            a[t] = door;
+            extend(t + 1, t, order, a, sequence);
+        }
+    }
+    let mut sequence = vec![];
+    extend(1, 1, order, &mut vec![0; order + 1], &mut sequence);
+    sequence
+}
+
+/// 決まった plan (焼きなましで作ってファイルに保存したものなど) を毎回そのまま返す
+///
+/// `door_steps` より長ければ後ろを切り捨てる。短くても伸ばさない
+pub struct FixedPlan(pub Plan);
+
+impl PlanGenerator for FixedPlan {
+    fn generate(&self, _num_rooms: usize, door_steps: usize, _rng: &mut dyn RngCore) -> Plan {
+        let mut plan = self.0.clone();
+        plan.truncate_door_steps(door_steps);
+        plan
+    }
+}
+
+/// 仮のマップで、スタートから全てのドアを1回ずつ通って戻ってくるオイラー閉路
+///
+/// 初めて出る部屋には `3 - label` の印を書くので、仮のマップと本物の違いが結果に出やすい。
+/// 歩数が余れば閉路を繰り返し、足りなければ途中で打ち切る
+pub struct EulerTour {
+    labels: Vec<usize>,
+    /// (部屋, ドア) の順
+    tour: Vec<(usize, usize)>,
+}
+
+impl EulerTour {
+    /// 各部屋はドアが6つずつ出て6つずつ入るので、つながっていれば必ず閉路がある
+    pub fn new(map: &Map) -> Result<EulerTour, MapError> {
+        map.validate()?;
+        let table = map.door_table();
+        // Hierholzer 法。next_door[room] はまだ通っていない最初のドア
+        let mut next_door = vec![0; table.len()];
+        let mut stack = vec![(map.starting_room, None)];
+        let mut tour = vec![];
+        while let Some(&(room, _)) = stack.last() {
+            if next_door[room] < 6 {
+                let door = next_door[room];
+                next_door[room] += 1;
+                stack.push((table[room][door].room, Some((room, door))));
+            } else if let Some(arc) = stack.pop().unwrap().1 {
+                tour.push(arc);
+            }
+        }
+        tour.reverse();
+        Ok(EulerTour {
+            labels: map.rooms.clone(),
+            tour,
+        })
+    }
+}
+
+impl PlanGenerator for EulerTour {
+    fn generate(&self, _num_rooms: usize, door_steps: usize, _rng: &mut dyn RngCore) -> Plan {
+        let mut visited = vec![false; self.labels.len()];
+        let mut plan = Plan::new();
+        for &(room, door) in self.tour.iter().cycle().take(door_steps) {
+            if !visited[room] {
+                visited[room] = true;
+                plan.push(Step::Mark(3 - self.labels[room]));
+            }
+            plan.push(Step::Door(door));
+        }
+        plan
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strategy {
+    UniformRandom,
+    RandomMarks,
+    DeBruijn,
+    EulerTour,
+}
+
+impl Strategy {
+    pub const ALL: [Strategy; 4] = [
+        Strategy::UniformRandom,
+        Strategy::RandomMarks,
+        Strategy::DeBruijn,
+        Strategy::EulerTour,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Strategy::UniformRandom => "random",
+            Strategy::RandomMarks => "random-marks",
+            Strategy::DeBruijn => "de-bruijn",
+            Strategy::EulerTour => "euler-tour",
+        }
+    }
+
+    /// `euler-tour` だけは仮のマップ (`hypothesis`) が必要
+    pub fn build(self, hypothesis: Option<&Map>) -> Result<Box<dyn PlanGenerator>, String> {
+        Ok(match self {
+            Strategy::UniformRandom => Box::new(UniformRandom),
+            Strategy::RandomMarks => Box::new(RandomMarks),
+            Strategy::DeBruijn => Box::new(DeBruijn),
+            Strategy::EulerTour => {
+                let map = hypothesis.ok_or("euler-tour needs a hypothesized map")?;
+                Box::new(EulerTour::new(map).map_err(|e| format!("invalid hypothesis: {}", e))?)
+            }
+        })
+    }
+}
+
+impl Strategy {
+    /// ドライバが使えるものだけを名前で選ぶ。使えないときは使えるものを並べたエラー
+    pub fn parse_supported(name: &str, supported: &[Strategy]) -> Result<Strategy, String> {
+        let names = supported
+            .iter()
+            .map(|strategy| strategy.name())
+            .collect::<Vec<_>>()
+            .join(", ");
+        match name.parse::<Strategy>() {
+            Ok(strategy) if supported.contains(&strategy) => Ok(strategy),
+            Ok(strategy) => Err(format!(
+                "plan strategy {} is not supported here (supported: {})",
+                strategy, names
+            )),
+            Err(e) => Err(format!("{} (supported: {})", e, names)),
+        }
+    }
+}
+
+impl fmt::Display for Strategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Strategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Strategy::ALL
+            .into_iter()
+            .find(|strategy| strategy.name() == s)
+            .ok_or_else(|| format!("unknown plan strategy: {}", s))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::problem::Problem;
+    use rand::{SeedableRng, rngs::StdRng};
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_every_strategy() {
+        let problem = Problem::find("primus").unwrap();
+        let map = problem.generate(42);
+        let mut rng = StdRng::seed_from_u64(42);
+        for strategy in Strategy::ALL {
+            assert_eq!(strategy.name().parse::<Strategy>(), Ok(strategy));
+            let generator = strategy.build(Some(&map)).unwrap();
+            for door_steps in [0, 5, 36, problem.max_door_steps()] {
+                let plan = generator.generate(problem.num_rooms(), door_steps, &mut rng);
+                assert_eq!(plan.door_steps(), door_steps);
+                assert_eq!(
+                    map.explore(&plan.to_string()).unwrap().len(),
+                    plan.result_len()
+                );
+            }
+        }
+        assert!(Strategy::EulerTour.build(None).is_err());
+        let supported = [Strategy::UniformRandom, Strategy::DeBruijn];
+        assert_eq!(
+            Strategy::parse_supported("de-bruijn", &supported),
+            Ok(Strategy::DeBruijn)
+        );
+        assert_eq!(
+            Strategy::parse_supported("euler-tour", &supported),
+            Err(
+                "plan strategy euler-tour is not supported here (supported: random, de-bruijn)"
+                    .to_string()
+            )
+        );
+        assert!(Strategy::parse_supported("zigzag", &supported).is_err());
+
+        let fixed = FixedPlan("0[1]23".parse().unwrap());
+        assert_eq!(fixed.generate(1, 2, &mut rng).to_string(), "0[1]2");
+        assert_eq!(fixed.generate(1, 5, &mut rng).to_string(), "0[1]23");
+
+        // 108 歩なら長さ 2 の並び 36 通りが全部出る
+        let plan = DeBruijn.generate(problem.num_rooms(), 108, &mut rng);
+        let doors: Vec<usize> = plan.doors().collect();
+        let pairs: HashSet<_> = doors.windows(2).map(|w| (w[0], w[1])).collect();
+        assert_eq!(pairs.len(), 36);
+
+        // オイラー閉路は 6n 歩で全てのドアを通る
+        let num_doors = 6 * problem.num_rooms();
+        let plan = EulerTour::new(&map)
+            .unwrap()
+            .generate(problem.num_rooms(), num_doors, &mut rng);
+        let table = map.door_table();
+        let mut room = map.starting_room;
+        let mut used = HashSet::new();
+        for door in plan.doors() {
+            used.insert((room, door));
+            room = table[room][door].room;
+        }
+        assert_eq!(used.len(), num_doors);
+        assert_eq!(room, map.starting_room);
+    }
+}
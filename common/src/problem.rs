@@ -0,0 +1,103 @@
+use rand::{SeedableRng, rngs::StdRng};
+
+use crate::api::Map;
+use crate::generator::generate_graph;
+use crate::plan::{Plan, PlanTooLong};
+
+/// 問題の種類。部屋数は `rooms_per_layer * layers`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Problem {
+    pub name: &'static str,
+    pub rooms_per_layer: usize,
+    pub layers: usize,
+}
+
+const fn problem(name: &'static str, rooms_per_layer: usize, layers: usize) -> Problem {
+    Problem {
+        name,
+        rooms_per_layer,
+        layers,
+    }
+}
+
+/// simulator の PROBLEM_DATA と同じ一覧 (testmoririn は simulator のテスト用)
+pub const PROBLEMS: [Problem; 17] = [
+    problem("probatio", 3, 1),
+    problem("primus", 6, 1),
+    problem("secundus", 12, 1),
+    problem("tertius", 18, 1),
+    problem("quartus", 24, 1),
+    problem("quintus", 30, 1),
+    problem("aleph", 6, 2),
+    problem("beth", 12, 2),
+    problem("gimel", 18, 2),
+    problem("daleth", 24, 2),
+    problem("he", 30, 2),
+    problem("vau", 6, 3),
+    problem("zain", 12, 3),
+    problem("hhet", 18, 3),
+    problem("teth", 24, 3),
+    problem("iod", 30, 3),
+    problem("testmoririn", 3, 3),
+];
+
+/// 本番のルールで、1層の問題は部屋数の 18 倍、複数層の問題は 6 倍
+pub fn max_door_steps(num_rooms: usize, layers: usize) -> usize {
+    let factor = if layers == 1 { 18 } else { 6 };
+    num_rooms * factor
+}
+
+impl Problem {
+    pub fn find(name: &str) -> Option<Problem> {
+        PROBLEMS.iter().find(|p| p.name == name).copied()
+    }
+
+    pub fn num_rooms(&self) -> usize {
+        self.rooms_per_layer * self.layers
+    }
+
+    /// 1つの plan で通れるドアの数の上限 (`[d]` は数えない)
+    pub fn max_door_steps(&self) -> usize {
+        max_door_steps(self.num_rooms(), self.layers)
+    }
+
+    pub fn check_plan(&self, plan: &Plan) -> Result<(), PlanTooLong> {
+        plan.check_door_steps(self.max_door_steps())
+    }
+
+    /// 上限を超えた分のドアを切り捨て、切り捨てた数を返す
+    pub fn truncate_plan(&self, plan: &mut Plan) -> usize {
+        plan.truncate_door_steps(self.max_door_steps())
+    }
+
+    /// この問題と同じ大きさのマップを作る。同じ seed なら同じマップになる
+    pub fn generate(&self, seed: u64) -> Map {
+        let mut rng = StdRng::seed_from_u64(seed);
+        generate_graph(self.rooms_per_layer, self.layers, &mut rng)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate_every_problem() {
+        for problem in PROBLEMS {
+            let map = problem.generate(42);
+            map.validate().unwrap();
+            assert_eq!(map.rooms.len(), problem.num_rooms());
+            assert_eq!(
+                serde_json::to_string(&map).unwrap(),
+                serde_json::to_string(&problem.generate(42)).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn test_max_door_steps() {
+        assert_eq!(Problem::find("probatio").unwrap().max_door_steps(), 54);
+        assert_eq!(Problem::find("aleph").unwrap().max_door_steps(), 72);
+        assert_eq!(Problem::find("iod").unwrap().max_door_steps(), 540);
+    }
+}
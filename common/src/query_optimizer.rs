@@ -0,0 +1,182 @@
+// ドアの並び (クエリ) の焼きなまし (onikubo/make_query.cpp の Rust 版)
+//
+// ランダムなマップをたくさん作り、そのマップでクエリをたどったときに一度も通らない
+// (部屋, ドア) の数の合計をスコアにして、これが小さくなるように1文字ずつ変える。
+// マップは毎回作り直すので、特定のマップに合わせすぎたクエリにはならない。
+// スコアの計算は全コアで分担する。
+use std::{fs, path::Path, thread, time::Duration, time::Instant};
+
+use rand::{Rng, RngCore, SeedableRng, rngs::StdRng};
+
+use crate::generator::{Doors, generate_doors};
+use crate::plan::{Plan, Step};
+use crate::plan_generator::{PlanGenerator, UniformRandom};
+
+pub struct QueryOptimizer {
+    /// 層の数 (部屋数は `rooms_per_layer * layers`)
+    pub layers: usize,
+    /// 1回のスコア計算で作るマップの数
+    pub samples: usize,
+    pub time_limit: Duration,
+    /// 焼きなましの温度 (make_query.cpp と同じく一定)
+    pub temperature: f64,
+    pub threads: usize,
+}
+
+/// 焼きなましの結果
+#[derive(Debug, Clone)]
+pub struct Optimized {
+    pub plan: Plan,
+    /// `samples` 個のマップで通らなかった (部屋, ドア) の数の合計
+    pub score: usize,
+    pub iterations: usize,
+}
+
+impl QueryOptimizer {
+    /// make_query.cpp と同じ設定 (マップ 500 個, 温度 10)。スレッド数はコア数
+    pub fn new(layers: usize, time_limit: Duration) -> Self {
+        QueryOptimizer {
+            layers,
+            samples: 500,
+            time_limit,
+            temperature: 10.0,
+            threads: thread::available_parallelism().map_or(1, |n| n.get()),
+        }
+    }
+
+    /// `initial` のドアの並びを焼きなます。`[d]` は無視する
+    pub fn optimize(
+        &self,
+        rooms_per_layer: usize,
+        initial: &Plan,
+        rng: &mut (impl Rng + ?Sized),
+    ) -> Optimized {
+        let start = Instant::now();
+        let mut query: Vec<usize> = initial.doors().collect();
+        let mut current_score = self.score(rooms_per_layer, &query, rng);
+        let mut best = (current_score, query.clone());
+        let mut iterations = 0;
+        while !query.is_empty() && start.elapsed() < self.time_limit {
+            iterations += 1;
+            let pos = rng.gen_range(0..query.len());
+            let pre_door = query[pos];
+            // make_query.cpp は同じドアを引くことがあるが、ここでは必ず変える
+            query[pos] = (pre_door + rng.gen_range(1..6)) % 6;
+
+            let score = self.score(rooms_per_layer, &query, rng);
+            // スコア最小化なので diff > 0 が改善
+            let diff = current_score as f64 - score as f64;
+            if diff > 0.0 || (diff / self.temperature).exp() > rng.r#gen::<f64>() {
+                current_score = score;
+                if score < best.0 {
+                    best = (score, query.clone());
+                }
+            } else {
+                query[pos] = pre_door;
+            }
+        }
+        Optimized {
+            plan: best.1.into_iter().map(Step::Door).collect(),
+            score: best.0,
+            iterations,
+        }
+    }
+
+    /// 新しく作った `samples` 個のマップで、`query` が通らない (部屋, ドア) の数の合計
+    fn score(
+        &self,
+        rooms_per_layer: usize,
+        query: &[usize],
+        rng: &mut (impl Rng + ?Sized),
+    ) -> usize {
+        let threads = self.threads.clamp(1, self.samples.max(1));
+        let seeds: Vec<u64> = (0..threads).map(|_| rng.r#gen()).collect();
+        thread::scope(|scope| {
+            let handles: Vec<_> = seeds
+                .iter()
+                .enumerate()
+                .map(|(i, &seed)| {
+                    // samples をスレッドに均等に分ける
+                    let samples = self.samples / threads + usize::from(i < self.samples % threads);
+                    scope.spawn(move || {
+                        let mut rng = StdRng::seed_from_u64(seed);
+                        (0..samples)
+                            .map(|_| {
+                                let doors = generate_doors(rooms_per_layer, self.layers, &mut rng);
+                                unvisited_doors(&doors, query)
+                            })
+                            .sum::<usize>()
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).sum()
+        })
+    }
+}
+
+/// 部屋 0 から `query` をたどって、一度も通らなかった (部屋, ドア) の数
+fn unvisited_doors(doors: &Doors, query: &[usize]) -> usize {
+    let mut visited = vec![[false; 6]; doors.len()];
+    let mut room = 0;
+    for &door in query {
+        visited[room][door] = true;
+        room = doors[room][door].0;
+    }
+    visited.iter().flatten().filter(|&&v| !v).count()
+}
+
+/// ランダムなドアの並びから焼きなます。`num_rooms` は全体の部屋数
+impl PlanGenerator for QueryOptimizer {
+    fn generate(&self, num_rooms: usize, door_steps: usize, rng: &mut dyn RngCore) -> Plan {
+        let initial = UniformRandom.generate(num_rooms, door_steps, rng);
+        self.optimize(num_rooms / self.layers, &initial, rng).plan
+    }
+}
+
+/// plan を1行のテキストとして保存する。`load_plan` やソルバーの `--plan-file` で読める
+pub fn save_plan(path: &Path, plan: &Plan) -> Result<(), String> {
+    fs::write(path, format!("{}\n", plan)).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+pub fn load_plan(path: &Path) -> Result<Plan, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    text.trim()
+        .parse()
+        .map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_optimize_improves_query() {
+        let mut rng = StdRng::seed_from_u64(42);
+        let optimizer = QueryOptimizer {
+            samples: 20,
+            threads: 2,
+            ..QueryOptimizer::new(2, Duration::from_millis(200))
+        };
+        // ドア 0 しか使わないクエリから始めれば、必ず良くなる
+        let initial: Plan = "0".repeat(36).parse().unwrap();
+        let initial_score = optimizer.score(3, &[0; 36], &mut rng);
+        let optimized = optimizer.optimize(3, &initial, &mut rng);
+        assert_eq!(optimized.plan.door_steps(), 36);
+        assert!(optimized.iterations > 0);
+        assert!(optimized.score < initial_score);
+
+        // 全部のドアを通るなら 0
+        let doors: Doors = vec![[(0, 0), (0, 1), (0, 2), (0, 3), (0, 4), (0, 5)]];
+        assert_eq!(unvisited_doors(&doors, &[0, 1, 2, 3, 4, 5]), 0);
+        assert_eq!(unvisited_doors(&doors, &[0, 0]), 5);
+
+        // 並列に走る他のテストやプロセスとぶつからないように pid とテスト名を入れる
+        let path = std::env::temp_dir().join(format!(
+            "query_optimizer_{}_test_optimize_improves_query.txt",
+            std::process::id()
+        ));
+        save_plan(&path, &optimized.plan).unwrap();
+        assert_eq!(load_plan(&path).unwrap(), optimized.plan);
+        fs::remove_file(path).unwrap();
+    }
+}
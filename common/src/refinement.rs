@@ -0,0 +1,484 @@
+// 観測の列から「同じ部屋」「違う部屋」と確定できる組を求める前処理 (Moore / Hopcroft の分割の細分化に近い)
+//
+// 同じ部屋: 全 plan のスタート、`[d]` の前後、同じ部屋から同じドアで出た先 (合同閉包)。
+// 違う部屋: 元のラベルが違う観測、同じドアで違う部屋に着いた観測 (後ろ向きに伝播)。
+// 互いに違う部屋の観測が部屋数だけ見つかれば、残りの観測はそのどれかなので、
+// 違うと分かっていない部屋が1つしかなければその部屋とまとめる。まとめたらまた伝播し直す。
+// これで手作業で作っていた solution.dot の Members のような分類がソルバーの前に得られる。
+use std::fmt;
+
+use crate::alignment::{Alignment, AlignmentError};
+use crate::observations::Observations;
+use crate::plan::{Plan, Step};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RefinementError {
+    /// 結果が plan と合わない
+    Invalid {
+        plan_idx: usize,
+        error: AlignmentError,
+    },
+    /// 同じ部屋のはずの2つの観測で元のラベルが違う (観測は全 plan を通した番号)
+    Contradiction { first: usize, second: usize },
+    /// 互いに違う部屋の観測が部屋数より多い
+    TooManyRooms { found: usize, num_rooms: usize },
+    /// どの部屋にもなれない観測
+    NoCandidate { observation: usize },
+}
+
+impl fmt::Display for RefinementError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RefinementError::Invalid { plan_idx, error } => {
+                write!(f, "result for plan {} is invalid: {}", plan_idx, error)
+            }
+            RefinementError::Contradiction { first, second } => write!(
+                f,
+                "observations {} and {} must be the same room but have different labels",
+                first, second
+            ),
+            RefinementError::TooManyRooms { found, num_rooms } => write!(
+                f,
+                "found {} pairwise distinct rooms but there are only {}",
+                found, num_rooms
+            ),
+            RefinementError::NoCandidate { observation } => {
+                write!(f, "observation {} cannot be any room", observation)
+            }
+        }
+    }
+}
+
+impl std::error::Error for RefinementError {}
+
+/// 観測の分類 (クラス = 同じ部屋と確定した観測の集まり) と、各クラスがなりうる部屋
+///
+/// 観測は全 plan を通した番号 (`observation`) で数える。
+/// 部屋 `r < anchors().len()` は `anchors()[r]` のクラスの部屋で、それより後ろはまだ観測されていない部屋。
+#[derive(Debug, Clone)]
+pub struct Refinement {
+    num_rooms: usize,
+    offsets: Vec<usize>,
+    class_of: Vec<usize>,
+    members: Vec<Vec<usize>>,
+    /// `[d]` で書き換える前のラベル。`[d]` のあとの観測しかないクラスは None
+    labels: Vec<Option<usize>>,
+    /// edges[class][door] = 行き先のクラス
+    edges: Vec<[Option<usize>; 6]>,
+    distinct: Vec<Vec<bool>>,
+    anchors: Vec<usize>,
+    candidates: Vec<Vec<usize>>,
+}
+
+impl Refinement {
+    pub fn from_observations(
+        observations: &Observations,
+        num_rooms: usize,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let plans = observations
+            .plans
+            .iter()
+            .enumerate()
+            .map(|(plan_idx, plan)| {
+                plan.parse()
+                    .map_err(|e| format!("plan {}: {}", plan_idx, e))
+            })
+            .collect::<Result<Vec<Plan>, _>>()?;
+        Ok(Refinement::new(&plans, &observations.results, num_rooms)?)
+    }
+
+    /// 部屋数 `num_rooms` のマップで `plans` を歩いて `results` を見たとして分類する
+    pub fn new(
+        plans: &[Plan],
+        results: &[Vec<usize>],
+        num_rooms: usize,
+    ) -> Result<Self, RefinementError> {
+        assert_eq!(plans.len(), results.len(), "one result per plan");
+        let mut offsets = vec![];
+        // next[observation] = ドアで進む場合の (ドア, 次の観測)
+        let mut next = vec![];
+        let mut known_labels = vec![];
+        let mut uf = UnionFind::new(results.iter().map(Vec::len).sum());
+        for (plan_idx, (plan, result)) in plans.iter().zip(results).enumerate() {
+            let alignment = Alignment::new(plan, result)
+                .map_err(|error| RefinementError::Invalid { plan_idx, error })?;
+            let offset = next.len();
+            offsets.push(offset);
+            // 最初の `[d]` までの観測だけが元のラベル
+            let marked_from = plan
+                .steps()
+                .iter()
+                .position(|step| matches!(step, Step::Mark(_)))
+                .map_or(result.len(), |pos| pos + 1);
+            for (observation, &label) in result.iter().enumerate() {
+                next.push(None);
+                known_labels.push((observation < marked_from).then_some(label));
+            }
+            uf.union(0, offset);
+            for aligned in alignment.steps() {
+                match aligned.step {
+                    Step::Door(door) => {
+                        next[offset + aligned.before] = Some((door, offset + aligned.after))
+                    }
+                    Step::Mark(_) => {
+                        uf.union(offset + aligned.before, offset + aligned.after);
+                    }
+                }
+            }
+        }
+
+        loop {
+            close_congruence(&mut uf, &next);
+            let mut refinement = Refinement::classify(&mut uf, &next, &known_labels, num_rooms)?;
+            refinement.offsets = offsets.clone();
+            refinement.propagate_distinct();
+            refinement.choose_anchors()?;
+            // 候補が1つしかないクラスをその部屋にまとめる
+            let mut merged = false;
+            for class in 0..refinement.num_classes() {
+                if let [room] = refinement.candidates[class][..] {
+                    let anchor = refinement.anchors[room];
+                    if anchor != class {
+                        uf.union(refinement.members[anchor][0], refinement.members[class][0]);
+                        merged = true;
+                    }
+                }
+            }
+            if !merged {
+                return Ok(refinement);
+            }
+        }
+    }
+
+    /// union-find の結果からクラスを作る。クラスの番号は一番小さい観測の順
+    fn classify(
+        uf: &mut UnionFind,
+        next: &[Option<(usize, usize)>],
+        known_labels: &[Option<usize>],
+        num_rooms: usize,
+    ) -> Result<Self, RefinementError> {
+        let mut class_of_root = vec![usize::MAX; next.len()];
+        let mut class_of = vec![0; next.len()];
+        let mut members: Vec<Vec<usize>> = vec![];
+        let mut labels: Vec<Option<usize>> = vec![];
+        for observation in 0..next.len() {
+            let root = uf.find(observation);
+            if class_of_root[root] == usize::MAX {
+                class_of_root[root] = members.len();
+                members.push(vec![]);
+                labels.push(None);
+            }
+            let class = class_of_root[root];
+            class_of[observation] = class;
+            members[class].push(observation);
+            match (labels[class], known_labels[observation]) {
+                (Some(a), Some(b)) if a != b => {
+                    let first = members[class]
+                        .iter()
+                        .copied()
+                        .find(|&m| known_labels[m] == Some(a))
+                        .unwrap();
+                    return Err(RefinementError::Contradiction {
+                        first,
+                        second: observation,
+                    });
+                }
+                (None, label) => labels[class] = label,
+                _ => {}
+            }
+        }
+        let mut edges = vec![[None; 6]; members.len()];
+        for (observation, step) in next.iter().enumerate() {
+            if let Some((door, to)) = *step {
+                edges[class_of[observation]][door] = Some(class_of[to]);
+            }
+        }
+        let num_classes = members.len();
+        Ok(Refinement {
+            num_rooms,
+            offsets: vec![],
+            class_of,
+            members,
+            labels,
+            edges,
+            distinct: vec![vec![false; num_classes]; num_classes],
+            anchors: vec![],
+            candidates: vec![],
+        })
+    }
+
+    /// ラベルの違いから始めて、同じドアで違う部屋に着くクラスの組を違う部屋とする
+    fn propagate_distinct(&mut self) {
+        let n = self.num_classes();
+        // preds[class][door] = そのドアで class に着くクラス
+        let mut preds = vec![[const { Vec::new() }; 6]; n];
+        for (from, edges) in self.edges.iter().enumerate() {
+            for (door, to) in edges.iter().enumerate() {
+                if let Some(to) = *to {
+                    preds[to][door].push(from);
+                }
+            }
+        }
+        let mut stack = vec![];
+        for a in 0..n {
+            for b in a + 1..n {
+                if let (Some(x), Some(y)) = (self.labels[a], self.labels[b])
+                    && x != y
+                {
+                    self.set_distinct(a, b, &mut stack);
+                }
+            }
+        }
+        while let Some((a, b)) = stack.pop() {
+            for (from_a, from_b) in preds[a].iter().zip(&preds[b]) {
+                for &p in from_a {
+                    for &q in from_b {
+                        // 合同閉包のあとなので p == q にはならない
+                        self.set_distinct(p, q, &mut stack);
+                    }
+                }
+            }
+        }
+    }
+
+    fn set_distinct(&mut self, a: usize, b: usize, stack: &mut Vec<(usize, usize)>) {
+        if !self.distinct[a][b] {
+            self.distinct[a][b] = true;
+            self.distinct[b][a] = true;
+            stack.push((a, b));
+        }
+    }
+
+    /// 互いに違う部屋のクラスを貪欲に集め (スタートが部屋 0)、各クラスの候補の部屋を決める
+    fn choose_anchors(&mut self) -> Result<(), RefinementError> {
+        let n = self.num_classes();
+        let mut order: Vec<usize> = (1..n).collect();
+        order.sort_by_key(|&c| std::cmp::Reverse(self.distinct[c].iter().filter(|&&d| d).count()));
+        let mut anchors = vec![0];
+        for c in order {
+            if anchors.iter().all(|&a| self.distinct[a][c]) {
+                anchors.push(c);
+            }
+        }
+        if anchors.len() > self.num_rooms {
+            return Err(RefinementError::TooManyRooms {
+                found: anchors.len(),
+                num_rooms: self.num_rooms,
+            });
+        }
+        let mut candidates = vec![vec![]; n];
+        for (room, &anchor) in anchors.iter().enumerate() {
+            candidates[anchor].push(room);
+        }
+        for (class, rooms) in candidates.iter_mut().enumerate() {
+            if !rooms.is_empty() {
+                continue;
+            }
+            *rooms = (0..self.num_rooms)
+                .filter(|&room| anchors.get(room).is_none_or(|&a| !self.distinct[a][class]))
+                .collect();
+            if rooms.is_empty() {
+                return Err(RefinementError::NoCandidate {
+                    observation: self.members[class][0],
+                });
+            }
+        }
+        self.anchors = anchors;
+        self.candidates = candidates;
+        Ok(())
+    }
+
+    /// `plan_idx` 番目の plan の `observation` 番目の観測の、全 plan を通した番号
+    pub fn observation(&self, plan_idx: usize, observation: usize) -> usize {
+        self.offsets[plan_idx] + observation
+    }
+
+    pub fn num_observations(&self) -> usize {
+        self.class_of.len()
+    }
+
+    pub fn num_classes(&self) -> usize {
+        self.members.len()
+    }
+
+    pub fn class_of(&self, observation: usize) -> usize {
+        self.class_of[observation]
+    }
+
+    /// クラスの観測 (昇順)
+    pub fn members(&self, class: usize) -> &[usize] {
+        &self.members[class]
+    }
+
+    pub fn label(&self, class: usize) -> Option<usize> {
+        self.labels[class]
+    }
+
+    /// 2つのクラスが違う部屋と分かっているか
+    pub fn is_distinct(&self, a: usize, b: usize) -> bool {
+        self.distinct[a][b]
+    }
+
+    /// `distinct_classes(class)[other]` = `is_distinct(class, other)`
+    pub fn distinct_classes(&self, class: usize) -> &[bool] {
+        &self.distinct[class]
+    }
+
+    /// 互いに違う部屋のクラス。`anchors()[0]` はスタートのクラス
+    pub fn anchors(&self) -> &[usize] {
+        &self.anchors
+    }
+
+    /// クラスがなりうる部屋 (`0..num_rooms`)
+    pub fn candidates(&self, class: usize) -> &[usize] {
+        &self.candidates[class]
+    }
+
+    /// 部屋が1つに決まったクラスならその部屋
+    pub fn fixed(&self, class: usize) -> Option<usize> {
+        match self.candidates[class][..] {
+            [room] => Some(room),
+            _ => None,
+        }
+    }
+
+    /// solution.dot と同じ形の graphviz。頂点はクラス、辺は分かっているドア
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph G {\n  node [shape=Mrecord];\n");
+        for (class, members) in self.members.iter().enumerate() {
+            let label = self.labels[class].map_or("?".to_string(), |l| l.to_string());
+            let members: Vec<String> = members.iter().map(usize::to_string).collect();
+            dot.push_str(&format!(
+                "  room_{} [label=\"{{Label: {}|Members: {}}}\"];\n",
+                self.members[class][0],
+                label,
+                members.join(", ")
+            ));
+        }
+        for (class, edges) in self.edges.iter().enumerate() {
+            for (door, to) in edges.iter().enumerate() {
+                if let Some(to) = *to {
+                    dot.push_str(&format!(
+                        "  room_{} -> room_{} [label=\"{}\"];\n",
+                        self.members[class][0], self.members[to][0], door
+                    ));
+                }
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+/// 同じクラスから同じドアで出た先を同じクラスにする
+fn close_congruence(uf: &mut UnionFind, next: &[Option<(usize, usize)>]) {
+    loop {
+        let mut target = vec![[None; 6]; next.len()];
+        let mut merged = false;
+        for (observation, step) in next.iter().enumerate() {
+            let Some((door, to)) = *step else { continue };
+            let root = uf.find(observation);
+            match target[root][door] {
+                None => target[root][door] = Some(to),
+                Some(other) => merged |= uf.union(other, to),
+            }
+        }
+        if !merged {
+            return;
+        }
+    }
+}
+
+struct UnionFind {
+    parent: Vec<usize>,
+}
+
+impl UnionFind {
+    fn new(n: usize) -> Self {
+        UnionFind {
+            parent: (0..n).collect(),
+        }
+    }
+
+    fn find(&mut self, x: usize) -> usize {
+        let mut root = x;
+        while self.parent[root] != root {
+            root = self.parent[root];
+        }
+        let mut x = x;
+        while self.parent[x] != root {
+            x = std::mem::replace(&mut self.parent[x], root);
+        }
+        root
+    }
+
+    /// まとめたら true
+    fn union(&mut self, a: usize, b: usize) -> bool {
+        let (a, b) = (self.find(a), self.find(b));
+        if a == b {
+            return false;
+        }
+        self.parent[a.max(b)] = a.min(b);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::problem::Problem;
+    use rand::{Rng, SeedableRng, rngs::StdRng};
+
+    #[test]
+    fn test_refinement_is_consistent_with_map() {
+        // 部屋 0 (ラベル 0) と部屋 1 (ラベル 1) の間を、ドア 0 で行き来するだけのマップ
+        let plan: Plan = "000".parse().unwrap();
+        let refinement =
+            Refinement::new(std::slice::from_ref(&plan), &[vec![0, 1, 0, 1]], 2).unwrap();
+        assert_eq!(refinement.num_classes(), 2);
+        assert_eq!(refinement.members(0), [0, 2]);
+        assert_eq!(refinement.fixed(refinement.class_of(3)), Some(1));
+        // 2部屋なのにラベルが3種類
+        assert_eq!(
+            Refinement::new(&[plan], &[vec![0, 1, 2, 1]], 2).unwrap_err(),
+            RefinementError::TooManyRooms {
+                found: 3,
+                num_rooms: 2
+            }
+        );
+
+        // 本物のマップの部屋割りと矛盾しない
+        let mut rng = StdRng::seed_from_u64(42);
+        let problem = Problem::find("primus").unwrap();
+        for seed in 0..10 {
+            let map = problem.generate(seed);
+            let plans: Vec<Plan> = (0..2)
+                .map(|_| {
+                    (0..rng.gen_range(10..60))
+                        .map(|_| match rng.gen_range(0..8) {
+                            0 => Step::Mark(rng.gen_range(0..4)),
+                            _ => Step::Door(rng.gen_range(0..6)),
+                        })
+                        .collect()
+                })
+                .collect();
+            let results: Vec<Vec<usize>> = plans
+                .iter()
+                .map(|plan| map.explore(&plan.to_string()).unwrap())
+                .collect();
+            let rooms: Vec<usize> = plans.iter().flat_map(|plan| map.walk(plan)).collect();
+            let refinement = Refinement::new(&plans, &results, map.rooms.len()).unwrap();
+            for a in 0..rooms.len() {
+                for b in 0..rooms.len() {
+                    let (ca, cb) = (refinement.class_of(a), refinement.class_of(b));
+                    if ca == cb {
+                        assert_eq!(rooms[a], rooms[b]);
+                    }
+                    if refinement.is_distinct(ca, cb) {
+                        assert_ne!(rooms[a], rooms[b]);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,136 @@
+use std::time::Duration;
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::error::ApiError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Endpoint {
+    Select,
+    Explore,
+    Guess,
+}
+
+impl Endpoint {
+    pub fn path(&self) -> &'static str {
+        match self {
+            Endpoint::Select => "select",
+            Endpoint::Explore => "explore",
+            Endpoint::Guess => "guess",
+        }
+    }
+}
+
+/// リトライとバックオフの設定
+///
+/// 失敗は2種類に分けて考える。
+/// - 明らかにサーバーに届いていない失敗（接続できない、429）: どのエンドポイントでもリトライしてよい
+/// - 届いたかどうか分からない失敗（タイムアウト、途中切断、5xx）: `/select` は何度送っても同じだが、
+///   `/explore` と `/guess` はサーバー側でクエリ数に数えられている可能性がある
+///
+/// 後者をリトライするかどうかをエンドポイントごとに決められる。
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// 最初の1回を含めた試行回数。1ならリトライしない
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    pub multiplier: f64,
+    /// 待ち時間をランダムに縮める割合 (0.0..=1.0)。0.5なら 50%〜100% の間でばらつく
+    pub jitter: f64,
+    pub retry_ambiguous_select: bool,
+    pub retry_ambiguous_explore: bool,
+    pub retry_ambiguous_guess: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(10),
+            multiplier: 2.0,
+            jitter: 0.5,
+            retry_ambiguous_select: true,
+            retry_ambiguous_explore: false,
+            retry_ambiguous_guess: false,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// リトライしない
+    pub fn none() -> Self {
+        RetryPolicy {
+            max_attempts: 1,
+            ..Default::default()
+        }
+    }
+
+    pub fn retries_ambiguous(&self, endpoint: Endpoint) -> bool {
+        match endpoint {
+            Endpoint::Select => self.retry_ambiguous_select,
+            Endpoint::Explore => self.retry_ambiguous_explore,
+            Endpoint::Guess => self.retry_ambiguous_guess,
+        }
+    }
+
+    /// `attempt` 回目 (1始まり) の失敗のあと、もう一度送るべきか
+    pub fn should_retry(&self, endpoint: Endpoint, attempt: u32, error: &ApiError) -> bool {
+        if attempt >= self.max_attempts || !error.is_transient() {
+            return false;
+        }
+        !error.is_ambiguous() || self.retries_ambiguous(endpoint)
+    }
+
+    /// `attempt` 回目 (1始まり) の失敗のあとに待つ時間
+    pub fn backoff(&self, attempt: u32, rng: &mut impl Rng) -> Duration {
+        let exp = self.multiplier.powi(attempt.saturating_sub(1) as i32);
+        // Duration のまま掛け算するとオーバーフローで panic しうるので f64 で上限を取る
+        let base = (self.initial_backoff.as_secs_f64() * exp).min(self.max_backoff.as_secs_f64());
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        Duration::from_secs_f64(base * (1.0 - jitter * rng.r#gen::<f64>()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ambiguous_failures_are_per_endpoint() {
+        let policy = RetryPolicy::default();
+        let timeout = ApiError::Timeout;
+        assert!(policy.should_retry(Endpoint::Select, 1, &timeout));
+        assert!(!policy.should_retry(Endpoint::Explore, 1, &timeout));
+        assert!(!policy.should_retry(Endpoint::Guess, 1, &timeout));
+
+        // 429 はサーバーで処理されていないので explore でもリトライしてよい
+        let rate_limited = ApiError::Status {
+            status: 429,
+            message: None,
+        };
+        assert!(policy.should_retry(Endpoint::Explore, 1, &rate_limited));
+        assert!(!policy.should_retry(Endpoint::Explore, policy.max_attempts, &rate_limited));
+
+        let bad_request = ApiError::Status {
+            status: 400,
+            message: Some("Unknown problem: foo".to_string()),
+        };
+        assert!(!policy.should_retry(Endpoint::Select, 1, &bad_request));
+    }
+
+    #[test]
+    fn test_backoff_is_bounded() {
+        let policy = RetryPolicy::default();
+        let mut rng = rand::thread_rng();
+        for attempt in 1..200 {
+            let wait = policy.backoff(attempt, &mut rng);
+            assert!(wait <= policy.max_backoff);
+        }
+        let first = policy.backoff(1, &mut rng);
+        assert!(first >= policy.initial_backoff.mul_f64(1.0 - policy.jitter));
+    }
+}
@@ -0,0 +1,207 @@
+// signature による「この2つの観測は違う部屋」という不等式
+//
+// 観測 a と b (別の plan でもよい) のラベルが同じで、そこから同じ歩き方をして
+// L - 1 回目まで同じラベル、L 回目だけ違うラベルを見たなら、a と b は違う部屋。
+// 各ソルバーの find_signatures_ineqs は (L, a, b) の三重ループで調べていたので plan の長さの3乗かかる。
+// ここでは全 plan の「ラベル 歩き ラベル 歩き ...」を1つの列にして接尾辞配列を作り、
+// 接尾辞の共通接頭辞の長さから同じ不等式を求める。
+// `[d]` のあとは同じ部屋でもラベルが変わりうるので、各 plan の最初の `[d]` より前だけを使う。
+use crate::plan::{Plan, Step};
+
+/// `(plan_idx1, obs_idx1, plan_idx2, obs_idx2)`: 2つの観測は違う部屋
+///
+/// 組は `(plan_idx1, obs_idx1) < (plan_idx2, obs_idx2)` の向きで、1回ずつ並べる
+pub type Inequality = (usize, usize, usize, usize);
+
+/// signature の食い違いから分かる不等式を全部求める
+///
+/// 比べるのは各 plan の最初の `[d]` の直前の観測までで、それより後ろの観測は不等式に出てこない。
+/// 元の複数 plan 版は最後の観測での食い違いを見ていなかったが、ここでは含める。
+///
+/// 時間は全 plan の長さの合計を n として O(n log² n) + (ラベルと最初の1歩が同じ観測の組の数)
+pub fn signature_inequalities(plans: &[Plan], results: &[Vec<usize>]) -> Vec<Inequality> {
+    assert_eq!(plans.len(), results.len(), "one result per plan");
+    // ラベルは 0..4、ドアは 4..10、plan の区切りは 10 から plan ごとに別の記号
+    let mut text = vec![];
+    // origin[pos] = text[pos] がラベルなら (plan_idx, obs_idx)
+    let mut origin = vec![];
+    for (plan_idx, (plan, result)) in plans.iter().zip(results).enumerate() {
+        assert_eq!(
+            result.len(),
+            plan.result_len(),
+            "result of plan {}",
+            plan_idx
+        );
+        for (obs_idx, &label) in result.iter().enumerate() {
+            text.push(label);
+            origin.push(Some((plan_idx, obs_idx)));
+            match plan.steps().get(obs_idx) {
+                Some(&Step::Door(door)) => {
+                    text.push(4 + door);
+                    origin.push(None);
+                }
+                // ここから先はラベルが書き換わっているかもしれない
+                Some(Step::Mark(_)) | None => break,
+            }
+        }
+        text.push(10 + plan_idx);
+        origin.push(None);
+    }
+
+    let sa = suffix_array(&text);
+    let lcp = lcp_array(&text, &sa);
+    // ラベルから始まる接尾辞だけを辞書順に並べ、1つ前との共通接頭辞の長さを持つ
+    let mut starts: Vec<((usize, usize), usize)> = vec![];
+    let mut common = usize::MAX;
+    for (rank, &pos) in sa.iter().enumerate() {
+        common = common.min(lcp[rank]);
+        if let Some(observation) = origin[pos] {
+            starts.push((observation, common));
+            common = usize::MAX;
+        }
+    }
+
+    // 2つの接尾辞の共通接頭辞が偶数 2L (L ≥ 1) なら、L 歩目まで歩きが同じでラベルだけが違う。
+    // 奇数なら歩きが違うか plan の終わりなので何も言えない。区切りは plan ごとに違うので終わりを越えて一致しない
+    let mut inequalities = vec![];
+    for (i, &(first, _)) in starts.iter().enumerate() {
+        let mut common = usize::MAX;
+        for &(second, lcp) in &starts[i + 1..] {
+            common = common.min(lcp);
+            if common < 2 {
+                break;
+            }
+            if common % 2 == 0 {
+                let (x, y) = (first.min(second), first.max(second));
+                inequalities.push((x.0, x.1, y.0, y.1));
+            }
+        }
+    }
+    inequalities.sort_unstable();
+    inequalities
+}
+
+/// 接尾辞配列 (ダブリング)
+fn suffix_array(text: &[usize]) -> Vec<usize> {
+    let n = text.len();
+    let mut sa: Vec<usize> = (0..n).collect();
+    if n == 0 {
+        return sa;
+    }
+    let mut rank = text.to_vec();
+    let mut next_rank = vec![0; n];
+    let mut k = 1;
+    loop {
+        let key = |rank: &[usize], i: usize| (rank[i], rank.get(i + k).map_or(0, |r| r + 1));
+        sa.sort_unstable_by_key(|&i| key(&rank, i));
+        next_rank[sa[0]] = 0;
+        for w in 1..n {
+            let step = key(&rank, sa[w - 1]) < key(&rank, sa[w]);
+            next_rank[sa[w]] = next_rank[sa[w - 1]] + usize::from(step);
+        }
+        std::mem::swap(&mut rank, &mut next_rank);
+        if rank[sa[n - 1]] == n - 1 {
+            break;
+        }
+        k *= 2;
+    }
+    sa
+}
+
+/// lcp[i] = 接尾辞 sa[i - 1] と sa[i] の共通接頭辞の長さ (Kasai 法)。lcp[0] = 0
+fn lcp_array(text: &[usize], sa: &[usize]) -> Vec<usize> {
+    let n = text.len();
+    let mut rank = vec![0; n];
+    for (i, &pos) in sa.iter().enumerate() {
+        rank[pos] = i;
+    }
+    let mut lcp = vec![0; n];
+    let mut h: usize = 0;
+    for pos in 0..n {
+        if rank[pos] == 0 {
+            h = 0;
+            continue;
+        }
+        let prev = sa[rank[pos] - 1];
+        while pos + h < n && prev + h < n && text[pos + h] == text[prev + h] {
+            h += 1;
+        }
+        lcp[rank[pos]] = h;
+        h = h.saturating_sub(1);
+    }
+    lcp
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{Connection, Map, RoomAndDoor};
+    use crate::problem::Problem;
+    use rand::{Rng, SeedableRng, rngs::StdRng};
+
+    // 不等式の2つの観測が本当に違う部屋か
+    fn assert_sound(map: &Map, plans: &[Plan]) -> usize {
+        let results: Vec<Vec<usize>> = plans
+            .iter()
+            .map(|plan| map.explore(&plan.to_string()).unwrap())
+            .collect();
+        let rooms: Vec<Vec<usize>> = plans.iter().map(|plan| map.walk(plan)).collect();
+        let inequalities = signature_inequalities(plans, &results);
+        for &(p1, a, p2, b) in &inequalities {
+            assert_ne!(
+                rooms[p1][a],
+                rooms[p2][b],
+                "{:?} in {:?}",
+                (p1, a, p2, b),
+                plans
+            );
+        }
+        inequalities.len()
+    }
+
+    #[test]
+    fn test_inequalities_are_different_rooms() {
+        // ラベル 0 の部屋 A, B をドア 0 でつなぎ、ほかのドアは自己ループ。
+        // B に [1] を書いてから戻ってくると、同じ B なのに 0 と 1 に見える
+        let mut connections = vec![Connection {
+            from: RoomAndDoor { room: 0, door: 0 },
+            to: RoomAndDoor { room: 1, door: 0 },
+        }];
+        for room in 0..2 {
+            for door in 1..6 {
+                let end = RoomAndDoor { room, door };
+                connections.push(Connection { from: end, to: end });
+            }
+        }
+        let map = Map {
+            rooms: vec![0, 0],
+            starting_room: 0,
+            connections,
+        };
+        let plan: Plan = "00[1]000".parse().unwrap();
+        assert_eq!(
+            map.explore(&plan.to_string()).unwrap(),
+            [0, 0, 0, 1, 0, 1, 0]
+        );
+        assert_sound(&map, &[plan]);
+
+        let mut rng = StdRng::seed_from_u64(42);
+        let mut found = 0;
+        for seed in 0..20 {
+            let map = Problem::find("primus").unwrap().generate(seed);
+            let plans: Vec<Plan> = (0..rng.gen_range(1..4))
+                .map(|_| {
+                    (0..rng.gen_range(0..60))
+                        .map(|_| match rng.gen_range(0..12) {
+                            0 => Step::Mark(rng.gen_range(0..4)),
+                            // ドアを少なくして同じ歩き方を増やす
+                            _ => Step::Door(rng.gen_range(0..2)),
+                        })
+                        .collect()
+                })
+                .collect();
+            found += assert_sound(&map, &plans);
+        }
+        assert!(found > 0);
+    }
+}
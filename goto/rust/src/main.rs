@@ -697,83 +697,48 @@ impl SimulatedAnnealingSolver {
 }
 
 // シグネチャ： ある長さのsuffixに対して、resultsが少しでも異なるなら、異なる部屋がわりあたるべきだ.
-// returns 複数の不等式
+// returns 複数の不等式 (common::signature で接尾辞配列を使って求める)
 fn find_signatures_ineqs(
     plans: Vec<String>,
     results: &Vec<Vec<usize>>,
 ) -> Vec<(usize, usize, usize, usize)> {
-    let mut inequalities = HashSet::default();
-    for start_plan in 0..plans.len() {
-        for sig_len in 1..plans[start_plan].len() {
-            for sig_start in 0..plans[start_plan].len() - sig_len {
-                let sig = &plans[start_plan][sig_start..sig_start + sig_len];
-                // 他の場所で同じsubstringがあるか?
-                for other_plan in start_plan..plans.len() {
-                    for other_start in match start_plan == other_plan {
-                        true => sig_start + 1..plans[other_plan].len() - sig_len,
-                        false => 0..plans[other_plan].len() - sig_len,
-                    } {
-                        if results[start_plan][sig_start] != results[other_plan][other_start] {
-                            continue;
-                        }
-                        let other = &plans[other_plan][other_start..other_start + sig_len];
-                        if sig == other {
-                            // 同じsubstringが見つかった
-                            // それぞれに対応するresultsを比較して、最後の文字だけが異なるなら、不要しき
-                            if (0..sig_len).all(|i| {
-                                if i < sig_len - 1 {
-                                    results[start_plan][1 + sig_start + i]
-                                        == results[other_plan][1 + other_start + i]
-                                } else {
-                                    results[start_plan][1 + sig_start + i]
-                                        != results[other_plan][1 + other_start + i]
-                                }
-                            }) {
-                                inequalities.insert((
-                                    start_plan,
-                                    sig_start,
-                                    other_plan,
-                                    other_start,
-                                ));
-                            }
-                        }
-                    }
-                }
-            }
-        }
-    }
-    inequalities.into_iter().collect()
-}
-
-fn gen_random_string(alphabet: &str, length: usize, rng: &mut impl Rng) -> String {
-    (0..length)
-        .map(|_| {
-            let idx = rng.gen_range(0..alphabet.len());
-            alphabet.chars().nth(idx).unwrap()
-        })
-        .collect()
+    let plans: Vec<Plan> = plans.iter().map(|plan| plan.parse().unwrap()).collect();
+    signature_inequalities(&plans, results)
 }
 
 // マップを見てすべての
 
 pub mod aleph;
-pub mod api;
+use common::api;
+use common::plan::Plan;
+use common::plan_generator::{PlanGenerator, UniformRandom};
+use common::problem::Problem;
+use common::signature::signature_inequalities;
 
 fn main() {
-    let api_client = api::ApiClient::new();
+    // 長時間回すので、explore がタイムアウトしても1クエリ余分に払ってでも続行する
+    let api_client = api::ApiClient::new().with_retry_policy(api::RetryPolicy {
+        max_attempts: 10,
+        retry_ambiguous_explore: true,
+        ..Default::default()
+    });
 
     let mut iteeeer = 0;
     loop {
         iteeeer += 1;
-        let num_rooms = 24;
-        let bb = 18;
-        let select_response = api_client.select_problem("teth").unwrap();
+        let problem = Problem::find("teth").unwrap();
+        let num_rooms = problem.rooms_per_layer;
+        let select_response = api_client.select_problem(problem.name).unwrap();
         // println!("Select response: {:?}", select_response);
         let oni_plan = "101000355110224551423435433021124433432312145253145124220224433254303442443030550402353401153505245234541244013123041522553444102052141153442355244134242325423132220032442040450311012513112254353413014132045533205510051322500155213225531225303232043000144345515151111053311223533013443540045351501020524234235231500511344453422134033231443300021331105455314301041113453331023303110035055150325044222550550111213234133540201315415545".to_string();
 
         let num_simple_plans = 1;
         let mut simple_plans = (0..num_simple_plans)
-            .map(|_| gen_random_string("012345", num_rooms * bb, &mut thread_rng()))
+            .map(|_| {
+                UniformRandom
+                    .generate(problem.num_rooms(), problem.max_door_steps(), &mut thread_rng())
+                    .to_string()
+            })
             .collect::<Vec<String>>();
 
         let gachi_plan = gen_new_plan(&oni_plan, &mut thread_rng());
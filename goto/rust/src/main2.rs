@@ -7,7 +7,7 @@ use std::sync::{Arc, Mutex};
 use std::thread;
 
 use crate::aleph::gen_new_plan;
-use crate::api::{Connection, Map, RoomAndDoor};
+use common::api::{self, Connection, Map, RoomAndDoor};
 
 // --- 焼きなましパラメータ ---
 const INITIAL_TEMPERATURE: f64 = 1.0;
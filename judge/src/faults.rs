@@ -0,0 +1,181 @@
+use std::time::Duration;
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+/// わざと起こす障害の確率 (0.0〜1.0)
+///
+/// settings.json の `faults` が全セッションのデフォルトで、`/faults` でセッションごとに変えられる。
+/// ```json
+/// {"faults": {"server_error_rate": 0.1, "slow_rate": 0.05, "slow_secs": 35}}
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Faults {
+    /// 処理はしたうえで 500 を返す (explore のクエリは数えられ、guess も判定される)
+    pub server_error_rate: f64,
+    /// 処理したうえで `slow_secs` 秒待ってから返す。クライアントのタイムアウトは 30 秒
+    pub slow_rate: f64,
+    pub slow_secs: f64,
+    /// 処理したうえで、HTTP でないものを返して応答を壊す
+    pub drop_rate: f64,
+    /// 処理せずに 429 を返す
+    pub rate_limit_rate: f64,
+}
+
+impl Default for Faults {
+    fn default() -> Self {
+        Faults {
+            server_error_rate: 0.0,
+            slow_rate: 0.0,
+            slow_secs: 35.0,
+            drop_rate: 0.0,
+            rate_limit_rate: 0.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fault {
+    ServerError,
+    Slow(Duration),
+    Drop,
+}
+
+impl Faults {
+    /// 確率が 0〜1 で、`slow_secs` が 0 以上の有限の値か
+    ///
+    /// 500・遅延・破損は `draw` で1つの乱数から選ぶので、その3つの合計も 1 以下でなければならない。
+    /// 429 は処理する前に別に引くので合計には入れない
+    pub fn validate(&self) -> Result<(), String> {
+        let rates = [
+            ("server_error_rate", self.server_error_rate),
+            ("slow_rate", self.slow_rate),
+            ("drop_rate", self.drop_rate),
+            ("rate_limit_rate", self.rate_limit_rate),
+        ];
+        for (name, rate) in rates {
+            if !(0.0..=1.0).contains(&rate) {
+                return Err(format!("{} must be in 0..=1, got {}", name, rate));
+            }
+        }
+        let total = self.server_error_rate + self.slow_rate + self.drop_rate;
+        if total > 1.0 {
+            return Err(format!(
+                "server_error_rate + slow_rate + drop_rate must be at most 1, got {}",
+                total
+            ));
+        }
+        if !self.slow_secs.is_finite() || self.slow_secs < 0.0 {
+            return Err(format!(
+                "slow_secs must be finite and at least 0, got {}",
+                self.slow_secs
+            ));
+        }
+        Ok(())
+    }
+
+    /// 処理する前に 429 を返すか
+    pub fn rate_limited(&self, rng: &mut impl Rng) -> bool {
+        rng.r#gen::<f64>() < self.rate_limit_rate
+    }
+
+    /// 処理したあとの応答に起こす障害。`validate` を通った設定で呼ぶ
+    pub fn draw(&self, rng: &mut impl Rng) -> Option<Fault> {
+        let x = rng.r#gen::<f64>();
+        if x < self.server_error_rate {
+            Some(Fault::ServerError)
+        } else if x < self.server_error_rate + self.slow_rate {
+            Some(Fault::Slow(Duration::from_secs_f64(self.slow_secs)))
+        } else if x < self.server_error_rate + self.slow_rate + self.drop_rate {
+            Some(Fault::Drop)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{SeedableRng, rngs::StdRng};
+
+    #[test]
+    fn test_draw() {
+        let mut rng = StdRng::seed_from_u64(0);
+        assert!((0..100).all(|_| Faults::default().draw(&mut rng).is_none()));
+
+        let faults = Faults {
+            slow_rate: 1.0,
+            slow_secs: 0.5,
+            ..Default::default()
+        };
+        assert!(faults.validate().is_ok());
+        assert_eq!(
+            faults.draw(&mut rng),
+            Some(Fault::Slow(Duration::from_millis(500)))
+        );
+
+        // 3つの障害がだいたい設定どおりの割合で起きる
+        let faults = Faults {
+            server_error_rate: 0.2,
+            slow_rate: 0.3,
+            drop_rate: 0.5,
+            ..Default::default()
+        };
+        let mut counts = [0; 3];
+        for _ in 0..10000 {
+            match faults.draw(&mut rng) {
+                Some(Fault::ServerError) => counts[0] += 1,
+                Some(Fault::Slow(_)) => counts[1] += 1,
+                Some(Fault::Drop) => counts[2] += 1,
+                None => panic!("total rate is 1"),
+            }
+        }
+        assert!((1700..2300).contains(&counts[0]), "{:?}", counts);
+        assert!((2700..3300).contains(&counts[1]), "{:?}", counts);
+        assert!((4700..5300).contains(&counts[2]), "{:?}", counts);
+    }
+
+    #[test]
+    fn test_validate() {
+        let bad = [
+            Faults {
+                server_error_rate: -0.1,
+                ..Default::default()
+            },
+            Faults {
+                rate_limit_rate: 1.5,
+                ..Default::default()
+            },
+            Faults {
+                slow_rate: f64::NAN,
+                ..Default::default()
+            },
+            Faults {
+                server_error_rate: 0.6,
+                drop_rate: 0.6,
+                ..Default::default()
+            },
+            Faults {
+                slow_secs: -1.0,
+                ..Default::default()
+            },
+            Faults {
+                slow_secs: f64::INFINITY,
+                ..Default::default()
+            },
+        ];
+        for faults in bad {
+            assert!(faults.validate().is_err(), "{:?}", faults);
+        }
+        let ok = Faults {
+            server_error_rate: 0.5,
+            drop_rate: 0.5,
+            rate_limit_rate: 1.0,
+            slow_secs: 0.0,
+            ..Default::default()
+        };
+        assert!(ok.validate().is_ok());
+    }
+}
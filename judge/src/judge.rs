@@ -0,0 +1,467 @@
+// simulator (moririn/simulator/main.py) の各エンドポイントの処理
+//
+// simulator は選択中のマップをグローバルに1つしか持たないが、ここでは
+// リクエストの id ごとに別々のセッション (選択中の問題・クエリ数・guess の履歴) を持つ。
+// id のないリクエストは "default" のセッションになる。
+//
+// `/faults` ({"id": ..., "faults": {...}}) でそのセッションに起こす障害を変えられる。
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use common::api::Map;
+use common::equivalence::distinguishing_plan;
+use common::generator::generate_graph;
+use common::map_file::load_map;
+use common::plan::Plan;
+use common::problem::Problem;
+use rand::{SeedableRng, rngs::StdRng, seq::SliceRandom};
+use serde_json::{Value, json};
+
+use crate::faults::{Fault, Faults};
+use crate::settings::Settings;
+
+/// エラー時のステータスとメッセージ。`{"error": message}` として返す
+pub type Reply = Result<Value, (u16, String)>;
+
+fn bad_request(message: impl Into<String>) -> (u16, String) {
+    (400, message.into())
+}
+
+pub struct Judge {
+    settings: Settings,
+    /// graph_data/ の場所 (settings.json と同じフォルダ)
+    data_dir: PathBuf,
+    sessions: Mutex<HashMap<String, Arc<Mutex<Session>>>>,
+}
+
+/// 1つの id の状態
+struct Session {
+    id: String,
+    rng: StdRng,
+    selected_map: Option<Map>,
+    /// 最後の /explore の {plans, results}
+    last_exploration: Option<Value>,
+    total_query_count: usize,
+    guess_history: Vec<Value>,
+    log_dir: Option<PathBuf>,
+    faults: Faults,
+    /// 選択中の問題 (plan の長さの上限に使う)
+    problem: Option<Problem>,
+}
+
+impl Judge {
+    pub fn new(settings: Settings, data_dir: PathBuf) -> Judge {
+        Judge {
+            settings,
+            data_dir,
+            sessions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn session(&self, data: &Value) -> Arc<Mutex<Session>> {
+        let id = data
+            .get("id")
+            .and_then(Value::as_str)
+            .unwrap_or("default")
+            .to_string();
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions
+            .entry(id.clone())
+            .or_insert_with(|| {
+                Arc::new(Mutex::new(Session {
+                    id,
+                    rng: StdRng::from_entropy(),
+                    selected_map: None,
+                    last_exploration: None,
+                    total_query_count: 0,
+                    guess_history: vec![],
+                    log_dir: None,
+                    faults: self.settings.faults.clone(),
+                    problem: None,
+                }))
+            })
+            .clone()
+    }
+
+    /// リクエストを処理して、応答と、応答に起こす障害を返す
+    pub fn handle(&self, endpoint: &str, data: &Value) -> (Reply, Option<Fault>) {
+        let handler = match endpoint {
+            "/select" => Judge::select,
+            "/explore" => Judge::explore,
+            "/guess" => Judge::guess,
+            "/faults" => return (self.set_faults(data), None),
+            _ => return (Err((404, "Not found".to_string())), None),
+        };
+        let session = self.session(data);
+        let rate_limited = {
+            let mut guard = session.lock().unwrap();
+            let session = &mut *guard;
+            session.faults.rate_limited(&mut session.rng)
+        };
+        if rate_limited {
+            return (Err((429, "Too many requests".to_string())), None);
+        }
+        let reply = handler(self, data);
+        let mut guard = session.lock().unwrap();
+        let session = &mut *guard;
+        let fault = session.faults.draw(&mut session.rng);
+        if let Some(fault) = fault {
+            println!(
+                "[{}] Injecting fault into {}: {:?}",
+                session.id, endpoint, fault
+            );
+        }
+        (reply, fault)
+    }
+
+    fn set_faults(&self, data: &Value) -> Reply {
+        let Some(faults) = data.get("faults") else {
+            return Err(bad_request("Missing required field: faults"));
+        };
+        let faults: Faults = serde_json::from_value(faults.clone())
+            .map_err(|e| bad_request(format!("Invalid faults: {}", e)))?;
+        faults
+            .validate()
+            .map_err(|e| bad_request(format!("Invalid faults: {}", e)))?;
+        let session = self.session(data);
+        let mut session = session.lock().unwrap();
+        println!("[{}] Faults: {:?}", session.id, faults);
+        session.faults = faults;
+        Ok(json!({ "faults": session.faults }))
+    }
+
+    fn select(&self, data: &Value) -> Reply {
+        let Some(problem_name) = data.get("problemName") else {
+            return Err(bad_request("Missing required field: problemName"));
+        };
+        let problem_name = problem_name.as_str().unwrap_or_default().to_string();
+        let session = self.session(data);
+        let mut guard = session.lock().unwrap();
+        let session = &mut *guard;
+        session.total_query_count = 0;
+        let Some(problem) = Problem::find(&problem_name) else {
+            return Err(bad_request(format!("Unknown problem: {}", problem_name)));
+        };
+
+        // seed があれば毎回同じマップになる
+        if let Some(seed) = self.settings.seed {
+            session.rng = StdRng::seed_from_u64(seed);
+        }
+        let map = match &self.settings.graph_folder {
+            Some(folder) => self.load_random_map(folder, &mut session.rng)?,
+            None => generate_graph(problem.rooms_per_layer, problem.layers, &mut session.rng),
+        };
+
+        session.log_dir = None;
+        if self.settings.debug {
+            let mut dir_name = chrono::Local::now().format("%Y%m%d_%H%M%S_%3f").to_string();
+            if session.id != "default" {
+                dir_name = format!("{}_{}", dir_name, sanitize(&session.id));
+            }
+            let log_dir = Path::new("log").join(dir_name);
+            fs::create_dir_all(&log_dir).map_err(|e| (500, e.to_string()))?;
+            write_json(
+                &log_dir.join("graph.json"),
+                &json!({ "id": "id", "map": map }),
+            );
+            session.log_dir = Some(log_dir);
+        }
+        println!(
+            "[{}] Selected problem: {} ({} rooms)",
+            session.id,
+            problem_name,
+            map.rooms.len()
+        );
+        session.selected_map = Some(map);
+        session.problem = Some(problem);
+        session.last_exploration = None;
+        session.guess_history.clear();
+        Ok(json!({ "problemName": problem_name }))
+    }
+
+    fn load_random_map(&self, folder: &str, rng: &mut StdRng) -> Result<Map, (u16, String)> {
+        let folder = self.data_dir.join("graph_data").join(folder);
+        let mut files: Vec<PathBuf> = fs::read_dir(&folder)
+            .map(|entries| {
+                entries
+                    .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                    .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+                    .collect()
+            })
+            .unwrap_or_default();
+        // seed で同じファイルを選ぶように並べておく
+        files.sort();
+        let Some(path) = files.choose(rng) else {
+            return Err((
+                404,
+                format!("No JSON files found in folder: {}", folder.display()),
+            ));
+        };
+        load_map(path).map_err(|e| bad_request(format!("Invalid data: {}", e)))
+    }
+
+    fn explore(&self, data: &Value) -> Reply {
+        let Some(plans) = data.get("plans") else {
+            return Err(bad_request("Missing required field: plans"));
+        };
+        let Some(plans) = plans.as_array() else {
+            return Err(bad_request("plans must be an array"));
+        };
+        let session = self.session(data);
+        let mut guard = session.lock().unwrap();
+        let session = &mut *guard;
+        let Some(map) = &session.selected_map else {
+            return Err(bad_request(
+                "No graph selected. Please select a problem first.",
+            ));
+        };
+
+        let mut results = vec![];
+        for (i, plan) in plans.iter().enumerate() {
+            let Some(plan) = plan.as_str() else {
+                return Err(bad_request(format!("Plan at index {} must be a string", i)));
+            };
+            let parsed: Plan = plan
+                .parse()
+                .map_err(|e| bad_request(format!("Invalid plan at index {}: {}", i, e)))?;
+            // simulator にはないが、本番と同じくドアの数の上限を超える plan は受け付けない
+            if let Some(problem) = &session.problem {
+                problem
+                    .check_plan(&parsed)
+                    .map_err(|e| bad_request(format!("Plan at index {} is too long: {}", i, e)))?;
+            }
+            results.push(
+                map.explore(plan)
+                    .map_err(|e| bad_request(format!("Invalid plan at index {}: {}", i, e)))?,
+            );
+        }
+
+        session.last_exploration = Some(json!({ "plans": plans, "results": results }));
+        // 本番と違い、queryCount は累計ではなくこの呼び出しの分だけ
+        let query_count = plans.len() + 1;
+        session.total_query_count += query_count;
+        println!(
+            "[{}] Explore response: {} results, queryCount: {}",
+            session.id,
+            results.len(),
+            query_count
+        );
+        Ok(json!({ "results": results, "queryCount": query_count }))
+    }
+
+    fn guess(&self, data: &Value) -> Reply {
+        let Some(map_data) = data.get("map") else {
+            return Err(bad_request("Missing required field: map"));
+        };
+        if ["rooms", "startingRoom", "connections"]
+            .iter()
+            .any(|key| map_data.get(key).is_none())
+        {
+            return Err(bad_request(
+                "Missing required map fields: rooms, startingRoom, and connections",
+            ));
+        }
+        if !map_data["rooms"].is_array() {
+            return Err(bad_request("rooms must be an array"));
+        }
+        if !map_data["connections"].is_array() {
+            return Err(bad_request("connections must be an array"));
+        }
+        let session = self.session(data);
+        let mut guard = session.lock().unwrap();
+        let session = &mut *guard;
+        let Some(selected_map) = &session.selected_map else {
+            return Err(bad_request(
+                "No graph selected. Please select a problem first.",
+            ));
+        };
+
+        let guessed_map: Map = serde_json::from_value(map_data.clone())
+            .map_err(|e| bad_request(format!("Invalid graph data: {}", e)))?;
+        guessed_map
+            .validate()
+            .map_err(|e| bad_request(format!("Invalid graph data: {}", e)))?;
+
+        // 最後の /explore を guess したマップが再現できるか (simulator の "for test" と同じ確認)
+        if let Some(exploration) = &session.last_exploration {
+            let plans = exploration["plans"].as_array().into_iter().flatten();
+            for (k, plan) in plans.filter_map(Value::as_str).enumerate() {
+                let (res1, res2) = match (selected_map.explore(plan), guessed_map.explore(plan)) {
+                    (Ok(res1), Ok(res2)) => (res1, res2),
+                    (Err(e), _) | (_, Err(e)) => {
+                        return Err(bad_request(format!("Invalid plan {}: {}", k, e)));
+                    }
+                };
+                if let Some(i) = (0..res1.len()).find(|&i| res1[i] != res2[i]) {
+                    return Err(bad_request(format!(
+                        "Invalid graph data: explore({}) response error at index {}, bef: {}, guess: {}",
+                        k, i, res1[i], res2[i]
+                    )));
+                }
+            }
+        }
+
+        // simulator はランダムウォークで比べるが、ここでは厳密に判定する
+        let correct = if selected_map.rooms.len() != guessed_map.rooms.len() {
+            println!(
+                "[{}] Guessed {} rooms, expected {}",
+                session.id,
+                guessed_map.rooms.len(),
+                selected_map.rooms.len()
+            );
+            false
+        } else if let Some(plan) = distinguishing_plan(selected_map, &guessed_map) {
+            println!("[{}] Distinguishing plan: {}", session.id, plan);
+            false
+        } else {
+            true
+        };
+        println!("[{}] Graph comparison result: {}", session.id, correct);
+
+        let timestamp = chrono::Local::now()
+            .format("%Y-%m-%dT%H:%M:%S%.6f")
+            .to_string();
+        let record = json!({
+            "id": data.get("id").cloned().unwrap_or(json!("unknown")),
+            "map": map_data,
+            "correct": correct,
+            "timestamp": timestamp,
+        });
+        session.guess_history.push(record.clone());
+        if let (Some(log_dir), true) = (&session.log_dir, self.settings.debug) {
+            let mut exploration_data = serde_json::Map::new();
+            if let Some(exploration) = &session.last_exploration {
+                exploration_data.insert(session.id.clone(), exploration.clone());
+            }
+            write_json(&log_dir.join("guess.json"), &record);
+            write_json(
+                &log_dir.join("guess_history.json"),
+                &Value::from(session.guess_history.clone()),
+            );
+            write_json(
+                &log_dir.join("explore_history.json"),
+                &json!({
+                    "exploration_data": exploration_data,
+                    "total_query_count": session.total_query_count,
+                    "timestamp": timestamp,
+                }),
+            );
+        }
+        Ok(json!({ "correct": correct }))
+    }
+}
+
+/// id をフォルダ名に使えるようにする
+fn sanitize(id: &str) -> String {
+    id.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+fn write_json(path: &Path, value: &Value) {
+    let text = serde_json::to_string_pretty(value).unwrap();
+    if let Err(e) = fs::write(path, text) {
+        println!("Failed to save {}: {}", path.display(), e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn judge() -> Judge {
+        Judge::new(Settings::default(), PathBuf::from("."))
+    }
+
+    fn selected_map(judge: &Judge, id: &str) -> Map {
+        let session = judge.session(&json!({ "id": id }));
+        let map = session.lock().unwrap().selected_map.clone();
+        map.unwrap()
+    }
+
+    #[test]
+    fn test_sessions_are_separate() {
+        let judge = judge();
+        let (reply, _) = judge.handle("/select", &json!({"id": "a", "problemName": "probatio"}));
+        assert_eq!(reply.unwrap()["problemName"], "probatio");
+        let (reply, _) = judge.handle("/select", &json!({"id": "b", "problemName": "primus"}));
+        assert_eq!(reply.unwrap()["problemName"], "primus");
+        let (map_a, map_b) = (selected_map(&judge, "a"), selected_map(&judge, "b"));
+        assert_eq!((map_a.rooms.len(), map_b.rooms.len()), (3, 6));
+
+        // 結果はそれぞれの選んだマップから
+        let (reply, _) = judge.handle("/explore", &json!({"id": "a", "plans": ["0", "12"]}));
+        let reply = reply.unwrap();
+        assert_eq!(reply["queryCount"], 3);
+        assert_eq!(
+            reply["results"],
+            json!([map_a.explore("0").unwrap(), map_a.explore("12").unwrap()])
+        );
+        let (reply, _) = judge.handle("/explore", &json!({"id": "b", "plans": ["345"]}));
+        let reply = reply.unwrap();
+        assert_eq!(reply["queryCount"], 2);
+        assert_eq!(reply["results"], json!([map_b.explore("345").unwrap()]));
+        let count = |id: &str| {
+            judge
+                .session(&json!({ "id": id }))
+                .lock()
+                .unwrap()
+                .total_query_count
+        };
+        assert_eq!((count("a"), count("b")), (3, 2));
+
+        // 選び直しても他の id のクエリ数は変わらない
+        let (reply, _) = judge.handle("/select", &json!({"id": "a", "problemName": "probatio"}));
+        assert!(reply.is_ok());
+        let map_a = selected_map(&judge, "a");
+        assert_eq!((count("a"), count("b")), (0, 2));
+        // 選んでいない id は explore できない
+        let (reply, _) = judge.handle("/explore", &json!({"id": "c", "plans": ["0"]}));
+        assert_eq!(reply.unwrap_err().0, 400);
+
+        // guess もそれぞれのマップと比べ、履歴も別々
+        let (reply, _) = judge.handle("/guess", &json!({"id": "a", "map": map_a}));
+        assert_eq!(reply.unwrap(), json!({"correct": true}));
+        let (reply, _) = judge.handle("/guess", &json!({"id": "b", "map": map_a}));
+        assert_ne!(reply, Ok(json!({"correct": true})));
+        let history = |id: &str| {
+            let session = judge.session(&json!({ "id": id }));
+            let history = session.lock().unwrap().guess_history.clone();
+            history
+                .iter()
+                .map(|record| record["correct"].as_bool().unwrap())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(history("a"), vec![true]);
+        assert!(!history("b").contains(&true));
+    }
+
+    #[test]
+    fn test_set_faults() {
+        let judge = judge();
+        let (reply, _) = judge.handle("/faults", &json!({"id": "a", "faults": {"drop_rate": 1.0}}));
+        assert_eq!(reply.unwrap()["faults"]["drop_rate"], 1.0);
+        // 決めたセッションにだけ障害が起きる
+        let (_, fault) = judge.handle("/select", &json!({"id": "a", "problemName": "probatio"}));
+        assert_eq!(fault, Some(Fault::Drop));
+        let (_, fault) = judge.handle("/select", &json!({"id": "b", "problemName": "probatio"}));
+        assert_eq!(fault, None);
+
+        for faults in [
+            json!({"slow_rate": 0.5, "slow_secs": -1.0}),
+            json!({"server_error_rate": 1.5}),
+            json!({"server_error_rate": 0.5, "slow_rate": 0.3, "drop_rate": 0.3}),
+            json!({"unknown_rate": 0.1}),
+        ] {
+            let (reply, _) = judge.handle("/faults", &json!({"id": "a", "faults": faults}));
+            assert_eq!(reply.unwrap_err().0, 400, "{}", faults);
+        }
+        // 弾いた設定は反映されない
+        let (_, fault) = judge.handle("/select", &json!({"id": "a", "problemName": "probatio"}));
+        assert_eq!(fault, Some(Fault::Drop));
+    }
+}
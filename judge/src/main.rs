@@ -0,0 +1,102 @@
+// ローカル用の API サーバー (moririn/simulator の Rust 版)
+//
+// 使い方: cargo run --release -p judge [settings.json]
+// PORT (デフォルト 5000) で `/select` `/explore` `/guess` を受け付ける。
+// id ごとに別のセッションになるので、複数のソルバーから同時に使える。
+// settings.json の `faults` か `/faults` で、500・遅延・応答の破損・429 をわざと起こせる。
+mod faults;
+mod judge;
+mod settings;
+
+use std::{io::Write, path::Path, sync::Arc, thread};
+
+use serde_json::{Value, json};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use faults::Fault;
+use judge::{Judge, Reply};
+use settings::Settings;
+
+fn main() {
+    let settings_path = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "settings.json".to_string());
+    let settings_path = Path::new(&settings_path);
+    let settings = Settings::load(settings_path);
+    // graph_folder は settings.json の隣の graph_data/ から探す
+    let data_dir = settings_path
+        .parent()
+        .unwrap_or(Path::new("."))
+        .to_path_buf();
+    println!("Settings: {:?}", settings);
+    let judge = Arc::new(Judge::new(settings, data_dir));
+
+    let port: u16 = std::env::var("PORT")
+        .ok()
+        .and_then(|port| port.parse().ok())
+        .unwrap_or(5000);
+    let server = Arc::new(Server::http(("0.0.0.0", port)).expect("failed to start server"));
+    let num_threads = thread::available_parallelism().map_or(4, |n| n.get());
+    println!(
+        "Starting API server on port {} with {} threads",
+        port, num_threads
+    );
+
+    let workers: Vec<_> = (0..num_threads)
+        .map(|_| {
+            let (server, judge) = (server.clone(), judge.clone());
+            thread::spawn(move || {
+                for request in server.incoming_requests() {
+                    respond(&judge, request);
+                }
+            })
+        })
+        .collect();
+    for worker in workers {
+        worker.join().unwrap();
+    }
+}
+
+fn respond(judge: &Judge, mut request: Request) {
+    let (reply, fault) = handle(judge, &mut request);
+    let (status, body) = match (reply, fault) {
+        (_, Some(Fault::ServerError)) => (500, json!({ "error": "Internal server error" })),
+        (Ok(body), _) => (200, body),
+        (Err((status, message)), _) => {
+            println!("{} {}: {}", status, request.url(), message);
+            (status, json!({ "error": message }))
+        }
+    };
+    match fault {
+        Some(Fault::Drop) => {
+            // tiny_http では接続を切れないので、HTTP でないものを返してクライアント側で失敗させる
+            let mut writer = request.into_writer();
+            let _ = writer.write_all(b"\0\r\n\r\n");
+            let _ = writer.flush();
+            return;
+        }
+        Some(Fault::Slow(duration)) => thread::sleep(duration),
+        _ => {}
+    }
+    let response = Response::from_string(body.to_string())
+        .with_status_code(status)
+        .with_header(Header::from_bytes("Content-Type", "application/json").unwrap())
+        .with_header(Header::from_bytes("Access-Control-Allow-Origin", "*").unwrap());
+    if let Err(e) = request.respond(response) {
+        println!("Failed to send response: {}", e);
+    }
+}
+
+fn handle(judge: &Judge, request: &mut Request) -> (Reply, Option<Fault>) {
+    if *request.method() != Method::Post {
+        return (Err((405, "Method not allowed".to_string())), None);
+    }
+    let mut body = String::new();
+    if let Err(e) = request.as_reader().read_to_string(&mut body) {
+        return (Err((400, e.to_string())), None);
+    }
+    match serde_json::from_str(&body) {
+        Ok(data @ Value::Object(_)) => judge.handle(request.url(), &data),
+        _ => (Err((400, "No JSON data provided".to_string())), None),
+    }
+}
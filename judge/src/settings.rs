@@ -0,0 +1,51 @@
+use std::{fs, path::Path};
+
+use serde::Deserialize;
+
+use crate::faults::Faults;
+
+/// simulator の `settings.json` と同じ設定
+///
+/// ```json
+/// {"seed": 42, "debug": true, "graph_folder": "template", "faults": {"server_error_rate": 0.1}}
+/// ```
+#[derive(Debug, Default, Deserialize)]
+pub struct Settings {
+    /// あれば select のたびに乱数をこの seed で初期化する
+    pub seed: Option<u64>,
+    /// true なら select ごとに `log/<時刻>_<id>/` を作り、graph.json / guess.json / explore_history.json を書き出す
+    #[serde(default)]
+    pub debug: bool,
+    /// あれば `graph_data/<graph_folder>/*.json` からランダムに1つ選んでマップにする
+    pub graph_folder: Option<String>,
+    /// 各セッションで最初に使う障害の設定
+    #[serde(default)]
+    pub faults: Faults,
+}
+
+impl Settings {
+    /// ファイルがなければデフォルト設定。読めない・壊れている場合も警告してデフォルトにする
+    pub fn load(path: &Path) -> Settings {
+        if !path.exists() {
+            println!("{} not found, using default settings", path.display());
+            return Settings::default();
+        }
+        let parsed = fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|text| serde_json::from_str(&text).map_err(|e| e.to_string()));
+        let parsed = parsed.and_then(|settings: Settings| {
+            settings
+                .faults
+                .validate()
+                .map(|()| settings)
+                .map_err(|e| format!("invalid faults: {}", e))
+        });
+        match parsed {
+            Ok(settings) => settings,
+            Err(e) => {
+                println!("Failed to load {}: {}", path.display(), e);
+                Settings::default()
+            }
+        }
+    }
+}
@@ -1,26 +1,21 @@
+use common::plan::{Plan, Step};
 use rand::Rng;
 use std::process::Command;
 use serde::{Serialize, Deserialize};
 use crate::api;
 
-fn gen_random_string(alphabet: &str, length: usize, rng: &mut impl Rng) -> String {
-    (0..length)
-        .map(|_| {
-            let idx = rng.gen_range(0..alphabet.len());
-            alphabet.chars().nth(idx).unwrap()
-        })
-        .collect()
-}
-
-
-
+// bef_plan の各ドアのあとに [0]〜[3] をランダムに書く。最初の部屋は [0]
 pub fn gen_new_plan(bef_plan: &String, rng: &mut impl Rng) -> String {
-    let mut new_plan = "[0]".to_string();
-    let charcoals = gen_random_string("0123", bef_plan.len(), rng);
-    for (p,c) in bef_plan.chars().zip(charcoals.chars()) {
-        new_plan += &format!("{}[{}]",p, c);
+    let bef_plan: Plan = bef_plan
+        .parse()
+        .unwrap_or_else(|e| panic!("invalid plan {:?}: {}", bef_plan, e));
+    let mut new_plan = Plan::new();
+    new_plan.push(Step::Mark(0));
+    for door in bef_plan.doors() {
+        new_plan.push(Step::Door(door));
+        new_plan.push(Step::Mark(rng.gen_range(0..4)));
     }
-    new_plan
+    new_plan.to_string()
 }
 #[derive(Serialize, Debug)]
 pub struct GoInput {
@@ -19,12 +19,13 @@ const MAX_DOORS: usize = 6;
 
 struct SimulatedAnnealingSolver {
     /// 問題定義
-    observed_labels: Vec<Vec<usize>>, // observed_labels[query_id][observation_idx] = label
-    transitions: Vec<Vec<(usize, usize)>>, // transitions[query_id][step] = (from_observation_idx, door)
-    num_rooms: usize,                      // 現在の部屋数
+    /// 共通の接頭辞をまとめた木 (どの plan も同じ部屋から始まる)。頂点ごとに部屋を1つ割り当てる
+    trie: ObservationTrie,
+    transitions: Vec<(usize, usize, usize)>, // transitions[i] = (from_node, door, to_node)
+    num_rooms: usize,                        // 現在の部屋数
 
     /// 探索中の状態
-    assignment: Vec<Vec<usize>>, // assignment[query_id][observation_idx] = room_id
+    assignment: Vec<usize>, // assignment[node] = room_id
 
     /// コスト計算用の補助データ構造
     cost: i32,
@@ -33,20 +34,12 @@ struct SimulatedAnnealingSolver {
     filled_in_future: Vec<i32>,    // room -> count of filled doors
     kasikari_count: Vec<Vec<i32>>, // kasikari_count[from_room][to_room] = count
 
-    known_inequalities: Vec<(usize, usize, usize, usize)>, // (plan_idx1, obs_idx1, plan_idx2, obs_idx2) という形で、obs_idx1 != obs_idx2 であるべきことを示す
-    known_inequalities_by_obs_idx: Vec<Vec<Vec<(usize, usize)>>>,
+    known_inequalities: Vec<(usize, usize)>, // (node1, node2) という形で、違う部屋であるべきことを示す
+    known_inequalities_by_node: Vec<Vec<usize>>,
 }
 
 impl SimulatedAnnealingSolver {
     pub fn new(plan_str: Vec<String>, results_str: Vec<String>, num_rooms: usize) -> Self {
-        let plan: Vec<Vec<usize>> = plan_str
-            .iter()
-            .map(|s| {
-                s.chars()
-                    .map(|c| c.to_digit(10).unwrap() as usize)
-                    .collect()
-            })
-            .collect();
         let observed_labels: Vec<Vec<usize>> = results_str
             .iter()
             .map(|s| {
@@ -56,42 +49,30 @@ impl SimulatedAnnealingSolver {
             })
             .collect();
 
-        let known_inequalities = find_signatures_ineqs(plan_str, &observed_labels);
-
-        let mut transitions = plan
-            .iter()
-            .map(|single_plan| {
-                let mut single_t = Vec::new();
-                for i in 0..single_plan.len() {
-                    single_t.push((i, single_plan[i]))
-                }
-                single_t
-            })
-            .collect();
-
-        let mut assignment = vec![];
-        observed_labels
-            .iter()
-            .enumerate()
-            .for_each(|(idx, single_obs)| {
-                let num_observations = single_obs.len();
-                let mut assignment_single = vec![0; num_observations];
-                for i in 0..num_observations {
-                    let label = single_obs[i];
-                    assignment_single[i] = label;
-                }
-                assignment.push(assignment_single);
-            });
+        let mut trie = ObservationTrie::new();
+        for (single_plan, single_obs) in plan_str.iter().zip(&observed_labels) {
+            trie.insert(&single_plan.parse().unwrap(), single_obs)
+                .unwrap_or_else(|e| panic!("{}", e));
+        }
+        println!(
+            "{} observations share {} trie nodes",
+            trie.num_observations(),
+            trie.len()
+        );
 
-        let mut known_inequalities_by_obs_idx =
-            vec![vec![vec![]; observed_labels[0].len()]; observed_labels.len()];
-        for (p1, r1, p2, r2) in &known_inequalities {
-            known_inequalities_by_obs_idx[*p1][*r1].push((*p2, *r2));
-            known_inequalities_by_obs_idx[*p2][*r2].push((*p1, *r1));
+        let known_inequalities =
+            trie.node_inequalities(&find_signatures_ineqs(plan_str, &observed_labels));
+        let mut known_inequalities_by_node = vec![vec![]; trie.len()];
+        for &(n1, n2) in &known_inequalities {
+            known_inequalities_by_node[n1].push(n2);
+            known_inequalities_by_node[n2].push(n1);
         }
 
+        let transitions = trie.transitions().map(|t| (t.from, t.door, t.to)).collect();
+        let assignment = trie.nodes().iter().map(|node| node.label).collect();
+
         let mut solver = Self {
-            observed_labels,
+            trie,
             transitions,
             assignment,
             num_rooms,
@@ -100,13 +81,22 @@ impl SimulatedAnnealingSolver {
             filled_in_future: vec![0; MAX_ROOMS],
             kasikari_count: vec![vec![0; MAX_ROOMS]; MAX_ROOMS],
             known_inequalities,
-            known_inequalities_by_obs_idx,
+            known_inequalities_by_node,
         };
 
         solver.recalculate_cost();
         solver
     }
 
+    /// plan ごとの部屋割り当て (assignment[plan_idx][observation_idx] = room_id)
+    fn plan_assignments(&self) -> Vec<Vec<usize>> {
+        self.trie
+            .paths()
+            .iter()
+            .map(|path| path.iter().map(|&node| self.assignment[node]).collect())
+            .collect()
+    }
+
     /// 現在の `assignment` に基づいてコストをゼロから再計算する
     fn recalculate_cost(&mut self) {
         let mut total_cost: i32 = 0;
@@ -123,14 +113,11 @@ impl SimulatedAnnealingSolver {
             }
         }
 
-        for i in 0..self.transitions.len() {
-            let transitions_single = &self.transitions[i];
-            for (from_idx, door) in transitions_single {
-                let from_room = self.assignment[i][*from_idx];
-                let to_room = self.assignment[i][*from_idx + 1];
-                // 遷移の回数を増やす
-                self.graph[from_room][*door][to_room] += 1;
-            }
+        for &(from_node, door, to_node) in &self.transitions {
+            let from_room = self.assignment[from_node];
+            let to_room = self.assignment[to_node];
+            // 遷移の回数を増やす
+            self.graph[from_room][door][to_room] += 1;
         }
 
         for from_room in 0..self.num_rooms {
@@ -156,8 +143,8 @@ impl SimulatedAnnealingSolver {
             }
         }
 
-        for (plan1, room1, plan2, room2) in &self.known_inequalities {
-            if self.assignment[*plan1][*room1] == self.assignment[*plan2][*room2] {
+        for &(node1, node2) in &self.known_inequalities {
+            if self.assignment[node1] == self.assignment[node2] {
                 total_cost += 1;
             }
         }
@@ -172,32 +159,73 @@ impl SimulatedAnnealingSolver {
         }
     }
 
-    /// 特定の観測点の部屋割り当てを変更した際のコスト差分を計算・適用する (修正版)
-    fn update_point(&mut self, plan_idx: usize, obs_idx: usize, new_room: usize) {
-        let old_room = self.assignment[plan_idx][obs_idx];
+    /// 遷移 from_room --door--> to_room を1つ消す
+    fn remove_edge(&mut self, from_room: usize, door: usize, to_room: usize) {
+        self.graph[from_room][door][to_room] -= 1;
+        if self.graph[from_room][door][to_room] == 0 {
+            // (from_room, door) のエッジがなくなった
+            self.filled_in_future[from_room] -= 1;
+            // kasikariの更新
+            let old_k_from_to = self.kasikari_count[from_room][to_room];
+            let old_k_to_from = self.kasikari_count[to_room][from_room];
+            self.kasikari_count[from_room][to_room] -= 1;
+            self.kasikari_count[to_room][from_room] += 1;
+            // kasikari変更によるfilled_in_futureの更新
+            self.filled_in_future[from_room] -=
+                self.kasikari_count[from_room][to_room].min(0) - old_k_from_to.min(0);
+            self.filled_in_future[to_room] -=
+                self.kasikari_count[to_room][from_room].min(0) - old_k_to_from.min(0);
+        }
+    }
+
+    /// 遷移 from_room --door--> to_room を1つ足す
+    fn add_edge(&mut self, from_room: usize, door: usize, to_room: usize) {
+        self.graph[from_room][door][to_room] += 1;
+        if self.graph[from_room][door][to_room] == 1 {
+            // (from_room, door) のエッジが新しくできた
+            self.filled_in_future[from_room] += 1;
+            // kasikariの更新
+            let old_k_from_to = self.kasikari_count[from_room][to_room];
+            let old_k_to_from = self.kasikari_count[to_room][from_room];
+            self.kasikari_count[from_room][to_room] += 1;
+            self.kasikari_count[to_room][from_room] -= 1;
+            // kasikari変更によるfilled_in_futureの更新
+            self.filled_in_future[from_room] -=
+                self.kasikari_count[from_room][to_room].min(0) - old_k_from_to.min(0);
+            self.filled_in_future[to_room] -=
+                self.kasikari_count[to_room][from_room].min(0) - old_k_to_from.min(0);
+        }
+    }
+
+    /// 木の頂点の部屋割り当てを変更した際のコスト差分を計算・適用する
+    fn update_point(&mut self, node: usize, new_room: usize) {
+        let old_room = self.assignment[node];
         if old_room == new_room {
             return;
         }
 
         // --- Step 1: 影響を受ける部屋を特定し、変更前のコストを減算 ---
-        let from_room_opt = if obs_idx > 0 {
-            Some(self.assignment[plan_idx][obs_idx - 1])
-        } else {
-            None
-        };
-        let to_room_opt = if obs_idx < self.assignment[plan_idx].len() - 1 {
-            Some(self.assignment[plan_idx][obs_idx + 1])
-        } else {
-            None
+        // 親からの辺と、子への辺 (どれもドアの移動のみ)
+        let in_edge = match self.trie.nodes()[node].parent {
+            Some((parent, Step::Door(door))) => Some((self.assignment[parent], door)),
+            _ => None,
         };
+        let out_edges: Vec<(usize, usize)> = self.trie.nodes()[node]
+            .children
+            .iter()
+            .filter_map(|&(step, child)| match step {
+                Step::Door(door) => Some((door, self.assignment[child])),
+                Step::Mark(_) => None,
+            })
+            .collect();
 
         let mut affected_rooms = HashSet::default();
         affected_rooms.insert(old_room);
         affected_rooms.insert(new_room);
-        if let Some(r) = from_room_opt {
+        if let Some((r, _)) = in_edge {
             affected_rooms.insert(r);
         }
-        if let Some(r) = to_room_opt {
+        for &(_, r) in &out_edges {
             affected_rooms.insert(r);
         }
 
@@ -207,89 +235,28 @@ impl SimulatedAnnealingSolver {
 
         // --- Step 2: 状態を更新 ---
 
-        // In-edge: (obs_idx - 1) -> obs_idx
-        if let Some(from_room) = from_room_opt {
-            let door = self.transitions[plan_idx][obs_idx - 1].1;
-
-            // 古い遷移を削除
-            self.graph[from_room][door][old_room] -= 1;
-            if self.graph[from_room][door][old_room] == 0 {
-                // (from_room, door) のエッジがなくなった
-                self.filled_in_future[from_room] -= 1;
-                // kasikariの更新
-                let old_k_from_old = self.kasikari_count[from_room][old_room];
-                let old_k_old_from = self.kasikari_count[old_room][from_room];
-                self.kasikari_count[from_room][old_room] -= 1;
-                self.kasikari_count[old_room][from_room] += 1;
-                // kasikari変更によるfilled_in_futureの更新
-                self.filled_in_future[from_room] -=
-                    self.kasikari_count[from_room][old_room].min(0) - old_k_from_old.min(0);
-                self.filled_in_future[old_room] -=
-                    self.kasikari_count[old_room][from_room].min(0) - old_k_old_from.min(0);
-            }
-
-            // 新しい遷移を追加
-            self.graph[from_room][door][new_room] += 1;
-            if self.graph[from_room][door][new_room] == 1 {
-                // (from_room, door) のエッジが新しくできた
-                self.filled_in_future[from_room] += 1;
-                // kasikariの更新
-                let old_k_from_new = self.kasikari_count[from_room][new_room];
-                let old_k_new_from = self.kasikari_count[new_room][from_room];
-                self.kasikari_count[from_room][new_room] += 1;
-                self.kasikari_count[new_room][from_room] -= 1;
-                // kasikari変更によるfilled_in_futureの更新
-                self.filled_in_future[from_room] -=
-                    self.kasikari_count[from_room][new_room].min(0) - old_k_from_new.min(0);
-                self.filled_in_future[new_room] -=
-                    self.kasikari_count[new_room][from_room].min(0) - old_k_new_from.min(0);
-            }
+        // In-edge: parent -> node
+        if let Some((from_room, door)) = in_edge {
+            self.remove_edge(from_room, door, old_room);
+            self.add_edge(from_room, door, new_room);
         }
 
-        // Out-edge: obs_idx -> (obs_idx + 1)
-        if let Some(to_room) = to_room_opt {
-            let door = self.transitions[plan_idx][obs_idx].1;
-
-            // 古い遷移を削除
-            self.graph[old_room][door][to_room] -= 1;
-            if self.graph[old_room][door][to_room] == 0 {
-                self.filled_in_future[old_room] -= 1;
-                let old_k_old_to = self.kasikari_count[old_room][to_room];
-                let old_k_to_old = self.kasikari_count[to_room][old_room];
-                self.kasikari_count[old_room][to_room] -= 1;
-                self.kasikari_count[to_room][old_room] += 1;
-                self.filled_in_future[old_room] -=
-                    self.kasikari_count[old_room][to_room].min(0) - old_k_old_to.min(0);
-                self.filled_in_future[to_room] -=
-                    self.kasikari_count[to_room][old_room].min(0) - old_k_to_old.min(0);
-            }
-
-            // 新しい遷移を追加
-            self.graph[new_room][door][to_room] += 1;
-            if self.graph[new_room][door][to_room] == 1 {
-                self.filled_in_future[new_room] += 1;
-                let old_k_new_to = self.kasikari_count[new_room][to_room];
-                let old_k_to_new = self.kasikari_count[to_room][new_room];
-                self.kasikari_count[new_room][to_room] += 1;
-                self.kasikari_count[to_room][new_room] -= 1;
-                self.filled_in_future[new_room] -=
-                    self.kasikari_count[new_room][to_room].min(0) - old_k_new_to.min(0);
-                self.filled_in_future[to_room] -=
-                    self.kasikari_count[to_room][new_room].min(0) - old_k_to_new.min(0);
-            }
+        // Out-edge: node -> child
+        for &(door, to_room) in &out_edges {
+            self.remove_edge(old_room, door, to_room);
+            self.add_edge(new_room, door, to_room);
         }
 
         // --- Step 3: 不等式制約と部屋割り当ての更新 ---
-        let ineq_idxs = &self.known_inequalities_by_obs_idx[plan_idx][obs_idx];
-        for &(neq_plan, neq_idx) in ineq_idxs {
-            if self.assignment[neq_plan][neq_idx] == old_room {
+        for &other in &self.known_inequalities_by_node[node] {
+            if self.assignment[other] == old_room {
                 self.cost -= 1;
             }
-            if self.assignment[neq_plan][neq_idx] == new_room {
+            if self.assignment[other] == new_room {
                 self.cost += 1;
             }
         }
-        self.assignment[plan_idx][obs_idx] = new_room;
+        self.assignment[node] = new_room;
 
         // --- Step 4: 変更後のコストを加算 ---
         for &room in &affected_rooms {
@@ -378,15 +345,13 @@ impl SimulatedAnnealingSolver {
         println!("Label candidates: {:?}", label_candidates_per_label);
         let mut rng = thread_rng();
 
-        // 初期化
-        for plan_idx in 0..self.assignment.len() {
-            for obs_idx in 0..self.assignment[plan_idx].len() {
-                let label = self.observed_labels[plan_idx][obs_idx];
-                self.assignment[plan_idx][obs_idx] = label_candidates_per_label[label]
-                    .choose(&mut rng)
-                    .unwrap()
-                    .to_owned();
-            }
+        // 初期化 (木の頂点ごとに1つ)
+        for node in 0..self.trie.len() {
+            let label = self.trie.nodes()[node].label;
+            self.assignment[node] = label_candidates_per_label[label]
+                .choose(&mut rng)
+                .unwrap()
+                .to_owned();
         }
         self.recalculate_cost();
 
@@ -405,31 +370,30 @@ impl SimulatedAnnealingSolver {
                 println!("[Thread {}] Found a solution with cost 0!", thread_id);
                 *stop_signal.lock().unwrap() = true;
                 self.print_results();
-                return Some(self.assignment.clone());
+                return Some(self.plan_assignments());
             }
             let original_cost: i32 = self.cost;
             if i % 100000 == 0 && i > 0 {
                 // 特定のラベルのノードを30%くらいリセットする
                 // println!("[Thread {}] Resetting all nodes", thread_id);
-                for plan_idx in 0..self.assignment.len() {
-                    for obs_idx in 0..self.assignment[plan_idx].len() {
-                        if rng.gen_bool(0.05) {
-                            let label = self.observed_labels[plan_idx][obs_idx];
-                            self.assignment[plan_idx][obs_idx] = label_candidates_per_label[label]
-                                .choose(&mut rng)
-                                .unwrap()
-                                .to_owned();
-                        }
+                // スタートは動かさない
+                for node in 1..self.trie.len() {
+                    if rng.gen_bool(0.05) {
+                        let label = self.trie.nodes()[node].label;
+                        self.assignment[node] = label_candidates_per_label[label]
+                            .choose(&mut rng)
+                            .unwrap()
+                            .to_owned();
                     }
                 }
                 self.recalculate_cost();
             } else {
-                let plan_idx_to_move = rng.gen_range(0..self.assignment.len());
-                let obs_idx_to_move = rng.gen_range(1..self.assignment[plan_idx_to_move].len());
-                let old_room = self.assignment[plan_idx_to_move][obs_idx_to_move];
+                // スタートは動かさない
+                let node = rng.gen_range(1..self.trie.len());
+                let old_room = self.assignment[node];
 
                 let new_room = loop {
-                    let label = self.observed_labels[plan_idx_to_move][obs_idx_to_move];
+                    let label = self.trie.nodes()[node].label;
                     break label_candidates_per_label[label]
                         .choose(&mut rng)
                         .unwrap()
@@ -440,7 +404,7 @@ impl SimulatedAnnealingSolver {
                 }
 
                 // 差分計算を使用（assignmentを更新する前に呼ぶ）
-                self.update_point(plan_idx_to_move, obs_idx_to_move, new_room);
+                self.update_point(node, new_room);
 
                 let new_cost = self.cost;
                 let cost_delta = new_cost - original_cost;
@@ -452,7 +416,7 @@ impl SimulatedAnnealingSolver {
                 {
                     // 遷移を承認
                 } else {
-                    self.update_point(plan_idx_to_move, obs_idx_to_move, old_room);
+                    self.update_point(node, old_room);
                     assert_eq!(self.cost, original_cost);
                 }
             }
@@ -479,19 +443,16 @@ impl SimulatedAnnealingSolver {
         let mut count_incoming_tuple_of_room_and_door: HashMap<usize, HashSet<(usize, usize)>> =
             HashMap::default();
 
-        for plan_idx in 0..self.assignment.len() {
-            for (from_idx, door) in &self.transitions[plan_idx] {
-                let to_idx = from_idx + 1;
-                let from_room = self.assignment[plan_idx][*from_idx];
-                let to_room = self.assignment[plan_idx][to_idx];
-                count_incoming_tuple_of_room_and_door
-                    .entry(to_room)
-                    .or_default()
-                    .insert((from_room, *door));
-            }
-            for (room_id, incoming) in &count_incoming_tuple_of_room_and_door {
-                println!("Room {}: incoming {:?}", room_id, incoming);
-            }
+        for &(from_node, door, to_node) in &self.transitions {
+            let from_room = self.assignment[from_node];
+            let to_room = self.assignment[to_node];
+            count_incoming_tuple_of_room_and_door
+                .entry(to_room)
+                .or_default()
+                .insert((from_room, door));
+        }
+        for (room_id, incoming) in &count_incoming_tuple_of_room_and_door {
+            println!("Room {}: incoming {:?}", room_id, incoming);
         }
 
         println!("[Thread {}] Finished without finding cost 0.", thread_id);
@@ -554,9 +515,9 @@ impl SimulatedAnnealingSolver {
 
     fn print_results(&self) {
         println!("\n--- Assignment Results ---");
-        for plan_idx in 0..self.assignment.len() {
+        for (plan_idx, plan_assignment) in self.plan_assignments().iter().enumerate() {
             print!("Plan {}: ", plan_idx);
-            for (obs_idx, room_id) in self.assignment[plan_idx].iter().enumerate() {
+            for room_id in plan_assignment {
                 print!("{},", room_id);
             }
             println!();
@@ -565,12 +526,8 @@ impl SimulatedAnnealingSolver {
         println!();
 
         let mut transition_table: HashMap<(usize, usize), usize> = HashMap::default();
-        for plan_idx in 0..self.assignment.len() {
-            for (from_idx, door) in self.transitions[plan_idx].iter() {
-                let from_room = self.assignment[plan_idx][*from_idx];
-                let to_room = self.assignment[plan_idx][*from_idx + 1];
-                transition_table.insert((from_room, *door), to_room);
-            }
+        for &(from_node, door, to_node) in &self.transitions {
+            transition_table.insert((self.assignment[from_node], door), self.assignment[to_node]);
         }
 
         let mut incoming_count: HashMap<usize, usize> = HashMap::default();
@@ -638,13 +595,10 @@ impl SimulatedAnnealingSolver {
     fn build_submission_map(&self) -> api::Map {
         // 1. (room, door) -> next_room のテーブルを構築
         let mut transition_table: HashMap<(usize, usize), usize> = HashMap::default();
-        for plan_idx in 0..self.assignment.len() {
-            for (from_idx, door) in &self.transitions[plan_idx] {
-                let to_idx = from_idx + 1;
-                let from_room = self.assignment[plan_idx][*from_idx];
-                let to_room = self.assignment[plan_idx][to_idx];
-                transition_table.insert((from_room, *door), to_room);
-            }
+        for &(from_node, door, to_node) in &self.transitions {
+            let from_room = self.assignment[from_node];
+            let to_room = self.assignment[to_node];
+            transition_table.insert((from_room, door), to_room);
         }
 
         // 2. 未確定の接続を補完する
@@ -655,7 +609,7 @@ impl SimulatedAnnealingSolver {
 
         // 4. 提出形式に変換
         let rooms: Vec<usize> = (0..self.num_rooms).map(|r| r % 4).collect();
-        let starting_room = self.assignment[0][0];
+        let starting_room = self.assignment[0];
 
         let mut connections = Vec::new();
         let mut processed_connections = HashSet::default();
@@ -693,80 +647,48 @@ impl SimulatedAnnealingSolver {
 }
 
 // シグネチャ： ある長さのsuffixに対して、resultsが少しでも異なるなら、異なる部屋がわりあたるべきだ.
-// returns 複数の不等式
+// returns 複数の不等式 (common::signature で接尾辞配列を使って求める)
 fn find_signatures_ineqs(
     plans: Vec<String>,
     results: &Vec<Vec<usize>>,
 ) -> Vec<(usize, usize, usize, usize)> {
-    let mut inequalities = HashSet::default();
-    for start_plan in 0..plans.len() {
-        for sig_len in 1..plans[start_plan].len() {
-            for sig_start in 0..plans[start_plan].len() - sig_len {
-                let sig = &plans[start_plan][sig_start..sig_start + sig_len];
-                // 他の場所で同じsubstringがあるか?
-                for other_plan in start_plan..plans.len() {
-                    for other_start in match start_plan == other_plan {
-                        true => sig_start + 1..plans[other_plan].len() - sig_len,
-                        false => 0..plans[other_plan].len() - sig_len,
-                    } {
-                        if results[start_plan][sig_start] != results[other_plan][other_start] {
-                            continue;
-                        }
-                        let other = &plans[other_plan][other_start..other_start + sig_len];
-                        if sig == other {
-                            // 同じsubstringが見つかった
-                            // それぞれに対応するresultsを比較して、最後の文字だけが異なるなら、不要しき
-                            if (0..sig_len).all(|i| {
-                                if i < sig_len - 1 {
-                                    results[start_plan][1 + sig_start + i]
-                                        == results[other_plan][1 + other_start + i]
-                                } else {
-                                    results[start_plan][1 + sig_start + i]
-                                        != results[other_plan][1 + other_start + i]
-                                }
-                            }) {
-                                inequalities.insert((
-                                    start_plan,
-                                    sig_start,
-                                    other_plan,
-                                    other_start,
-                                ));
-                            }
-                        }
-                    }
-                }
-            }
-        }
-    }
-    inequalities.into_iter().collect()
-}
-
-fn gen_random_string(alphabet: &str, length: usize, rng: &mut impl Rng) -> String {
-    (0..length)
-        .map(|_| {
-            let idx = rng.gen_range(0..alphabet.len());
-            alphabet.chars().nth(idx).unwrap()
-        })
-        .collect()
+    let plans: Vec<Plan> = plans.iter().map(|plan| plan.parse().unwrap()).collect();
+    signature_inequalities(&plans, results)
 }
 
 // マップを見てすべての
 
 pub mod aleph;
-pub mod api;
+use common::api;
+use common::budget::{BudgetTracker, Phase};
+use common::observation_trie::ObservationTrie;
+use common::plan::{Plan, Step};
+use common::plan_generator::{PlanGenerator, UniformRandom};
+use common::problem::Problem;
+use common::signature::signature_inequalities;
 
 fn main() {
-    let api_client = api::ApiClient::new();
+    let api_client = api::ApiClient::new().with_budget(BudgetTracker::default());
 
     loop {
-        let num_rooms = 12;
-        let bb = 12;
-        let select_response = api_client.select_problem("beth").unwrap();
+        let problem = Problem::find("beth").unwrap();
+        let num_rooms = problem.rooms_per_layer;
+        let select_response = api_client.select_problem(problem.name).unwrap();
         // println!("Select response: {:?}", select_response);
         // let plan = "115245025105023511443135433021153123153322105521015544420201443204300141113400500431553530455422430033525213005553024122010410544043441334352323452154243502121032520402442010442313113353451022133245300122031551315224".to_string();
-        let plan = gen_random_string("012345", num_rooms * bb, &mut thread_rng());
-        let plan2 = gen_random_string("012345", num_rooms * bb, &mut thread_rng());
+        let plan = UniformRandom
+            .generate(problem.num_rooms(), problem.max_door_steps(), &mut thread_rng())
+            .to_string();
+        let plan2 = UniformRandom
+            .generate(problem.num_rooms(), problem.max_door_steps(), &mut thread_rng())
+            .to_string();
         let plan3 = gen_new_plan(&plan, &mut thread_rng());
+        // plan, plan2 で基本構造を、印つきの plan3 で階層を決める
+        api_client.budget().unwrap().set_phases(&[
+            Phase::Identification,
+            Phase::Identification,
+            Phase::LayerResolution,
+        ]);
         let explore_response: api::ExploreResponse = api_client
             .explore(&vec![plan.clone(), plan2.clone(), plan3.clone()])
             .unwrap();
@@ -838,17 +760,15 @@ fn main() {
                 // println!("\n--- Found a valid graph structure! ---");
                 let mut rooms_map: HashMap<usize, Vec<usize>> = HashMap::default();
 
-                for plan_idx in 0..solver.assignment.len() {
-                    for (obs_idx, room_id) in solver.assignment[plan_idx].iter().enumerate() {
-                        rooms_map.entry(*room_id).or_default().push(obs_idx);
-                    }
+                for (node, room_id) in solver.assignment.iter().enumerate() {
+                    rooms_map.entry(*room_id).or_default().push(node);
                 }
                 println!("Number of rooms: {}", rooms_map.len());
-                for (room_id, obs_indices) in rooms_map.iter() {
-                    let label = solver.observed_labels[0][obs_indices[0]];
+                for (room_id, nodes) in rooms_map.iter() {
+                    let label = solver.trie.nodes()[nodes[0]].label;
                     println!(
-                        "Room {} (label {}): assigned observations {:?}",
-                        room_id, label, obs_indices
+                        "Room {} (label {}): assigned trie nodes {:?}",
+                        room_id, label, nodes
                     );
                 }
 
@@ -857,12 +777,21 @@ fn main() {
                 // 5. 解が見つかったら、提出用のMap形式に変換
                 let final_map = solver.build_submission_map();
 
+                // 観測と合わない地図は Go に渡さない。階層を決める前なので印のない plan だけで比べる
+                if let Err(e) =
+                    final_map.check_observations(&[&plan, &plan2], &explore_response.results[..2])
+                {
+                    println!("Map contradicts the observations: {}, continuing...", e);
+                    continue;
+                }
+
                 let res_plan3 = explore_response.results[2].clone();
                 match aleph::run_go_with_json(&plan3, &res_plan3, &final_map) {
                     Ok(res) => {
                         println!("Go program returned: {}", res);
                         if res {
                             println!("Go program returned true, exiting...");
+                            println!("{}", api_client.budget().unwrap().report());
                             break;
                         }
                     }
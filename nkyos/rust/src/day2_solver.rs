@@ -1,9 +1,13 @@
+use common::alignment::{Alignment, AlignmentError};
+use common::api::{ApiError, Map};
+use common::budget::Phase;
+use common::oracle::Oracle;
+use common::plan_generator::Strategy;
+
 use crate::{
-    _PROBLEMS,
-    client::ApiClient,
-    ganba_dfs,
+    _PROBLEMS, ganba_dfs,
     omori2::{self, omori2_sa::SimulatedAnnealingSolver},
-    utils::{Action, matrix_to_connections},
+    utils::{Action, matrix_to_connections, query_result_to_string},
 };
 
 pub struct Graph {
@@ -34,21 +38,33 @@ impl Graph {
     }
 }
 
-pub fn day2_solver() {
-    let client = ApiClient::new();
+/// `process_query_tour` は初めての部屋に印をつける歩き方を前提にしている
+pub const STRATEGIES: [Strategy; 1] = [Strategy::EulerTour];
 
+pub fn day2_solver(oracle: &impl Oracle, strategy: Strategy) {
     let problem = &_PROBLEMS[6];
 
     loop {
         let problem_name = problem.name;
-        let select_result = client.select(problem_name);
+        let select_result = oracle.select(problem_name);
         let N = problem.N;
         let N_layer = problem.layers;
 
+        oracle.set_phases(&[Phase::Identification]);
+        let identify_results = match oracle.explore(&[problem.query.to_string()]) {
+            Ok(response) => response.results,
+            Err(e) if e.is_transient() => {
+                println!("explore failed: {e}. Try again!");
+                continue;
+            }
+            Err(e) => panic!("explore failed: {e}"),
+        };
+        let identify_result = query_result_to_string(&identify_results[0]);
+
         let solver = omori2::omori2_sa::identify_omori2(
             N / N_layer,
             problem.query.to_string(),
-            problem.query.to_string(), // ERROR
+            identify_result,
         );
 
         if solver.is_none() {
@@ -59,9 +75,27 @@ pub fn day2_solver() {
         let solver = solver.unwrap();
 
         let graph = Graph::from_sasolver(&solver);
-        let result = build_query_tour(&graph);
-
-        let matrix = process_query_tour(&graph, &result);
+        // 仮のマップの全てのドアを1回ずつ通る。初めての部屋には印をつける
+        let hypothesis = solver.build_submission_map();
+        let tour = strategy
+            .build(Some(&hypothesis))
+            .unwrap_or_else(|e| panic!("{}", e));
+        let num_doors = 6 * hypothesis.rooms.len();
+        let result = Action::from_plan(&tour.generate(
+            hypothesis.rooms.len(),
+            num_doors,
+            &mut rand::thread_rng(),
+        ));
+
+        oracle.set_phases(&[Phase::LayerResolution]);
+        let matrix = match process_query_tour(oracle, &graph, &result) {
+            Ok(matrix) => matrix,
+            Err(e) if e.is_transient() => {
+                println!("explore failed: {e}. Try again!");
+                continue;
+            }
+            Err(e) => panic!("explore failed: {e}"),
+        };
 
         for row in matrix.iter() {
             println!("{row:?}");
@@ -75,36 +109,31 @@ pub fn day2_solver() {
         let answer = answer.unwrap();
         let all_labels = vec![graph.labels.clone(); 2].concat();
 
-        let guess_result = client.guess(all_labels, 0, answer);
+        let map = Map {
+            rooms: all_labels,
+            starting_room: 0,
+            connections: answer,
+        };
+        // 観測と合わない地図は提出しない
+        if let Err(e) = map.check_observations(&[problem.query], &identify_results) {
+            println!("Map contradicts the observations: {e}. Try again!");
+            continue;
+        }
+        let guess_result = oracle.guess(map);
         println!("guess_result: {guess_result:?}");
 
-        if guess_result.unwrap().correct {
-            println!("Congratulations! Your map was correct!");
-            break;
-        } else {
-            println!("Map was incorrect. Try again!");
+        match guess_result {
+            Ok(res) if res.correct => {
+                println!("Congratulations! Your map was correct!");
+                break;
+            }
+            Ok(_) => println!("Map was incorrect. Try again!"),
+            Err(e) if e.is_transient() => println!("guess failed: {e}. Try again!"),
+            Err(e) => panic!("guess failed: {e}"),
         }
     }
 }
 
-pub fn test_query_tour() {
-    let graph = Graph {
-        labels: vec![0, 1, 2, 3, 0, 1],
-        doors: vec![
-            vec![0, 0, 2, 4, 3, 5],
-            vec![1, 2, 1, 1, 4, 5],
-            vec![0, 1, 3, 4, 5, 3],
-            vec![0, 2, 4, 4, 2, 3],
-            vec![0, 1, 2, 3, 5, 3],
-            vec![0, 1, 2, 4, 5, 5],
-        ],
-    };
-    let result = build_query_tour(&graph);
-    let score = evaluate_tour(&graph, &result);
-
-    println!("score: {score}");
-}
-
 pub fn evaluate_tour(graph: &Graph, actions: &Vec<Action>) -> usize {
     let mut used_door = vec![vec![false; 6]; graph.doors.len()];
     let mut score = 0;
@@ -132,12 +161,16 @@ pub fn evaluate_tour(graph: &Graph, actions: &Vec<Action>) -> usize {
     score
 }
 
-pub fn process_query_tour(graph: &Graph, actions: &Vec<Action>) -> Vec<Vec<Option<usize>>> {
-    let client = ApiClient::new();
+pub fn process_query_tour(
+    oracle: &impl Oracle,
+    graph: &Graph,
+    actions: &Vec<Action>,
+) -> Result<Vec<Vec<Option<usize>>>, ApiError> {
     let query = Action::vec_to_str(actions);
-    let result = client.explore(&vec![query]).unwrap().results[0].clone();
+    let result = oracle.explore(&vec![query])?.results[0].clone();
 
-    let path = parse_query_result(graph, actions, &result);
+    let path = parse_query_result(graph, actions, &result)
+        .map_err(|error| ApiError::InconsistentResult { plan_idx: 0, error })?;
     let N = graph.doors.len();
 
     let mut result: Vec<Vec<Option<usize>>> = vec![vec![None; 6]; N * 2];
@@ -182,7 +215,7 @@ pub fn process_query_tour(graph: &Graph, actions: &Vec<Action>) -> Vec<Vec<Optio
         current_layer = next_layer;
     }
 
-    result
+    Ok(result)
 }
 
 #[derive(Debug, Clone)]
@@ -193,115 +226,26 @@ pub struct LayerPathResult {
 }
 
 /// 平面での部屋番号、部屋の扉、移動先のラベルの組合せにして手に入れる
+///
+/// 結果が plan と合わなければ `AlignmentError` を返す
 pub fn parse_query_result(
     graph: &Graph,
     actions: &Vec<Action>,
     result: &Vec<usize>,
-) -> Vec<LayerPathResult> {
+) -> Result<Vec<LayerPathResult>, AlignmentError> {
+    let plan = Action::to_plan(actions);
+    let alignment = Alignment::new(&plan, result)?;
     let mut res = vec![];
     let mut current_room = 0;
 
-    for i in 0..actions.len() {
-        match actions[i] {
-            Action::Door(door) => {
-                res.push(LayerPathResult {
-                    room_id_on_plane: current_room,
-                    door_id: door,
-                    target_label: result[i + 1],
-                });
-                current_room = graph.doors[current_room][door];
-            }
-            Action::Mark(mark) => {}
-        }
-    }
-
-    res
-}
-
-pub fn build_query_tour(graph: &Graph) -> Vec<Action> {
-    let tour = euler_tour(graph);
-    let mut result = vec![];
-
-    let mut visited_rooms = vec![false; graph.labels.len()];
-    let mut only_move = vec![];
-
-    for (room, door) in tour {
-        if !visited_rooms[room] {
-            result.push(Action::Mark(3 - graph.labels[room]));
-            visited_rooms[room] = true;
-        }
-        result.push(Action::Door(door));
-        only_move.push(Action::Door(door));
+    for transition in alignment.transitions() {
+        res.push(LayerPathResult {
+            room_id_on_plane: current_room,
+            door_id: transition.door,
+            target_label: alignment.label(transition.to),
+        });
+        current_room = graph.doors[current_room][transition.door];
     }
 
-    // for mv in only_move.iter() { result.push(mv.clone());
-    // }
-
-    result
-}
-
-/// オイラー閉路を求める。スタート地点からスタートして、全ての辺を巡って戻ってくる。
-pub fn euler_tour(graph: &Graph) -> Vec<(usize, usize)> {
-    let mut used_door = vec![vec![false; 6]; graph.doors.len()];
-
-    let mut tour = vec![];
-
-    dfs(graph, &mut used_door, 0, 0, &mut tour);
-
-    let mut prev_len = 0;
-    while prev_len < tour.len() {
-        prev_len = tour.len();
-        for (i, (room, door)) in tour.clone().iter().enumerate() {
-            let mut start_new_tour = false;
-            for d in 0..6 {
-                if !used_door[*room][d] {
-                    start_new_tour = true;
-                    break;
-                }
-            }
-
-            if start_new_tour {
-                let mut new_tour = vec![];
-                dfs(graph, &mut used_door, *room, *room, &mut new_tour);
-
-                tour.splice(i..i, new_tour);
-                break;
-            }
-        }
-    }
-    for row in used_door.iter() {
-        println!("{row:?}");
-    }
-
-    tour
-}
-
-/// current_room からスタートして戻ってくるパスを求める
-pub fn dfs(
-    graph: &Graph,
-    used_door: &mut Vec<Vec<bool>>,
-    start_room: usize,
-    current_room: usize,
-    tour: &mut Vec<(usize, usize)>,
-) {
-    if current_room == start_room && !tour.is_empty() {
-        return;
-    }
-
-    for door in 0..6 {
-        if used_door[current_room][door] {
-            continue;
-        }
-
-        used_door[current_room][door] = true;
-        tour.push((current_room, door));
-        dfs(
-            graph,
-            used_door,
-            start_room,
-            graph.doors[current_room][door],
-            tour,
-        );
-        break;
-    }
+    Ok(res)
 }
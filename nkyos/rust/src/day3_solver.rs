@@ -1,22 +1,28 @@
 use fxhash::{FxHashMap as HashMap, FxHashSet as HashSet};
 use reqwest::header::CONTENT_SECURITY_POLICY_REPORT_ONLY;
 
-use crate::{client::ApiClient, utils::Action, ProblemSetting, _PROBLEMS};
-use rand::Rng;
+use crate::{utils::Action, ProblemSetting, _PROBLEMS};
+use common::oracle::Oracle;
+use common::plan_generator::Strategy;
+use common::problem::Problem;
 
 const NUM_QUERY: usize = 1;
 
-pub fn day3_solver() {
-    let client = ApiClient::new();
+/// 階層を見分けるには印が要り、仮のマップはまだないので random-marks だけ
+pub const STRATEGIES: [Strategy; 1] = [Strategy::RandomMarks];
+
+pub fn day3_solver(oracle: &impl Oracle, strategy: Strategy) {
     let problem = &_PROBLEMS[1];
     let problem_name = problem.name;
-    let select_result = client.select(problem_name);
+    let select_result = oracle.select(problem_name);
 
-    let N = problem.N / problem.layers;
     let N_layer = problem.layers;
 
-    let queries = vec![create_random_query(18 * N); NUM_QUERY];
-    let query_results = get_query_results(queries);
+    let max_door_steps = Problem::find(problem_name).unwrap().max_door_steps();
+    let generator = strategy.build(None).unwrap_or_else(|e| panic!("{}", e));
+    let query = generator.generate(problem.N, max_door_steps, &mut rand::thread_rng());
+    let queries = vec![Action::from_plan(&query); NUM_QUERY];
+    let query_results = get_query_results(oracle, queries);
     let state = solve(problem, query_results);
 }
 
@@ -480,32 +486,17 @@ impl State {
     }
 }
 
-/// N回ドアを開けるランダムなクエリを生成する
-fn create_random_query(N: usize) -> Vec<Action> {
-    let mut rng = rand::thread_rng();
-    let mut query = vec![];
-    for _ in 0..N {
-        let label = rng.gen_range(0..4);
-        query.push(Action::Mark(label));
-        let door = rng.gen_range(0..6);
-        query.push(Action::Door(door));
-    }
-
-    query
-}
-
 pub struct QueryResult {
     pub query: Vec<Action>,
     pub result: Vec<usize>,
 }
 
-fn get_query_results(queries: Vec<Vec<Action>>) -> Vec<QueryResult> {
-    let client = ApiClient::new();
+fn get_query_results(oracle: &impl Oracle, queries: Vec<Vec<Action>>) -> Vec<QueryResult> {
     let queries_str = queries
         .iter()
         .map(|query| Action::vec_to_str(query))
         .collect::<Vec<String>>();
-    let query_results = client.explore(&queries_str).unwrap().results;
+    let query_results = oracle.explore(&queries_str).unwrap().results;
     let query_results = query_results
         .iter()
         .enumerate()
@@ -3,10 +3,15 @@ use reqwest::header::CONTENT_SECURITY_POLICY_REPORT_ONLY;
 
 use crate::{
     _PROBLEMS, ProblemSetting,
-    client::ApiClient,
     omori2::{self, omori2_sa::SimulatedAnnealingSolver},
     utils::{Action, query_result_to_string},
 };
+use common::alignment::{AlignedStep, Transition, aligned_steps, transitions};
+use common::observations::Observations;
+use common::oracle::Oracle;
+use common::plan::{Plan, Step};
+use common::plan_generator::{PlanGenerator, Strategy, UniformRandom};
+use common::signature::signature_inequalities;
 use rand::Rng;
 
 use crate::omori2::{
@@ -27,20 +32,23 @@ pub struct Graph {
     pub doors: Vec<Vec<usize>>,
 }
 
-pub fn day3_solver_2() {
-    let client = ApiClient::new();
+/// 階層を見分けるには印が要り、仮のマップはまだないので random-marks だけ
+pub const STRATEGIES: [Strategy; 1] = [Strategy::RandomMarks];
+
+pub fn day3_solver_2(oracle: &impl Oracle, strategy: Strategy) {
     let problem = &_PROBLEMS[1];
     let problem_name = problem.name;
-    let select_result = client.select(problem_name);
+    let select_result = oracle.select(problem_name);
 
     let N = problem.N / problem.layers;
     let N_layer = problem.layers;
 
+    let generator = strategy.build(None).unwrap_or_else(|e| panic!("{}", e));
     let queries = vec![
         Action::from_string(&problem.query.to_string()),
-        create_random_query(6 * N * N_layer),
+        Action::from_plan(&generator.generate(problem.N, 6 * N * N_layer, &mut rand::thread_rng())),
     ];
-    let query_results = get_query_results(queries);
+    let query_results = get_query_results(oracle, queries);
 
     let identify_query = query_results[0].query.clone();
     let identify_result = query_results[0].result.clone();
@@ -102,32 +110,17 @@ pub struct RoomDoor(Room, usize);
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct PlaneRoomDoor(PlaneRoom, usize);
 
-/// N回ドアを開けるランダムなクエリを生成する
-fn create_random_query(N: usize) -> Vec<Action> {
-    let mut rng = rand::thread_rng();
-    let mut query = vec![];
-    for _ in 0..N {
-        let label = rng.gen_range(0..4);
-        query.push(Action::Mark(label));
-        let door = rng.gen_range(0..6);
-        query.push(Action::Door(door));
-    }
-
-    query
-}
-
 pub struct QueryResult {
     pub query: Vec<Action>,
     pub result: Vec<usize>,
 }
 
-fn get_query_results(queries: Vec<Vec<Action>>) -> Vec<QueryResult> {
-    let client = ApiClient::new();
+fn get_query_results(oracle: &impl Oracle, queries: Vec<Vec<Action>>) -> Vec<QueryResult> {
     let queries_str = queries
         .iter()
         .map(|query| Action::vec_to_str(query))
         .collect::<Vec<String>>();
-    let query_results = client.explore(&queries_str).unwrap().results;
+    let query_results = oracle.explore(&queries_str).unwrap().results;
     let query_results = query_results
         .iter()
         .enumerate()
@@ -194,9 +187,9 @@ const MAX_DOORS: usize = 6;
 pub struct LayerSimulatedAnnealingSolver {
     /// 問題定義
     pub observed_labels: Vec<Vec<usize>>, // observed_labels[query_id][observation_idx] = label
-    pub transitions: Vec<Vec<(usize, usize)>>, // transitions[query_id][step] = (from_observation_idx, door)
-    // Mark の場合は door が 6
-    pub num_rooms: usize, // 現在の部屋数
+    pub steps: Vec<Vec<AlignedStep>>, // steps[query_id][step] = plan の1歩とその前後の観測
+    pub transitions: Vec<Vec<Transition>>, // transitions[query_id] = ドアの移動だけ
+    pub num_rooms: usize,             // 現在の部屋数
 
     /// 探索中の状態
     pub assignment: Vec<Vec<usize>>, // assignment[query_id][observation_idx] = (big_room_id)
@@ -213,6 +206,19 @@ pub struct LayerSimulatedAnnealingSolver {
 }
 
 impl LayerSimulatedAnnealingSolver {
+    pub fn from_observations(
+        observations: &Observations,
+        plane_rooms: Vec<Vec<usize>>,
+        num_rooms: usize,
+    ) -> Self {
+        let plan = observations
+            .plans
+            .iter()
+            .map(|p| Action::parse_plan(p))
+            .collect();
+        Self::new(plan, observations.results.clone(), plane_rooms, num_rooms)
+    }
+
     pub fn new(
         plan: Vec<Vec<Action>>,
         results: Vec<Vec<usize>>,
@@ -223,18 +229,17 @@ impl LayerSimulatedAnnealingSolver {
 
         let known_inequalities = find_signatures_ineqs(plan.clone(), &observed_labels);
 
-        let mut transitions = plan
+        let plans: Vec<Plan> = plan
             .iter()
-            .map(|single_plan| {
-                let mut single_t = Vec::new();
-                for i in 0..single_plan.len() {
-                    match single_plan[i] {
-                        Action::Door(d) => single_t.push((i, d)),
-                        Action::Mark(m) => single_t.push((i, 6)),
-                    }
-                }
-                single_t
-            })
+            .map(|single_plan| Action::to_plan(single_plan))
+            .collect();
+        let steps: Vec<Vec<AlignedStep>> = plans
+            .iter()
+            .map(|single_plan| aligned_steps(single_plan).collect())
+            .collect();
+        let transitions = plans
+            .iter()
+            .map(|single_plan| transitions(single_plan).collect())
             .collect();
 
         let mut assignment = vec![];
@@ -260,6 +265,7 @@ impl LayerSimulatedAnnealingSolver {
 
         let mut solver = Self {
             observed_labels,
+            steps,
             transitions,
             assignment,
             num_rooms,
@@ -293,11 +299,19 @@ impl LayerSimulatedAnnealingSolver {
 
         for i in 0..self.transitions.len() {
             let transitions_single = &self.transitions[i];
-            for (from_idx, door) in transitions_single {
-                let from_room = self.assignment[i][*from_idx];
-                let to_room = self.assignment[i][*from_idx + 1];
+            for t in transitions_single {
+                let from_room = self.assignment[i][t.from];
+                let to_room = self.assignment[i][t.to];
                 // 遷移の回数を増やす
-                self.graph[from_room][*door][to_room] += 1;
+                self.graph[from_room][t.door][to_room] += 1;
+            }
+            // `[d]` の前後は同じ部屋のはず
+            for aligned in &self.steps[i] {
+                if matches!(aligned.step, Step::Mark(_))
+                    && self.assignment[i][aligned.before] != self.assignment[i][aligned.after]
+                {
+                    total_cost += 1;
+                }
             }
         }
 
@@ -332,6 +346,18 @@ impl LayerSimulatedAnnealingSolver {
 
         self.cost = total_cost;
     }
+    /// plan_idx 番目の plan の step 歩目がドアならそのドア
+    fn door_of(&self, plan_idx: usize, step: usize) -> Option<usize> {
+        match self.steps[plan_idx].get(step)?.step {
+            Step::Door(door) => Some(door),
+            Step::Mark(_) => None,
+        }
+    }
+
+    fn is_mark(&self, plan_idx: usize, step: usize) -> bool {
+        matches!(self.steps[plan_idx][step].step, Step::Mark(_))
+    }
+
     fn calculate_penalty(&self, room_id: usize) -> i32 {
         if self.filled_in_future[room_id] > 6 {
             self.filled_in_future[room_id] - 6
@@ -374,12 +400,24 @@ impl LayerSimulatedAnnealingSolver {
             self.cost -= self.calculate_penalty(room);
         }
 
-        // --- Step 2: 状態を更新 ---
+        // `[d]` の前後は同じ部屋のはず
+        let mark_neighbors = [
+            from_room_opt.filter(|_| self.is_mark(plan_idx, obs_idx - 1)),
+            to_room_opt.filter(|_| self.is_mark(plan_idx, obs_idx)),
+        ];
+        for neighbor in mark_neighbors.into_iter().flatten() {
+            self.cost += i32::from(neighbor != new_room) - i32::from(neighbor != old_room);
+        }
 
-        // In-edge: (obs_idx - 1) -> obs_idx
-        if let Some(from_room) = from_room_opt {
-            let door = self.transitions[plan_idx][obs_idx - 1].1;
+        // --- Step 2: 状態を更新 ---
 
+        // In-edge: (obs_idx - 1) -> obs_idx (ドアの場合だけ)
+        if let (Some(from_room), Some(door)) = (
+            from_room_opt,
+            obs_idx
+                .checked_sub(1)
+                .and_then(|i| self.door_of(plan_idx, i)),
+        ) {
             // 古い遷移を削除
             self.graph[from_room][door][old_room] -= 1;
             if self.graph[from_room][door][old_room] == 0 {
@@ -415,10 +453,8 @@ impl LayerSimulatedAnnealingSolver {
             }
         }
 
-        // Out-edge: obs_idx -> (obs_idx + 1)
-        if let Some(to_room) = to_room_opt {
-            let door = self.transitions[plan_idx][obs_idx].1;
-
+        // Out-edge: obs_idx -> (obs_idx + 1) (ドアの場合だけ)
+        if let (Some(to_room), Some(door)) = (to_room_opt, self.door_of(plan_idx, obs_idx)) {
             // 古い遷移を削除
             self.graph[old_room][door][to_room] -= 1;
             if self.graph[old_room][door][to_room] == 0 {
@@ -656,14 +692,13 @@ impl LayerSimulatedAnnealingSolver {
             HashMap::default();
 
         for plan_idx in 0..self.assignment.len() {
-            for (from_idx, door) in &self.transitions[plan_idx] {
-                let to_idx = from_idx + 1;
-                let from_room = self.assignment[plan_idx][*from_idx];
-                let to_room = self.assignment[plan_idx][to_idx];
+            for t in &self.transitions[plan_idx] {
+                let from_room = self.assignment[plan_idx][t.from];
+                let to_room = self.assignment[plan_idx][t.to];
                 count_incoming_tuple_of_room_and_door
                     .entry(to_room)
                     .or_default()
-                    .insert((from_room, *door));
+                    .insert((from_room, t.door));
             }
             for (room_id, incoming) in &count_incoming_tuple_of_room_and_door {
                 println!("Room {}: incoming {:?}", room_id, incoming);
@@ -742,10 +777,10 @@ impl LayerSimulatedAnnealingSolver {
 
         let mut transition_table: HashMap<(usize, usize), usize> = HashMap::default();
         for plan_idx in 0..self.assignment.len() {
-            for (from_idx, door) in self.transitions[plan_idx].iter() {
-                let from_room = self.assignment[plan_idx][*from_idx];
-                let to_room = self.assignment[plan_idx][*from_idx + 1];
-                transition_table.insert((from_room, *door), to_room);
+            for t in self.transitions[plan_idx].iter() {
+                let from_room = self.assignment[plan_idx][t.from];
+                let to_room = self.assignment[plan_idx][t.to];
+                transition_table.insert((from_room, t.door), to_room);
             }
         }
 
@@ -816,10 +851,10 @@ impl LayerSimulatedAnnealingSolver {
         // 1. (room, door) -> next_room のテーブルを構築
         let mut transition_table: HashMap<(usize, usize), usize> = HashMap::default();
         for plan_idx in 0..self.assignment.len() {
-            for (from_idx, door) in self.transitions[plan_idx].iter() {
-                let from_room = self.assignment[plan_idx][*from_idx];
-                let to_room = self.assignment[plan_idx][*from_idx + 1];
-                transition_table.insert((from_room, *door), to_room);
+            for t in self.transitions[plan_idx].iter() {
+                let from_room = self.assignment[plan_idx][t.from];
+                let to_room = self.assignment[plan_idx][t.to];
+                transition_table.insert((from_room, t.door), to_room);
             }
         }
 
@@ -869,66 +904,19 @@ impl LayerSimulatedAnnealingSolver {
 }
 
 // シグネチャ： ある長さのsuffixに対して、resultsが少しでも異なるなら、異なる部屋がわりあたるべきだ.
-// returns 複数の不等式
+// returns 複数の不等式 (common::signature で接尾辞配列を使って求める)
 fn find_signatures_ineqs(
     plans: Vec<Vec<Action>>,
     results: &Vec<Vec<usize>>,
 ) -> Vec<(usize, usize, usize, usize)> {
-    let mut inequalities = HashSet::default();
-    for start_plan in 0..plans.len() {
-        for sig_len in 1..plans[start_plan].len() {
-            for sig_start in 0..plans[start_plan].len() - sig_len {
-                let sig = Action::vec_to_str(&plans[start_plan][sig_start..sig_start + sig_len]);
-                // 他の場所で同じsubstringがあるか?
-                for other_plan in start_plan..plans.len() {
-                    for other_start in match start_plan == other_plan {
-                        true => sig_start + 1..plans[other_plan].len() - sig_len,
-                        false => 0..plans[other_plan].len() - sig_len,
-                    } {
-                        if results[start_plan][sig_start] != results[other_plan][other_start] {
-                            continue;
-                        }
-                        let other = Action::vec_to_str(
-                            &plans[other_plan][other_start..other_start + sig_len],
-                        );
-                        if sig == other {
-                            // 同じsubstringが見つかった
-                            // それぞれに対応するresultsを比較して、最後の文字だけが異なるなら、不要しき
-                            if (0..sig_len).all(|i| {
-                                if i < sig_len - 1 {
-                                    results[start_plan][1 + sig_start + i]
-                                        == results[other_plan][1 + other_start + i]
-                                } else {
-                                    results[start_plan][1 + sig_start + i]
-                                        != results[other_plan][1 + other_start + i]
-                                }
-                            }) {
-                                inequalities.insert((
-                                    start_plan,
-                                    sig_start,
-                                    other_plan,
-                                    other_start,
-                                ));
-                            }
-                        }
-                    }
-                }
-            }
-        }
-    }
-    inequalities.into_iter().collect()
-}
-
-fn gen_random_string(alphabet: &str, length: usize, rng: &mut impl Rng) -> String {
-    (0..length)
-        .map(|_| {
-            let idx = rng.gen_range(0..alphabet.len());
-            alphabet.chars().nth(idx).unwrap()
-        })
-        .collect()
+    let plans: Vec<Plan> = plans.iter().map(|plan| Action::to_plan(plan)).collect();
+    signature_inequalities(&plans, results)
 }
 
 fn main() {
+    // 複数planを扱う焼きなましはこちら
+    use crate::omori2::multiple_plan_random_write::SimulatedAnnealingSolver;
+
     let api_client = api::ApiClient::new();
 
     loop {
@@ -940,8 +928,12 @@ fn main() {
         //let plan = "115245025105023511443135433021153123153322105521015544420201443204300141113400500431553530455422430033525213005553024122010410544043441334352323452154243502121032520402442010442313113353451022133245300122031551315224".to_string();
         //let plan = gen_random_string("012345", num_rooms * bb, &mut thread_rng());
         let plans = vec![
-            gen_random_string("012345", num_rooms * bb, &mut thread_rng()),
-            gen_random_string("012345", num_rooms * bb, &mut thread_rng()),
+            UniformRandom
+                .generate(num_rooms, num_rooms * bb, &mut thread_rng())
+                .to_string(),
+            UniformRandom
+                .generate(num_rooms, num_rooms * bb, &mut thread_rng())
+                .to_string(),
         ];
         let explore_response: api::ExploreResponse = api_client.explore(&plans).unwrap();
         println!("Explore response: {:?}", explore_response);
@@ -1006,17 +998,15 @@ fn main() {
                 println!("\n--- Found a valid graph structure! ---");
                 let mut rooms_map: HashMap<usize, Vec<usize>> = HashMap::default();
 
-                for plan_idx in 0..solver.assignment.len() {
-                    for (obs_idx, room_id) in solver.assignment[plan_idx].iter().enumerate() {
-                        rooms_map.entry(*room_id).or_default().push(obs_idx);
-                    }
+                for (node, room_id) in solver.assignment.iter().enumerate() {
+                    rooms_map.entry(*room_id).or_default().push(node);
                 }
                 println!("Number of rooms: {}", rooms_map.len());
-                for (room_id, obs_indices) in rooms_map.iter() {
-                    let label = solver.observed_labels[0][obs_indices[0]];
+                for (room_id, nodes) in rooms_map.iter() {
+                    let label = solver.trie.nodes()[nodes[0]].label;
                     println!(
-                        "Room {} (label {}): assigned observations {:?}",
-                        room_id, label, obs_indices
+                        "Room {} (label {}): assigned trie nodes {:?}",
+                        room_id, label, nodes
                     );
                 }
 
@@ -1025,6 +1015,12 @@ fn main() {
                 // 5. 解が見つかったら、提出用のMap形式に変換
                 let final_map = solver.build_submission_map();
 
+                // 観測と合わない地図は提出しない
+                if let Err(e) = final_map.check_observations(&plans, &explore_response.results) {
+                    println!("Map contradicts the observations: {}. Try again!", e);
+                    continue;
+                }
+
                 // 6. 地図を提出
                 println!("Submitting the guess...");
                 let guess_res = api_client.guess(final_map).unwrap();
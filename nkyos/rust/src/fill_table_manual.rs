@@ -1,17 +1,21 @@
 use std::collections::HashMap;
 
-use crate::{_PROBLEMS, client::ApiClient, utils::create_random_route};
+use common::oracle::Oracle;
+use common::plan_generator::{PlanGenerator, UniformRandom};
 
-pub fn fill_table_manual() {
-    let client = ApiClient::new();
+use crate::_PROBLEMS;
+
+pub fn fill_table_manual(oracle: &impl Oracle) {
     let problem = &_PROBLEMS[1];
     let problem_name = problem.name;
-    let select_result = client.select(problem_name);
+    let select_result = oracle.select(problem_name);
     let v = problem.N;
 
-    let random_route = create_random_route(v);
+    let random_route = UniformRandom
+        .generate(v, v, &mut rand::thread_rng())
+        .to_string();
 
-    let random_result = client.explore(&vec![random_route.clone()]).unwrap().results[0].clone();
+    let random_result = oracle.explore(&vec![random_route.clone()]).unwrap().results[0].clone();
 
     let mut table = vec![vec![HashMap::<usize, usize>::new(); 6]; v];
 
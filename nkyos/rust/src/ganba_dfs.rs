@@ -2,11 +2,11 @@ use std::collections::{HashMap, HashSet};
 
 use serde::Serialize;
 
-use crate::{
-    _PROBLEMS,
-    client::{ApiClient, Connection, Door, Map},
-    utils::get_ith_label,
-};
+use common::api::{Connection, Map, RoomAndDoor};
+use common::oracle::Oracle;
+use common::refinement::Refinement;
+
+use crate::{_PROBLEMS, utils::get_ith_label};
 
 const MAX_SIGNATURE_LEN: usize = 12;
 
@@ -38,18 +38,21 @@ pub struct Problem {
 pub struct State {
     pub room_history: Vec<usize>,
     pub rooms: Vec<Room>,
+
+    /// room_distinct[room][class] = その部屋の観測に、class と違う部屋と分かっているものがある
+    #[serde(skip)]
+    pub room_distinct: Vec<Vec<bool>>,
 }
 
-pub fn ganba_dfs_solver() {
-    let client = ApiClient::new();
+pub fn ganba_dfs_solver(oracle: &impl Oracle) {
     let problem = &_PROBLEMS[2];
     let problem_name = problem.name;
-    let _select_result = client.select(problem_name);
+    let _select_result = oracle.select(problem_name);
     let v = problem.N;
     let query = problem.query;
 
-    let result = client.explore(&vec![query.to_string()]).unwrap().results[0].clone();
-    let result = result
+    let results = oracle.explore(&vec![query.to_string()]).unwrap().results;
+    let result = results[0]
         .iter()
         .map(|x| x.to_string())
         .collect::<Vec<String>>()
@@ -80,7 +83,12 @@ pub fn ganba_dfs_solver() {
     }
 
     let map = state.to_map();
-    let guess_result = client.guess(map.rooms, map.starting_room, map.connections);
+    // 観測と合わない地図は提出しない
+    if let Err(e) = map.check_observations(&[query], &results) {
+        println!("Map contradicts the observations: {e}");
+        return;
+    }
+    let guess_result = oracle.guess(map);
     println!("guess_result: {guess_result:?}");
 }
 
@@ -88,11 +96,33 @@ pub fn solve(problem: &Problem) -> State {
     let rooms = vec![get_room_info(&problem, 0)];
     let room_history = vec![0];
 
+    // 同じ部屋・違う部屋と分かっている観測を先に求めておき、dfs の枝刈りに使う
+    let results = problem
+        .result
+        .chars()
+        .map(|c| c.to_digit(10).unwrap() as usize)
+        .collect();
+    // 矛盾していたら枝刈りなしで探す
+    let refinement = match Refinement::new(&[problem.query.parse().unwrap()], &[results], problem.N)
+    {
+        Ok(refinement) => Some(refinement),
+        Err(e) => {
+            println!("Refinement failed: {}", e);
+            None
+        }
+    };
+    let room_distinct = refinement
+        .iter()
+        .map(|r| r.distinct_classes(r.class_of(0)).to_vec())
+        .collect();
+
     let state = dfs(
         &problem,
+        refinement.as_ref(),
         State {
             rooms,
             room_history,
+            room_distinct,
         },
         0,
     )
@@ -203,7 +233,12 @@ pub fn fill_dfs(
     None
 }
 
-pub fn dfs(problem: &Problem, state: State, idx: usize) -> Option<State> {
+pub fn dfs(
+    problem: &Problem,
+    refinement: Option<&Refinement>,
+    state: State,
+    idx: usize,
+) -> Option<State> {
     println!("idx: {idx}");
     if idx + 1 == problem.query.len() {
         if state.rooms.len() == problem.N {
@@ -218,8 +253,22 @@ pub fn dfs(problem: &Problem, state: State, idx: usize) -> Option<State> {
 
     let door_id = get_ith_label(&problem.query, idx);
 
+    // 前の観測と同じ部屋と分かっていれば、その部屋しか試さない
+    let class = refinement.map(|r| r.class_of(idx + 1));
+    let forced_room_id = refinement.and_then(|r| {
+        let same_room = r.members(class.unwrap())[0];
+        (same_room <= idx).then(|| state.room_history[same_room])
+    });
+
     // 既存の部屋とまとめられるかチェック
     for (room_id, room) in state.rooms.iter().enumerate() {
+        if forced_room_id.is_some_and(|forced| forced != room_id) {
+            continue;
+        }
+        // 違う部屋と分かっている観測がすでにいる部屋は試さない
+        if class.is_some_and(|class| state.room_distinct[room_id][class]) {
+            continue;
+        }
         let merged_room = room.clone().merge(&next_room, problem);
         if merged_room.is_none() {
             continue;
@@ -243,14 +292,14 @@ pub fn dfs(problem: &Problem, state: State, idx: usize) -> Option<State> {
             continue;
         }
 
-        new_state.room_history.push(room_id);
+        new_state.push_observation(refinement, idx + 1, room_id);
 
-        if let Some(result) = dfs(problem, new_state, idx + 1) {
+        if let Some(result) = dfs(problem, refinement, new_state, idx + 1) {
             return Some(result);
         }
     }
 
-    if state.rooms.len() == problem.N {
+    if state.rooms.len() == problem.N || forced_room_id.is_some() {
         return None;
     }
 
@@ -261,7 +310,7 @@ pub fn dfs(problem: &Problem, state: State, idx: usize) -> Option<State> {
         .from_room_doors
         .insert((current_room_id, door_id));
     new_state.rooms.push(next_room_with_from);
-    new_state.room_history.push(new_state.rooms.len() - 1);
+    new_state.push_observation(refinement, idx + 1, new_state.rooms.len() - 1);
 
     let mut current_room = state.rooms[current_room_id].clone();
     current_room
@@ -269,7 +318,7 @@ pub fn dfs(problem: &Problem, state: State, idx: usize) -> Option<State> {
         .insert(door_id, new_state.rooms.len() - 1);
     new_state.rooms[current_room_id] = current_room;
 
-    if let Some(result) = dfs(problem, new_state, idx + 1) {
+    if let Some(result) = dfs(problem, refinement, new_state, idx + 1) {
         return Some(result);
     }
 
@@ -436,6 +485,22 @@ impl Answer {
 }
 
 impl State {
+    /// 観測 `obs` を部屋 `room_id` にする
+    fn push_observation(&mut self, refinement: Option<&Refinement>, obs: usize, room_id: usize) {
+        self.room_history.push(room_id);
+        let Some(refinement) = refinement else {
+            return;
+        };
+        let distinct = refinement.distinct_classes(refinement.class_of(obs));
+        if room_id == self.room_distinct.len() {
+            self.room_distinct.push(distinct.to_vec());
+            return;
+        }
+        for (d, &other) in self.room_distinct[room_id].iter_mut().zip(distinct) {
+            *d |= other;
+        }
+    }
+
     fn to_map(&self) -> Map {
         let rooms = self.rooms.iter().map(|room| room.label).collect::<Vec<_>>();
         let starting_room = self.room_history[0];
@@ -466,11 +531,11 @@ impl State {
             for j in i..self.rooms.len() {
                 for k in 0..doors[i][j].len() {
                     result.push(Connection {
-                        from: Door {
+                        from: RoomAndDoor {
                             room: i,
                             door: doors[i][j][k],
                         },
-                        to: Door {
+                        to: RoomAndDoor {
                             room: j,
                             door: doors[j][i][k],
                         },
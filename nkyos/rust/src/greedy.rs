@@ -1,9 +1,10 @@
 use std::collections::HashMap;
 
-use crate::{
-    client::ApiClient,
-    utils::{Action, all_doors, query_result_to_string},
-};
+use common::oracle::Oracle;
+use common::plan::Plan;
+use common::plan_builder::PlanBuilder;
+
+use crate::utils::{Action, all_doors, query_result_to_string};
 
 /// 適当な signature を使い、まずは同定を行う
 /// その後、その signature を使ってどの部屋に行くか決める
@@ -32,36 +33,40 @@ pub fn identify_one_step(
     n: usize,
     signature_query: &str,
     identities: &mut HashMap<String, Identity>,
-    client: &ApiClient,
+    oracle: &impl Oracle,
 ) {
     let all_doors = all_doors();
+    let signature_plan: Plan = signature_query.parse().unwrap();
 
     let mut queries = vec![];
-    let mut routes = vec![];
+    let mut composed_plans = vec![];
     for identity in identities.values() {
         let route = identity.route.clone();
         for action in &all_doors {
             let new_route = [route.clone(), vec![action.clone()]].concat();
-            let new_route_str = Action::vec_to_str(&new_route);
-
-            let query = new_route_str + signature_query + signature_query;
-            queries.push(query);
-            routes.push(new_route);
+            let composed = PlanBuilder::new()
+                .route("route", &Action::to_plan(&new_route))
+                .probe("first", &signature_plan)
+                .probe("second", &signature_plan)
+                .build();
+            queries.push(composed.plan.to_string());
+            composed_plans.push((new_route, composed));
         }
     }
 
-    let results = client.explore(&queries).unwrap().results;
-    for (i, result) in results.iter().enumerate() {
-        let result_str = query_result_to_string(&result);
-        let route = routes[i].clone();
-        let signature = result_str[route.len()..].to_string();
+    let results = oracle.explore(&queries).unwrap().results;
+    for (result, (route, composed)) in results.iter().zip(composed_plans) {
+        let first = composed.results("first", result).unwrap();
+        // second の最初は first の最後と同じ部屋
+        let second = &composed.results("second", result).unwrap()[1..];
+        let signature = query_result_to_string(&[first, second].concat());
 
         identities.insert(
             signature.clone(),
             Identity {
-                label: result[route.len()],
-                route: route.clone(),
-                signature: signature.clone(),
+                label: first[0],
+                route,
+                signature,
             },
         );
     }
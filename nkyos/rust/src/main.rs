@@ -1,4 +1,3 @@
-mod client;
 mod day2_solver;
 mod day3_solver;
 mod day3_solver_2;
@@ -10,7 +9,9 @@ mod signature_explore;
 mod utils;
 
 use anyhow::Result;
-use client::{ApiClient, Connection, Door};
+use common::api::ApiClient;
+use common::budget::BudgetTracker;
+use common::plan_generator::Strategy;
 
 pub struct ProblemSetting {
     pub name: &'static str,
@@ -19,8 +20,32 @@ pub struct ProblemSetting {
     pub query: &'static str,
 }
 
+// --<name> <value> の value
+fn arg_value(name: &str) -> Option<String> {
+    std::env::args().skip_while(|arg| arg != name).nth(1)
+}
+
 fn main() {
-    day2_solver::day2_solver();
+    // --solver <day2|day3|day3-2> で解き方を、--plan <name> で plan の作り方を選ぶ
+    let solver = arg_value("--solver").unwrap_or("day2".to_string());
+    let supported: &[Strategy] = match solver.as_str() {
+        "day2" => &day2_solver::STRATEGIES,
+        "day3" => &day3_solver::STRATEGIES,
+        "day3-2" => &day3_solver_2::STRATEGIES,
+        _ => panic!("unknown solver: {} (supported: day2, day3, day3-2)", solver),
+    };
+    let strategy = arg_value("--plan").map_or(Ok(supported[0]), |name| {
+        Strategy::parse_supported(&name, supported)
+    });
+    let strategy = strategy.unwrap_or_else(|e| panic!("{} --plan: {}", solver, e));
+
+    let client = ApiClient::new().with_budget(BudgetTracker::default());
+    match solver.as_str() {
+        "day2" => day2_solver::day2_solver(&client, strategy),
+        "day3" => day3_solver::day3_solver(&client, strategy),
+        _ => day3_solver_2::day3_solver_2(&client, strategy),
+    }
+    println!("{}", client.budget().unwrap().report());
 }
 
 const _PROBLEMS: &[ProblemSetting] = &[
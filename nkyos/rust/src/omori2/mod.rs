@@ -1,3 +1,3 @@
-pub mod api;
+pub use common::api;
 pub mod multiple_plan_random_write;
 pub mod omori2_sa;
@@ -856,6 +856,12 @@ fn main() {
                 // 5. 解が見つかったら、提出用のMap形式に変換
                 let final_map = solver.build_submission_map();
 
+                // 観測と合わない地図は提出しない
+                if let Err(e) = final_map.check_observations(&plans, &explore_response.results) {
+                    println!("Map contradicts the observations: {}. Try again!", e);
+                    continue;
+                }
+
                 // 6. 地図を提出
                 println!("Submitting the guess...");
                 let guess_res = api_client.guess(final_map).unwrap();
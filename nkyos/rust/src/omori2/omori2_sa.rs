@@ -1,3 +1,6 @@
+use common::observations::Observations;
+use common::plan::Plan;
+use common::signature::signature_inequalities;
 use fxhash::FxHashMap as HashMap;
 use fxhash::FxHashSet as HashSet;
 use itertools::Itertools;
@@ -40,6 +43,20 @@ pub struct SimulatedAnnealingSolver {
 }
 
 impl SimulatedAnnealingSolver {
+    /// `observations` の `plan_idx` 番目 (`[d]` を含まない plan) から作る
+    pub fn from_observations(
+        observations: &Observations,
+        plan_idx: usize,
+        num_rooms: usize,
+    ) -> Self {
+        assert!(!observations.has_marks(plan_idx));
+        Self::new(
+            &observations.plans[plan_idx],
+            &observations.results_str(plan_idx),
+            num_rooms,
+        )
+    }
+
     pub fn new(plan_str: &str, results_str: &str, num_rooms: usize) -> Self {
         let plan: Vec<usize> = plan_str
             .chars()
@@ -670,47 +687,12 @@ impl SimulatedAnnealingSolver {
 }
 
 // シグネチャ： ある長さのsuffixに対して、resultsが少しでも異なるなら、異なる部屋がわりあたるべきだ.
-// returns 複数の不等式
+// returns 複数の不等式 (common::signature で接尾辞配列を使って求める)
 fn find_signatures_ineqs(plan: &str, results: &Vec<usize>) -> Vec<(usize, usize)> {
-    let mut inequalities = HashSet::default();
-    for sig_len in (1..plan.len()) {
-        for sig_start in (0..plan.len() - sig_len) {
-            let sig = &plan[sig_start..sig_start + sig_len];
-            // 他の場所で同じsubstringがあるか?
-            for other_start in (sig_start + 1..plan.len() - sig_len) {
-                if results[sig_start] != results[other_start] {
-                    continue;
-                }
-                let other = &plan[other_start..other_start + sig_len];
-                if sig == other {
-                    // 同じsubstringが見つかった
-                    // それぞれに対応するresultsを比較して、最後の文字だけが異なるなら、不要しき
-                    if (0..sig_len).all(|i| {
-                        if i < sig_len - 1 {
-                            results[1 + sig_start + i] == results[1 + other_start + i]
-                        } else {
-                            results[1 + sig_start + i] != results[1 + other_start + i]
-                        }
-                    }) {
-                        if sig_start < other_start {
-                            inequalities.insert((sig_start, other_start));
-                        } else {
-                            inequalities.insert((other_start, sig_start));
-                        }
-                    }
-                }
-            }
-        }
-    }
-    inequalities.into_iter().collect()
-}
-
-fn gen_random_string(alphabet: &str, length: usize, rng: &mut impl Rng) -> String {
-    (0..length)
-        .map(|_| {
-            let idx = rng.gen_range(0..alphabet.len());
-            alphabet.chars().nth(idx).unwrap()
-        })
+    let plan: Plan = plan.parse().unwrap();
+    signature_inequalities(&[plan], std::slice::from_ref(results))
+        .into_iter()
+        .map(|(_, a, _, b)| (a, b))
         .collect()
 }
 
@@ -800,6 +782,12 @@ fn main() {
                 // 5. 解が見つかったら、提出用のMap形式に変換
                 let final_map = solver.build_submission_map();
 
+                // 観測と合わない地図は提出しない
+                if let Err(e) = final_map.check_observations(&[&plan], &explore_response.results) {
+                    println!("Map contradicts the observations: {}. Try again!", e);
+                    continue;
+                }
+
                 // 6. 地図を提出
                 println!("Submitting the guess...");
                 let guess_res = api_client.guess(final_map).unwrap();
@@ -823,11 +811,6 @@ pub fn identify_omori2(
     plan: String,
     result: String,
 ) -> Option<SimulatedAnnealingSolver> {
-    let api_client = api::ApiClient::new();
-
-    let explore_response: api::ExploreResponse = api_client.explore(&vec![plan.clone()]).unwrap();
-    println!("Explore response: {:?}", explore_response);
-
     println!("Plan:    {}", plan);
     println!("Results: {}", result);
 
@@ -1,7 +1,7 @@
-use rand::Rng;
 use serde::Serialize;
 
-use crate::client::{Connection, Door};
+use common::api::{Connection, RoomAndDoor};
+use common::plan::{Plan, Step};
 
 #[derive(Debug, Clone, Serialize)]
 pub struct OutEdges {
@@ -43,15 +43,6 @@ pub fn get_ith_label(s: &String, i: usize) -> usize {
     return ch as usize - '0' as usize;
 }
 
-pub fn create_random_route(v: usize) -> String {
-    let mut route = String::new();
-    let mut rng = rand::thread_rng();
-    for _ in 0..v {
-        route.push_str(&format!("{}", rng.gen_range(0..6)));
-    }
-    return route;
-}
-
 pub fn query_result_to_string(query_result: &Vec<usize>) -> String {
     query_result
         .iter()
@@ -69,17 +60,45 @@ impl Action {
     }
 
     pub fn vec_to_str(actions: &[Action]) -> String {
-        actions
-            .iter()
-            .map(|a| a.to_string())
-            .collect::<Vec<String>>()
-            .join("")
+        Action::to_plan(actions).to_string()
     }
 
     pub fn from_string(s: &String) -> Vec<Action> {
-        s.chars()
-            .map(|c| Action::Door(c.to_digit(10).unwrap() as usize))
-            .collect()
+        Action::parse_plan(s)
+    }
+
+    // "[d]" も Mark として読む。不正な文字列は位置付きのエラーで panic する
+    pub fn parse_plan(s: &str) -> Vec<Action> {
+        let plan: Plan = s
+            .parse()
+            .unwrap_or_else(|e| panic!("invalid plan {:?}: {}", s, e));
+        Action::from_plan(&plan)
+    }
+
+    pub fn from_plan(plan: &Plan) -> Vec<Action> {
+        plan.steps().iter().map(|&step| step.into()).collect()
+    }
+
+    pub fn to_plan(actions: &[Action]) -> Plan {
+        actions.iter().cloned().map(Step::from).collect()
+    }
+}
+
+impl From<Step> for Action {
+    fn from(step: Step) -> Self {
+        match step {
+            Step::Door(d) => Action::Door(d),
+            Step::Mark(m) => Action::Mark(m),
+        }
+    }
+}
+
+impl From<Action> for Step {
+    fn from(action: Action) -> Self {
+        match action {
+            Action::Door(d) => Step::Door(d),
+            Action::Mark(m) => Step::Mark(m),
+        }
     }
 }
 
@@ -108,11 +127,11 @@ pub fn matrix_to_connections(matrix: &Vec<Vec<Option<usize>>>) -> Option<Vec<Con
             }
             for k in 0..doors[i][j].len() {
                 result.push(Connection {
-                    from: Door {
+                    from: RoomAndDoor {
                         room: i,
                         door: doors[i][j][k],
                     },
-                    to: Door {
+                    to: RoomAndDoor {
                         room: j,
                         door: doors[j][i][k],
                     },
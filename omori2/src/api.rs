@@ -1,12 +1,8 @@
-use std::{error::Error, fmt, time::Duration};
-
 use fxhash::{FxHashMap as HashMap, FxHashSet as HashSet};
 use rand::{seq::SliceRandom, thread_rng};
-use serde::{Deserialize, Serialize};
 
-//const BASE_URL: &str = "https://31pwr5t6ij.execute-api.eu-west-2.amazonaws.com";
-const BASE_URL: &str = "http://localhost:5000";
-const TEAM_ID: &str = "";
+pub use common::api::*;
+use common::plan::{Plan, Step};
 
 #[derive(Debug, Clone)]
 pub struct BaseMap {
@@ -203,147 +199,32 @@ pub enum PlanStep {
     ChangeLabel(usize), // new_label
 }
 
-// 経路計画文字列をパースする関数
-pub fn parse_full_plan(plan_str: &str) -> (Vec<PlanStep>, String) {
-    let mut full_plan = Vec::new();
-    let mut simple_plan = String::new(); // SAで使うドアのみのplan
-    let mut chars = plan_str.chars().peekable();
-
-    while let Some(c) = chars.next() {
-        if c == '[' {
-            if let Some(label_char) = chars.next() {
-                if let Some(label) = label_char.to_digit(10) {
-                    full_plan.push(PlanStep::ChangeLabel(label as usize));
-                }
-            }
-            chars.next(); // ']' をスキップ
-        } else if let Some(door) = c.to_digit(10) {
-            full_plan.push(PlanStep::Move(door as usize));
-            simple_plan.push(c);
+impl From<PlanStep> for Step {
+    fn from(step: PlanStep) -> Self {
+        match step {
+            PlanStep::Move(door) => Step::Door(door),
+            PlanStep::ChangeLabel(label) => Step::Mark(label),
         }
     }
-    (full_plan, simple_plan)
-}
-// /select
-#[derive(Serialize)]
-pub struct SelectRequest<'a> {
-    id: &'a str,
-    #[serde(rename = "problemName")]
-    problem_name: &'a str,
-}
-
-#[derive(Deserialize, Debug)]
-pub struct SelectResponse {
-    #[serde(rename = "problemName")]
-    problem_name: String,
-}
-
-// /explore
-#[derive(Serialize)]
-pub struct ExploreRequest<'a> {
-    id: &'a str,
-    plans: &'a [String],
-}
-
-#[derive(Deserialize, Debug)]
-pub struct ExploreResponse {
-    pub results: Vec<Vec<usize>>,
-    #[serde(rename = "queryCount")]
-    pub query_count: i32,
-}
-
-// /guess
-#[derive(Serialize, Debug)]
-pub struct GuessRequest<'a> {
-    id: &'a str,
-    map: Map,
-}
-
-#[derive(Serialize, Debug)]
-pub struct Map {
-    pub rooms: Vec<usize>,
-    #[serde(rename = "startingRoom")]
-    pub starting_room: usize,
-    pub connections: Vec<Connection>,
 }
 
-#[derive(Serialize, Debug)]
-pub struct Connection {
-    pub from: RoomAndDoor,
-    pub to: RoomAndDoor,
-}
-
-#[derive(Serialize, PartialEq, Eq, Hash, Clone, Copy)]
-pub struct RoomAndDoor {
-    pub room: usize,
-    pub door: usize,
-}
-// デバッグ出力用にfmt::Debugを実装
-impl fmt::Debug for RoomAndDoor {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "R{}.D{}", self.room, self.door)
-    }
-}
-
-#[derive(Deserialize, Debug)]
-pub struct GuessResponse {
-    pub correct: bool,
-}
-use reqwest::blocking::Client;
-// --- APIクライアント ---
-pub struct ApiClient {
-    client: Client,
-    base_url: String,
-}
-
-impl ApiClient {
-    pub fn new() -> Self {
-        ApiClient {
-            client: Client::builder()
-                .timeout(Duration::from_secs(30))
-                .build()
-                .unwrap(),
-            base_url: BASE_URL.to_string(),
+impl From<Step> for PlanStep {
+    fn from(step: Step) -> Self {
+        match step {
+            Step::Door(door) => PlanStep::Move(door),
+            Step::Mark(label) => PlanStep::ChangeLabel(label),
         }
     }
+}
 
-    pub fn select_problem(&self, problem: &str) -> Result<SelectResponse, Box<dyn Error>> {
-        let request_body = SelectRequest {
-            id: TEAM_ID,
-            problem_name: problem,
-        };
-        let response = self
-            .client
-            .post(format!("{}/select", self.base_url))
-            .json(&request_body)
-            .send()?
-            .json::<SelectResponse>()?;
-        Ok(response)
-    }
-
-    pub fn explore(&self, plans: &[String]) -> Result<ExploreResponse, Box<dyn Error>> {
-        let request_body = ExploreRequest { id: TEAM_ID, plans };
-        let response = self
-            .client
-            .post(format!("{}/explore", self.base_url))
-            .json(&request_body)
-            .send()?
-            .json::<ExploreResponse>()?;
-        Ok(response)
-    }
-
-    pub fn guess(&self, map: Map) -> Result<GuessResponse, Box<dyn Error>> {
-        let request_body = GuessRequest { id: TEAM_ID, map };
-        println!(
-            "Guessing with map: {:?}",
-            serde_json::to_string(&request_body)?
-        );
-        let response = self
-            .client
-            .post(format!("{}/guess", self.base_url))
-            .json(&request_body)
-            .send()?
-            .json::<GuessResponse>()?;
-        Ok(response)
-    }
+// 経路計画文字列をパースする関数
+// 不正な文字列は位置付きのエラーで panic する
+pub fn parse_full_plan(plan_str: &str) -> (Vec<PlanStep>, String) {
+    let plan: Plan = plan_str
+        .parse()
+        .unwrap_or_else(|e| panic!("invalid plan {:?}: {}", plan_str, e));
+    let full_plan = plan.steps().iter().map(|&step| step.into()).collect();
+    // SAで使うドアのみのplan
+    let simple_plan = plan.without_marks().to_string();
+    (full_plan, simple_plan)
 }
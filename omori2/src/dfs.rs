@@ -1,21 +1,36 @@
 use crate::api::{BaseMap, Connection, Map, PlanStep, RoomAndDoor};
+use common::alignment::{AlignedStep, aligned_steps};
+use common::observation_trie::ObservationTrie;
+use common::observations::Observations;
+use common::plan::{Plan, Step};
+use common::refinement::Refinement;
 use fixedbitset::FixedBitSet;
 use fxhash::FxHashMap as HashMap;
 
 /// DFSを使って、基本構造から完全なマップを構築するソルバー
+/// 複数の plan を順にたどる。部屋は plan をまとめた木の頂点ごとに割り当てるので、
+/// 共通の接頭辞は同じ部屋を通り、スタートは全 plan で同じ部屋になる
 pub struct DfsSolver {
     num_base_rooms: usize,
     base_map: BaseMap,
-    full_plan: Vec<PlanStep>,
-    observed_labels: Vec<usize>,
+    // plans[p][i] = p 番目の plan の i 歩目
+    plans: Vec<Vec<PlanStep>>,
+    // plans[p][i] の前後の観測の位置
+    aligned_steps: Vec<Vec<AlignedStep>>,
+    trie: ObservationTrie,
 
     // a -> b に行くためのドア一覧（bitsetで保持）
     remaining_base_doors: Vec<Vec<FixedBitSet>>,
 
     // --- DFS中の状態 ---
     connections: HashMap<RoomAndDoor, RoomAndDoor>,
+    // full_assignment[node] = 部屋 (木の頂点ごと)
     pub full_assignment: Vec<isize>,
+    // plan ごとに、ラベルはこの状態からやり直す
+    initial_labels: Vec<usize>,
     current_labels: Vec<usize>,
+    // same_room_as[node] = 同じ部屋と分かっている一番前の頂点 (common::refinement)
+    same_room_as: Vec<usize>,
 
     // ログ出力用のインデントレベル
     log_indent: usize,
@@ -89,12 +104,38 @@ fn twins_patterns(
 }
 
 impl DfsSolver {
-    /// 新しいソルバーを初期化する
+    /// `observations` の全 plan (`[d]` を含んでよい) とその結果から作る
+    pub fn from_observations(
+        base_map: BaseMap,
+        observations: &Observations,
+        layer_num: usize,
+    ) -> Self {
+        let plans: Vec<Plan> = observations
+            .plans
+            .iter()
+            .map(|plan| plan.parse().unwrap())
+            .collect();
+        Self::from_plans(base_map, &plans, &observations.results, layer_num)
+    }
+
+    /// plan 1つで新しいソルバーを初期化する
     pub fn new(
         base_map: BaseMap,
         full_plan: Vec<PlanStep>,
         observed_labels: Vec<usize>,
         layer_num: usize,
+    ) -> Self {
+        let plan: Plan = full_plan.into_iter().map(Step::from).collect();
+        Self::from_plans(base_map, &[plan], &[observed_labels], layer_num)
+    }
+
+    /// 複数の plan とその結果から作る。この順にたどるので、`[d]` で階層を決める plan を先に置くとよい。
+    /// 前処理で同じ部屋と分かった頂点は枝刈りに使う
+    pub fn from_plans(
+        base_map: BaseMap,
+        plans: &[Plan],
+        results: &[Vec<usize>],
+        layer_num: usize,
     ) -> Self {
         let num_base_rooms = base_map.num_rooms;
         let mut initial_labels = vec![0; num_base_rooms * layer_num];
@@ -103,8 +144,38 @@ impl DfsSolver {
             initial_labels[i] = (i % num_base_rooms) % 4;
         }
 
-        let mut full_assignment = vec![-1; observed_labels.len()];
+        let mut trie = ObservationTrie::new();
+        for (plan, result) in plans.iter().zip(results) {
+            trie.insert(plan, result)
+                .unwrap_or_else(|e| panic!("{}", e));
+        }
+
+        let mut full_assignment = vec![-1; trie.len()];
         full_assignment[0] = 0;
+        let mut same_room_as: Vec<usize> = (0..trie.len()).collect();
+        match Refinement::new(plans, results, num_base_rooms * layer_num) {
+            Ok(refinement) => {
+                let mut node_of_observation = vec![0; refinement.num_observations()];
+                for (plan_idx, path) in trie.paths().iter().enumerate() {
+                    for (obs_idx, &node) in path.iter().enumerate() {
+                        node_of_observation[refinement.observation(plan_idx, obs_idx)] = node;
+                    }
+                }
+                for (plan_idx, path) in trie.paths().iter().enumerate() {
+                    for (obs_idx, &node) in path.iter().enumerate() {
+                        let class = refinement.class_of(refinement.observation(plan_idx, obs_idx));
+                        // 同じクラスで一番前の頂点 (頂点はたどる順に番号がつく)
+                        same_room_as[node] = refinement
+                            .members(class)
+                            .iter()
+                            .map(|&m| node_of_observation[m])
+                            .min()
+                            .unwrap();
+                    }
+                }
+            }
+            Err(e) => println!("Refinement failed: {}", e),
+        }
 
         let mut remaining_base_doors =
             vec![vec![FixedBitSet::with_capacity(6); num_base_rooms]; num_base_rooms];
@@ -112,14 +183,31 @@ impl DfsSolver {
             remaining_base_doors[*from_room][*to_room].insert(*from_door);
         }
 
+        let aligned_steps = plans
+            .iter()
+            .map(|plan| aligned_steps(plan).collect())
+            .collect();
+        let plans = plans
+            .iter()
+            .map(|plan| {
+                plan.steps()
+                    .iter()
+                    .map(|&step| PlanStep::from(step))
+                    .collect()
+            })
+            .collect();
+
         Self {
             num_base_rooms,
             base_map,
-            full_plan,
+            plans,
+            aligned_steps,
+            trie,
             full_assignment,
-            observed_labels,
+            same_room_as,
             connections: HashMap::default(),
-            current_labels: initial_labels,
+            current_labels: initial_labels.clone(),
+            initial_labels,
             remaining_base_doors,
             log_indent: 0,
             layer_num,
@@ -150,7 +238,7 @@ impl DfsSolver {
     pub fn solve(&mut self) -> Option<Map> {
         self.log("DFS Solver started.");
 
-        let start_obs_label = self.observed_labels[0];
+        let start_obs_label = self.trie.nodes()[0].label;
         self.log(&format!("Observed start label: {}", start_obs_label));
 
         let base_starting_room = 0;
@@ -162,7 +250,7 @@ impl DfsSolver {
             start_candidate_0, self.current_labels[start_candidate_0]
         ));
         if self.current_labels[start_candidate_0] == start_obs_label {
-            if self.dfs(0, 0, start_candidate_0) {
+            if self.dfs(0, 0, 0, start_candidate_0) {
                 self.log("Solution found starting from R{}!");
                 self.fill_missing_connections_with_self_loop();
                 return Some(Map {
@@ -183,27 +271,50 @@ impl DfsSolver {
         return None;
     }
 
-    /// 深さ優先探索の再帰関数本体
-    fn dfs(&mut self, plan_idx: usize, obs_idx: usize, current_full_room: usize) -> bool {
+    /// 深さ優先探索の再帰関数本体。`plan` 番目の plan の `step` 歩目の前にいて、観測は木の頂点 `node`
+    fn dfs(&mut self, plan: usize, step: usize, node: usize, current_full_room: usize) -> bool {
         self.log_indent += 1;
         self.log(&format!(
-            "-> dfs(plan: {}, obs: {}, room: R{})",
-            plan_idx, obs_idx, current_full_room
+            "-> dfs(plan: {}, step: {}, node: {}, room: R{})",
+            plan, step, node, current_full_room
         ));
 
-        self.full_assignment[obs_idx] = current_full_room as isize;
+        // 前の plan で同じ頂点を通っていたら同じ部屋でなければ失敗
+        let assigned = self.full_assignment[node];
+        if assigned != -1 && assigned != current_full_room as isize {
+            self.log_indent -= 1;
+            return false;
+        }
+        // 前の観測と同じ部屋と分かっているのに違う部屋なら失敗
+        let same = self.same_room_as[node];
+        if same != node && self.full_assignment[same] != current_full_room as isize {
+            self.log_indent -= 1;
+            return false;
+        }
+        self.full_assignment[node] = current_full_room as isize;
         self.log(&format!(
-            "[Assign] obs #{} -> R{}",
-            obs_idx, current_full_room
+            "[Assign] node #{} -> R{}",
+            node, current_full_room
         ));
-        //println!("plan_idx: {} / {}", plan_idx, self.full_plan.len());
-        if plan_idx >= self.full_plan.len() {
-            self.log("  [Success] Reached end of plan.");
+        if step >= self.plans[plan].len() {
+            if plan + 1 >= self.plans.len() {
+                self.log("  [Success] Reached end of plans.");
+                self.log_indent -= 1;
+                return true;
+            }
+            // 次の plan はラベルを戻してスタート (頂点 0、部屋 0) から
+            self.log("  [Next plan] Reset labels.");
+            let labels = std::mem::replace(&mut self.current_labels, self.initial_labels.clone());
+            let success = self.dfs(plan + 1, 0, 0, 0);
+            self.current_labels = labels;
+            if !success && assigned == -1 {
+                self.full_assignment[node] = -1;
+            }
             self.log_indent -= 1;
-            return true;
+            return success;
         }
 
-        let result = match self.full_plan[plan_idx] {
+        let result = match self.plans[plan][step] {
             PlanStep::ChangeLabel(new_label) => {
                 self.log(&format!(
                     "[Action] ChangeLabel in R{} to {}",
@@ -212,8 +323,11 @@ impl DfsSolver {
                 let old_label = self.current_labels[current_full_room];
                 self.current_labels[current_full_room] = new_label;
 
-                // ChangeLabelも観測を生成するので、obs_idxを+1する
-                let success = self.dfs(plan_idx + 1, obs_idx + 1, current_full_room);
+                // ChangeLabelも観測を生成する
+                let next_node = self
+                    .trie
+                    .node_of(plan, self.aligned_steps[plan][step].after);
+                let success = self.dfs(plan, step + 1, next_node, current_full_room);
 
                 self.log(&format!(
                     "[Backtrack] Revert label in R{} to {}",
@@ -227,22 +341,24 @@ impl DfsSolver {
                     "[Action] Move from R{}.D{}",
                     current_full_room, door
                 ));
-                self.handle_move(plan_idx, obs_idx, current_full_room)
+                self.handle_move(plan, step, current_full_room)
             }
         };
 
-        if !result {
+        // 前の plan で割り当てた頂点はそのまま
+        if !result && assigned == -1 {
             self.log(&format!(
-                "[Backtrack] Unassign obs #{} from R{}",
-                obs_idx, current_full_room
+                "[Backtrack] Unassign node #{} from R{}",
+                node, current_full_room
             ));
-            self.full_assignment[obs_idx] = -1;
+            self.full_assignment[node] = -1;
         }
 
         self.log(&format!(
-            "<- dfs(plan: {}, obs: {}, room: R{}) -> {}",
-            plan_idx,
-            obs_idx,
+            "<- dfs(plan: {}, step: {}, node: {}, room: R{}) -> {}",
+            plan,
+            step,
+            node,
             current_full_room,
             if result { "Success" } else { "Fail" }
         ));
@@ -279,10 +395,12 @@ impl DfsSolver {
     }
 
     /// 移動(Move)ステップを処理するヘルパー関数
-    fn handle_move(&mut self, plan_idx: usize, obs_idx: usize, from_room: usize) -> bool {
-        if let PlanStep::Move(from_door) = self.full_plan[plan_idx] {
-            let next_obs_idx = obs_idx + 1;
-            let expected_label_at_dest = self.observed_labels[next_obs_idx];
+    fn handle_move(&mut self, plan: usize, step: usize, from_room: usize) -> bool {
+        if let PlanStep::Move(from_door) = self.plans[plan][step] {
+            let next_node = self
+                .trie
+                .node_of(plan, self.aligned_steps[plan][step].after);
+            let expected_label_at_dest = self.trie.nodes()[next_node].label;
             self.log(&format!(
                 "  Destination room must have label: {}",
                 expected_label_at_dest
@@ -305,7 +423,7 @@ impl DfsSolver {
                 ));
                 if self.current_labels[to_room] == expected_label_at_dest {
                     self.log("  Label matches. Following this path.");
-                    if self.dfs(plan_idx + 1, next_obs_idx, to_room) {
+                    if self.dfs(plan, step + 1, next_node, to_room) {
                         return true;
                     }
                 } else {
@@ -348,7 +466,7 @@ impl DfsSolver {
                     continue;
                 }
                 assert!(self.connect_twins(&pattern, from_door, to_door));
-                if self.dfs(plan_idx + 1, next_obs_idx, to_room) {
+                if self.dfs(plan, step + 1, next_node, to_room) {
                     return true;
                 }
                 self.disconnect_twins(&pattern, from_door, to_door);
@@ -763,6 +881,39 @@ mod tests {
         println!("✓ 2-layer with swap test passed!");
     }
 
+    #[test]
+    fn test_dfs_solver_multiple_plans() {
+        // 三角形を一周すると、印のない plan では分からないが、印をつけると別の階層に戻ってくる
+        let mut base_connections = HashMap::default();
+        base_connections.insert((0, 0), 1);
+        base_connections.insert((1, 0), 2);
+        base_connections.insert((2, 0), 0);
+        base_connections.insert((1, 1), 0);
+        base_connections.insert((2, 1), 1);
+        base_connections.insert((0, 1), 2);
+
+        let base_map = BaseMap {
+            num_rooms: 3,
+            starting_room: 0,
+            connections: base_connections,
+        };
+
+        // 先にたどる印のない plan で決めた接続を、後の plan が覆す
+        let plans: Vec<Plan> = vec!["000".parse().unwrap(), "[3]000".parse().unwrap()];
+        let results = vec![vec![0, 1, 2, 0], vec![0, 3, 1, 2, 0]];
+        let mut solver = DfsSolver::from_plans(base_map, &plans, &results, 2);
+        assert!(solver.solve().is_some(), "Should find a map for both plans");
+
+        // スタートは共通の頂点
+        assert_eq!(solver.trie.node_of(1, 0), 0);
+        assert!(solver.full_assignment.iter().all(|&room| room >= 0));
+        // 三角形を一周すると、どちらの plan でも別の階層の R0 (= R3) に着く
+        let end_a = solver.full_assignment[solver.trie.node_of(0, 3)];
+        let end_b = solver.full_assignment[solver.trie.node_of(1, 4)];
+        assert_eq!(end_a, 3);
+        assert_eq!(end_b, 3);
+    }
+
     #[test]
     fn test_dfs_solver_3layers() {
         // Layer = 3のテスト
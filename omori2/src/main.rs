@@ -1,24 +1,27 @@
 const NUM_PARALLEL_THREADS: usize = 1;
+// --plan で選べる plan の作り方
+const SUPPORTED_STRATEGIES: [Strategy; 3] = [
+    Strategy::UniformRandom,
+    Strategy::RandomMarks,
+    Strategy::DeBruijn,
+];
 pub mod api;
 pub mod dfs;
+pub mod pipeline;
 pub mod sa;
+use std::path::Path;
 use std::sync::{Arc, Mutex, mpsc};
 use std::thread;
 
 use rand::{Rng, thread_rng};
 
-use crate::api::{PlanStep, parse_full_plan};
+use crate::api::{ApiError, PlanStep, parse_full_plan};
 use crate::dfs::DfsSolver;
 use crate::sa::SimulatedAnnealingSolver;
-
-fn gen_random_string(alphabet: &str, length: usize, rng: &mut impl Rng) -> String {
-    (0..length)
-        .map(|_| {
-            let idx = rng.gen_range(0..alphabet.len());
-            alphabet.chars().nth(idx).unwrap()
-        })
-        .collect()
-}
+use common::budget::{BudgetTracker, Phase};
+use common::plan_generator::{FixedPlan, Strategy};
+use common::problem::Problem;
+use common::query_optimizer::load_plan;
 
 fn run_simulated_annealing(
     plan: &str,
@@ -102,32 +105,74 @@ fn run_simulated_annealing(
 }
 
 fn main() {
-    let api_client = api::ApiClient::new();
+    let problem = Problem::find("probatio").unwrap();
+    // --plan <name> で plan の作り方を選ぶ (デフォルトは random)。
+    // euler-tour は仮のマップが要るので使えない
+    let strategy = std::env::args()
+        .skip_while(|arg| arg != "--plan")
+        .nth(1)
+        .map_or(Ok(Strategy::UniformRandom), |name| {
+            Strategy::parse_supported(&name, &SUPPORTED_STRATEGIES)
+        })
+        .unwrap_or_else(|e| panic!("omori2 --plan: {}", e));
+    let mut generator = strategy.build(None).unwrap_or_else(|e| panic!("{}", e));
+    // --plan-file <path> なら onikubo で作った plan を毎回使う
+    if let Some(path) = std::env::args()
+        .skip_while(|arg| arg != "--plan-file")
+        .nth(1)
+    {
+        let mut plan = load_plan(Path::new(&path)).unwrap_or_else(|e| panic!("{}", e));
+        let removed = problem.truncate_plan(&mut plan);
+        if removed > 0 {
+            println!("{}: removed {} door steps over the limit", path, removed);
+        }
+        generator = Box::new(FixedPlan(plan));
+    }
+
+    if std::env::args().any(|arg| arg == "--async") {
+        let client = Arc::new(
+            common::async_api::AsyncApiClient::new().with_budget(BudgetTracker::default()),
+        );
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(pipeline::run(client, problem, generator.as_ref()));
+        return;
+    }
+
+    let api_client = api::ApiClient::new().with_budget(BudgetTracker::default());
     let mut rng = thread_rng();
 
     loop {
-        let num_base_rooms = 3;
-        let layer_num = 1;
-        let select_response = api_client.select_problem("probatio").unwrap();
+        let num_base_rooms = problem.rooms_per_layer;
+        let layer_num = problem.layers;
+        let select_response = match api_client.select_problem(problem.name) {
+            Ok(response) => response,
+            Err(e) if e.is_transient() => {
+                println!("Select API error: {}. Retrying...", e);
+                continue;
+            }
+            Err(e) => panic!("Select API error: {}", e),
+        };
         println!("Select response: {:?}", select_response);
 
-        let num_sum_rooms = num_base_rooms * layer_num;
-        let bb = if layer_num > 2 { 6 } else { 18 };
-        let simple_plan = gen_random_string("012345", num_sum_rooms * bb, &mut thread_rng());
-        let mut plan_with_labels = String::new();
-        for (i, door_char) in simple_plan.chars().enumerate() {
-            plan_with_labels.push_str(&format!("[{}]", rng.gen_range(0..4)));
-            plan_with_labels.push(door_char);
-        }
+        // SA にはドアだけの plan を使う
+        let (simple_plan, plan_with_labels) =
+            pipeline::simple_and_labeled_plans(&problem, generator.as_ref(), &mut rng);
 
         println!("explore...");
-        let explore_response = api_client
-            .explore(&vec![simple_plan.clone(), plan_with_labels.clone()])
-            .map_err(|e| {
-                println!("Explore API error: {:?}", e);
-                e
-            })
-            .unwrap();
+        // ドアだけの plan で基本構造を、印つきの plan で階層を決める
+        if let Some(budget) = api_client.budget() {
+            budget.set_phases(&[Phase::Identification, Phase::LayerResolution]);
+        }
+        let explore_response =
+            match api_client.explore(&[simple_plan.clone(), plan_with_labels.clone()]) {
+                Ok(response) => response,
+                // 一時的な失敗や、選択中の問題が失われた(400)場合は select からやり直す
+                Err(e) if e.is_transient() || matches!(e, ApiError::Status { status: 400, .. }) => {
+                    println!("Explore API error: {}. Reselecting...", e);
+                    continue;
+                }
+                Err(e) => panic!("Explore API error: {}", e),
+            };
         let results_simple_vec = explore_response.results[0].clone();
         let results_simple_str = results_simple_vec
             .iter()
@@ -170,19 +215,41 @@ fn main() {
 
             // 2c. DFSで階層を解決
             println!("\n--- Running DFS to resolve layers ---");
-            let full_plan_steps: Vec<api::PlanStep> = parse_full_plan(&plan_with_labels).0;
-
-            let mut dfs_solver = DfsSolver::new(base_map, full_plan_steps, results_labeled_vec, 2);
+            let mut dfs_solver = DfsSolver::from_plans(
+                base_map,
+                &[
+                    plan_with_labels.parse().unwrap(),
+                    simple_plan.parse().unwrap(),
+                ],
+                &[results_labeled_vec, results_simple_vec],
+                2,
+            );
 
             if let Some(solution) = dfs_solver.solve() {
                 println!("\n★ DFS successfully found a consistent path through layers! ★");
 
+                // 観測と合わない地図は提出しない
+                if let Err(e) = solution.check_observations(
+                    &[&simple_plan, &plan_with_labels],
+                    &explore_response.results,
+                ) {
+                    println!("Map contradicts the observations: {}. Retrying...", e);
+                    continue;
+                }
                 println!("Submitting the guess...");
-                let guess_res = api_client.guess(solution).unwrap();
+                let guess_res = match api_client.guess(solution) {
+                    Ok(guess_res) => guess_res,
+                    Err(e) if e.is_transient() => {
+                        println!("Guess API error: {}. Retrying the whole process...", e);
+                        continue;
+                    }
+                    Err(e) => panic!("Guess API error: {}", e),
+                };
                 println!("Guess result: correct = {}", guess_res.correct);
 
                 if guess_res.correct {
                     println!("★★★ Congratulations! Your map was correct! ★★★");
+                    println!("{}", api_client.budget().unwrap().report());
                     break;
                 } else {
                     println!("Map was incorrect. Retrying the whole process...");
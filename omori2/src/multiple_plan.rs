@@ -9,6 +9,8 @@ use std::sync::mpsc;
 use std::sync::{Arc, Mutex};
 use std::thread;
 use crate::api::{Connection, Map, RoomAndDoor};
+use common::plan::Plan;
+use common::signature::signature_inequalities;
 
 // --- 焼きなましパラメータ ---
 const INITIAL_TEMPERATURE: f64 = 1.0;
@@ -682,42 +684,13 @@ impl SimulatedAnnealingSolver {
 }
 
 // シグネチャ： ある長さのsuffixに対して、resultsが少しでも異なるなら、異なる部屋がわりあたるべきだ.
-// returns 複数の不等式
-fn find_signatures_ineqs(plans: Vec<String>, results: &Vec<Vec<usize>>) -> Vec<(usize, usize, usize, usize)> {
-    let mut inequalities = HashSet::default();
-    for start_plan in 0..plans.len() {
-        for sig_len in 1..plans[start_plan].len() {
-            for sig_start in 0..plans[start_plan].len() - sig_len {
-                let sig = &plans[start_plan][sig_start..sig_start + sig_len];
-                // 他の場所で同じsubstringがあるか?
-                for other_plan in start_plan..plans.len() {
-                    for other_start in match start_plan == other_plan {
-                        true => sig_start + 1..plans[other_plan].len() - sig_len,
-                        false => 0..plans[other_plan].len() - sig_len
-                    } {
-                        if results[start_plan][sig_start] != results[other_plan][other_start] {
-                            continue;
-                        }
-                        let other = &plans[other_plan][other_start..other_start + sig_len];
-                        if sig == other {
-                            // 同じsubstringが見つかった
-                            // それぞれに対応するresultsを比較して、最後の文字だけが異なるなら、不要しき
-                            if (0..sig_len).all(|i| {
-                                if i < sig_len - 1 {
-                                    results[start_plan][1 + sig_start + i] == results[other_plan][1 + other_start + i]
-                                } else {
-                                    results[start_plan][1 + sig_start + i] != results[other_plan][1 + other_start + i]
-                                }
-                            }) {
-                                inequalities.insert((start_plan, sig_start, other_plan, other_start));
-                            }
-                        }
-                    }
-                }
-            }
-        }
-    }
-    inequalities.into_iter().collect()
+// returns 複数の不等式 (common::signature で接尾辞配列を使って求める)
+fn find_signatures_ineqs(
+    plans: Vec<String>,
+    results: &Vec<Vec<usize>>,
+) -> Vec<(usize, usize, usize, usize)> {
+    let plans: Vec<Plan> = plans.iter().map(|plan| plan.parse().unwrap()).collect();
+    signature_inequalities(&plans, results)
 }
 
 fn gen_random_string(alphabet: &str, length: usize, rng: &mut impl Rng) -> String {
@@ -824,6 +797,12 @@ fn main() {
                 // 5. 解が見つかったら、提出用のMap形式に変換
                 let final_map = solver.build_submission_map();
 
+                // 観測と合わない地図は提出しない
+                if let Err(e) = final_map.check_observations(&plans, &explore_response.results) {
+                    println!("Map contradicts the observations: {}. Try again!", e);
+                    continue;
+                }
+
                 // 6. 地図を提出
                 println!("Submitting the guess...");
                 let guess_res = api_client.guess(final_map).unwrap();
@@ -0,0 +1,234 @@
+// 非同期版のメインループ (`--async`)
+//
+// 1回の select の間に、次のバッチの /explore・前のバッチの焼きなまし + DFS・
+// 候補マップの検証を並行して進める。
+// guess すると問題の選択が外れるので、その時点で残りの焼きなましは stop_signal で止める。
+use std::sync::{Arc, Mutex};
+
+use common::async_api::AsyncApiClient;
+use common::budget::Phase;
+use common::plan::Step;
+use common::plan_generator::PlanGenerator;
+use common::problem::Problem;
+use rand::{Rng, thread_rng};
+use tokio::task::{JoinHandle, JoinSet};
+
+use crate::api::{ApiError, Map, parse_full_plan};
+use crate::dfs::DfsSolver;
+use crate::sa::SimulatedAnnealingSolver;
+
+// 同時に走らせる焼きなましの数。これ未満のときだけ次のバッチを先に explore する
+const MAX_SA_TASKS: usize = 2;
+
+// 1回の /explore で送る plan の組と、その結果
+struct Batch {
+    simple_plan: String,
+    labeled_plan: String,
+    simple_results: Vec<usize>,
+    labeled_results: Vec<usize>,
+}
+
+impl Batch {
+    fn is_consistent_with(&self, map: &Map) -> bool {
+        map.check_observations(
+            &[&self.simple_plan, &self.labeled_plan],
+            &[&self.simple_results, &self.labeled_results],
+        )
+        .is_ok()
+    }
+}
+
+pub async fn run(client: Arc<AsyncApiClient>, problem: Problem, generator: &dyn PlanGenerator) {
+    loop {
+        match client.select_problem(problem.name).await {
+            Ok(response) => println!("Select response: {:?}", response),
+            Err(e) if e.is_transient() => {
+                println!("Select API error: {}. Retrying...", e);
+                continue;
+            }
+            Err(e) => panic!("Select API error: {}", e),
+        }
+        if solve_selected(&client, problem, generator).await {
+            println!("★★★ Congratulations! Your map was correct! ★★★");
+            if let Some(budget) = client.budget() {
+                println!("{}", budget.report());
+            }
+            break;
+        }
+        println!("Retrying the whole process...");
+    }
+}
+
+// 選択中の問題を解いて guess する。正解なら true
+async fn solve_selected(
+    client: &Arc<AsyncApiClient>,
+    problem: Problem,
+    generator: &dyn PlanGenerator,
+) -> bool {
+    let (num_base_rooms, layer_num) = (problem.rooms_per_layer, problem.layers);
+    let mut batches: Vec<Arc<Batch>> = vec![];
+    let mut stop_signals: Vec<Arc<Mutex<bool>>> = vec![];
+    let mut sa_tasks: JoinSet<Option<Map>> = JoinSet::new();
+    let mut explore_task = Some(spawn_explore(client.clone(), &problem, generator));
+
+    let correct = loop {
+        tokio::select! {
+            joined = async { explore_task.as_mut().unwrap().await }, if explore_task.is_some() => {
+                explore_task = None;
+                let batch = match joined.expect("explore task panicked") {
+                    Ok(batch) => Arc::new(batch),
+                    // 一時的な失敗や、選択中の問題が失われた(400)場合は select からやり直す
+                    Err(e) => {
+                        println!("Explore API error: {}. Reselecting...", e);
+                        break false;
+                    }
+                };
+                let thread_id = batches.len();
+                let stop_signal = Arc::new(Mutex::new(false));
+                batches.push(batch.clone());
+                stop_signals.push(stop_signal.clone());
+                sa_tasks.spawn_blocking(move || {
+                    solve_batch(thread_id, &batch, num_base_rooms, layer_num, stop_signal)
+                });
+                // 焼きなましの枠が空いていれば、終わるのを待たずに次のバッチを取りに行く
+                if sa_tasks.len() < MAX_SA_TASKS {
+                    explore_task = Some(spawn_explore(client.clone(), &problem, generator));
+                }
+            }
+            Some(joined) = sa_tasks.join_next() => {
+                match joined.expect("SA task panicked") {
+                    // 他のバッチの観測とも合うか手元で確かめてから guess する
+                    Some(map) if batches.iter().all(|batch| batch.is_consistent_with(&map)) => {
+                        stop_all(&stop_signals);
+                        if let Some(task) = explore_task.take() {
+                            task.abort();
+                        }
+                        println!("Submitting the guess...");
+                        match client.guess(map).await {
+                            Ok(res) => {
+                                println!("Guess result: correct = {}", res.correct);
+                                break res.correct;
+                            }
+                            Err(e) => {
+                                println!("Guess API error: {}", e);
+                                break false;
+                            }
+                        }
+                    }
+                    Some(_) => println!("Candidate contradicts another batch. Discarding..."),
+                    None => println!("SA failed on a batch."),
+                }
+                if explore_task.is_none() {
+                    explore_task = Some(spawn_explore(client.clone(), &problem, generator));
+                }
+            }
+            else => break false,
+        }
+    };
+
+    stop_all(&stop_signals);
+    if let Some(task) = explore_task {
+        task.abort();
+    }
+    // spawn_blocking のタスクは abort できないので、stop_signal で止まるのを待つ
+    while sa_tasks.join_next().await.is_some() {}
+    correct
+}
+
+fn stop_all(stop_signals: &[Arc<Mutex<bool>>]) {
+    for stop_signal in stop_signals {
+        *stop_signal.lock().unwrap() = true;
+    }
+}
+
+/// ドアだけの plan と、同じドアに印をつけた plan を作る。
+/// 生成した plan に印があればそのまま使い、なければドアの前ごとにランダムな印をつける
+pub fn simple_and_labeled_plans(
+    problem: &Problem,
+    generator: &dyn PlanGenerator,
+    rng: &mut impl Rng,
+) -> (String, String) {
+    let plan = generator.generate(problem.num_rooms(), problem.max_door_steps(), rng);
+    let simple_plan = plan.without_marks().to_string();
+    if plan
+        .steps()
+        .iter()
+        .any(|step| matches!(step, Step::Mark(_)))
+    {
+        return (simple_plan, plan.to_string());
+    }
+    let mut labeled_plan = String::new();
+    for door_char in simple_plan.chars() {
+        labeled_plan.push_str(&format!("[{}]", rng.gen_range(0..4)));
+        labeled_plan.push(door_char);
+    }
+    (simple_plan, labeled_plan)
+}
+
+fn spawn_explore(
+    client: Arc<AsyncApiClient>,
+    problem: &Problem,
+    generator: &dyn PlanGenerator,
+) -> JoinHandle<Result<Batch, ApiError>> {
+    // thread_rng は await をまたげないので、plan はここで作っておく
+    let (simple_plan, labeled_plan) =
+        simple_and_labeled_plans(problem, generator, &mut thread_rng());
+
+    tokio::spawn(async move {
+        println!("explore...");
+        // ドアだけの plan で基本構造を、印つきの plan で階層を決める
+        if let Some(budget) = client.budget() {
+            budget.set_phases(&[Phase::Identification, Phase::LayerResolution]);
+        }
+        let plans = [simple_plan, labeled_plan];
+        let response = client.explore(&plans).await?;
+        let [simple_plan, labeled_plan] = plans;
+        let mut results = response.results.into_iter();
+        Ok(Batch {
+            simple_plan,
+            labeled_plan,
+            simple_results: results.next().unwrap(),
+            labeled_results: results.next().unwrap(),
+        })
+    })
+}
+
+// 焼きなましで基本構造を決め、DFSで階層を解決する
+fn solve_batch(
+    thread_id: usize,
+    batch: &Batch,
+    num_base_rooms: usize,
+    layer_num: usize,
+    stop_signal: Arc<Mutex<bool>>,
+) -> Option<Map> {
+    let results_simple_str = batch
+        .simple_results
+        .iter()
+        .map(|&x| std::char::from_digit(x as u32, 10).unwrap())
+        .collect::<String>();
+    let full_plan_steps = parse_full_plan(&batch.labeled_plan).0;
+
+    let mut solver =
+        SimulatedAnnealingSolver::new(&batch.simple_plan, &results_simple_str, num_base_rooms);
+    solver.solve(
+        thread_id,
+        stop_signal,
+        full_plan_steps,
+        batch.labeled_results.clone(),
+        layer_num,
+    )?;
+    if !solver.is_valid_assignment() {
+        return None;
+    }
+    // 印つきの plan で階層を決めてから、印のない plan とも合うか確かめる
+    let mut dfs_solver = DfsSolver::from_plans(
+        solver.build_base_map(),
+        &[
+            batch.labeled_plan.parse().unwrap(),
+            batch.simple_plan.parse().unwrap(),
+        ],
+        &[batch.labeled_results.clone(), batch.simple_results.clone()],
+        layer_num,
+    );
+    dfs_solver.solve()
+}
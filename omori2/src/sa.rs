@@ -10,6 +10,10 @@ use rand::thread_rng;
 use crate::api::BaseMap;
 use crate::api::PlanStep;
 use crate::dfs::DfsSolver;
+use common::observations::Observations;
+use common::plan::{Plan, Step};
+use common::refinement::Refinement;
+use common::signature::signature_inequalities;
 // --- 焼きなましパラメータ ---
 const INITIAL_TEMPERATURE: f64 = 100.0;
 const COOLING_RATE: f64 = 0.99999;
@@ -35,9 +39,28 @@ pub struct SimulatedAnnealingSolver {
 
     known_inequalities: Vec<(usize, usize)>, // (obs_idx1, obs_idx2) という形で、obs_idx1 != obs_idx2 であるべきことを示す
     known_inequalities_by_obs_idx: Vec<Vec<usize>>,
+
+    // 同じ部屋と分かっている観測のまとまり (common::refinement)。まとめて動かす
+    classes: Vec<Vec<usize>>,
+    class_candidates: Vec<Vec<usize>>, // class -> 割り当ててよい room_id
+    movable_classes: Vec<usize>,       // 候補が2つ以上あるクラス
 }
 
 impl SimulatedAnnealingSolver {
+    /// `observations` の `plan_idx` 番目 (`[d]` を含まない plan) から作る
+    pub fn from_observations(
+        observations: &Observations,
+        plan_idx: usize,
+        num_rooms: usize,
+    ) -> Self {
+        assert!(!observations.has_marks(plan_idx));
+        Self::new(
+            &observations.plans[plan_idx],
+            &observations.results_str(plan_idx),
+            num_rooms,
+        )
+    }
+
     pub fn new(plan_str: &str, results_str: &str, num_rooms: usize) -> Self {
         let plan: Vec<usize> = plan_str
             .chars()
@@ -49,6 +72,11 @@ impl SimulatedAnnealingSolver {
             .collect();
 
         let known_inequalities = find_signatures_ineqs(plan_str, &observed_labels);
+        let (classes, class_candidates) =
+            room_classes(&plan_str.parse().unwrap(), &observed_labels, num_rooms);
+        let movable_classes = (0..classes.len())
+            .filter(|&class| class_candidates[class].len() > 1)
+            .collect();
 
         let num_observations = observed_labels.len();
 
@@ -78,6 +106,9 @@ impl SimulatedAnnealingSolver {
             kasikari_count: vec![vec![0; MAX_ROOMS]; MAX_ROOMS],
             known_inequalities,
             known_inequalities_by_obs_idx,
+            classes,
+            class_candidates,
+            movable_classes,
         };
 
         solver.recalculate_cost();
@@ -296,21 +327,18 @@ impl SimulatedAnnealingSolver {
 
         layer_num: usize,
     ) -> Option<Vec<usize>> {
-        let mut label_candidates_per_label = vec![vec![]; 4];
-        for room_id in 0..self.num_rooms {
-            let label = room_id % 4;
-            label_candidates_per_label[label].push(room_id);
-        }
-        println!("Label candidates: {:?}", label_candidates_per_label);
+        println!(
+            "Classes: {} (observations: {}), movable: {}",
+            self.classes.len(),
+            self.assignment.len(),
+            self.movable_classes.len()
+        );
         let mut rng = thread_rng();
 
         // 初期化
-        for obs_idx in 1..self.assignment.len() {
-            let label = self.observed_labels[obs_idx];
-            self.assignment[obs_idx] = label_candidates_per_label[label]
-                .choose(&mut rng)
-                .unwrap()
-                .to_owned();
+        for class in 0..self.classes.len() {
+            let room = *self.class_candidates[class].choose(&mut rng).unwrap();
+            self.assign_class(class, room);
         }
         self.assignment[0] = 0;
         self.recalculate_cost();
@@ -338,10 +366,13 @@ impl SimulatedAnnealingSolver {
                 } else {
                     let base_map = self.build_base_map();
 
-                    let mut dfs_solver = DfsSolver::new(
+                    let mut dfs_solver = DfsSolver::from_plans(
                         base_map,
-                        full_plan_steps.clone(),
-                        results_labeled_vec.clone(),
+                        &[
+                            full_plan_steps.iter().cloned().map(Step::from).collect(),
+                            self.simple_plan(),
+                        ],
+                        &[results_labeled_vec.clone(), self.observed_labels.clone()],
                         layer_num,
                     );
 
@@ -354,14 +385,16 @@ impl SimulatedAnnealingSolver {
                     }
                 }
                 if invalid {
+                    if self.movable_classes.is_empty() {
+                        println!("[Thread {}] Every room is fixed. Giving up.", thread_id);
+                        return None;
+                    }
                     // 適当にkick
-                    for obs_idx in 1..self.assignment.len() {
+                    for i in 0..self.movable_classes.len() {
                         if rng.gen_bool(0.6) {
-                            let label = self.observed_labels[obs_idx];
-                            self.assignment[obs_idx] = label_candidates_per_label[label]
-                                .choose(&mut rng)
-                                .unwrap()
-                                .to_owned();
+                            let class = self.movable_classes[i];
+                            let room = *self.class_candidates[class].choose(&mut rng).unwrap();
+                            self.assign_class(class, room);
                         }
                     }
                     self.recalculate_cost();
@@ -374,33 +407,32 @@ impl SimulatedAnnealingSolver {
             if i % 100000 == 0 && i > 0 {
                 // 特定のラベルのノードを30%くらいリセットする
                 //println!("[Thread {}] Resetting all nodes", thread_id);
-                for obs_idx in 1..self.assignment.len() {
+                for i in 0..self.movable_classes.len() {
                     if rng.gen_bool(0.05) {
-                        self.assignment[obs_idx] = label_candidates_per_label
-                            [self.observed_labels[obs_idx]]
-                            .choose(&mut rng)
-                            .unwrap()
-                            .to_owned();
+                        let class = self.movable_classes[i];
+                        let room = *self.class_candidates[class].choose(&mut rng).unwrap();
+                        self.assign_class(class, room);
                     }
                 }
                 self.recalculate_cost();
             } else {
-                let obs_idx_to_move = rng.gen_range(1..self.assignment.len());
-                let old_room = self.assignment[obs_idx_to_move];
-
-                let new_room = loop {
-                    let label = self.observed_labels[obs_idx_to_move];
-                    break label_candidates_per_label[label]
-                        .choose(&mut rng)
-                        .unwrap()
-                        .to_owned();
+                // 全部の部屋が決まっているなら動かせない
+                let Some(&class_to_move) = self.movable_classes.choose(&mut rng) else {
+                    break;
                 };
+                let old_room = self.assignment[self.classes[class_to_move][0]];
+
+                let new_room = *self.class_candidates[class_to_move]
+                    .choose(&mut rng)
+                    .unwrap();
                 if new_room == old_room {
                     continue;
                 }
 
-                // 差分計算を使用（assignmentを更新する前に呼ぶ）
-                self.update_point(obs_idx_to_move, new_room);
+                // 差分計算を使用（assignmentを更新する前に呼ぶ）。同じクラスの観測はまとめて動かす
+                for i in 0..self.classes[class_to_move].len() {
+                    self.update_point(self.classes[class_to_move][i], new_room);
+                }
 
                 let new_cost = self.cost;
                 let cost_delta = new_cost - original_cost;
@@ -412,7 +444,9 @@ impl SimulatedAnnealingSolver {
                 {
                     // 遷移を承認
                 } else {
-                    self.update_point(obs_idx_to_move, old_room);
+                    for i in (0..self.classes[class_to_move].len()).rev() {
+                        self.update_point(self.classes[class_to_move][i], old_room);
+                    }
                     assert!(self.cost == original_cost);
                 }
             }
@@ -457,6 +491,21 @@ impl SimulatedAnnealingSolver {
         println!("[Thread {}] Final num_rooms: {}", thread_id, self.num_rooms);
         None
     }
+    /// SA に使ったドアだけの plan
+    fn simple_plan(&self) -> Plan {
+        self.transitions
+            .iter()
+            .map(|&(_, door)| Step::Door(door))
+            .collect()
+    }
+
+    /// クラスの観測を全部 `room` にする (コストは再計算しない)
+    fn assign_class(&mut self, class: usize, room: usize) {
+        for &obs_idx in &self.classes[class] {
+            self.assignment[obs_idx] = room;
+        }
+    }
+
     pub fn build_base_map(&self) -> BaseMap {
         let mut connections = HashMap::default();
         for (from_idx, door) in self.transitions.iter() {
@@ -519,38 +568,88 @@ impl SimulatedAnnealingSolver {
     }
 }
 
-// シグネチャ： ある長さのsuffixに対して、resultsが少しでも異なるなら、異なる部屋がわりあたるべきだ.
-// returns 複数の不等式
-fn find_signatures_ineqs(plan: &str, results: &Vec<usize>) -> Vec<(usize, usize)> {
-    let mut inequalities = HashSet::default();
-    for sig_len in (1..plan.len()) {
-        for sig_start in (0..=plan.len() - sig_len) {
-            let sig = &plan[sig_start..sig_start + sig_len];
-            // 他の場所で同じsubstringがあるか?
-            for other_start in (sig_start + 1..=plan.len() - sig_len) {
-                if results[sig_start] != results[other_start] {
-                    continue;
-                }
-                let other = &plan[other_start..other_start + sig_len];
-                if sig == other {
-                    // 同じsubstringが見つかった
-                    // それぞれに対応するresultsを比較して、最後の文字だけが異なるなら、不要しき
-                    if (0..sig_len).all(|i| {
-                        if i < sig_len - 1 {
-                            results[1 + sig_start + i] == results[1 + other_start + i]
-                        } else {
-                            results[1 + sig_start + i] != results[1 + other_start + i]
-                        }
-                    }) {
-                        if sig_start < other_start {
-                            inequalities.insert((sig_start, other_start));
-                        } else {
-                            inequalities.insert((other_start, sig_start));
-                        }
-                    }
+/// 観測をクラス (common::refinement で同じ部屋と分かったもの) にまとめ、各クラスに割り当ててよい部屋を求める
+///
+/// 部屋のラベルは room_id % 4 で、スタートは部屋 0。互いに違う部屋と分かったクラスには同じラベルの部屋を小さい順に割り当てる。
+/// 前処理が矛盾したり部屋が足りなかったりしたら、観測ごとにラベルだけで候補を作る
+fn room_classes(
+    plan: &Plan,
+    observed_labels: &[usize],
+    num_rooms: usize,
+) -> (Vec<Vec<usize>>, Vec<Vec<usize>>) {
+    let rooms_with_label =
+        |label: usize| -> Vec<usize> { (0..num_rooms).filter(|r| r % 4 == label).collect() };
+    let fallback = || {
+        let classes = (0..observed_labels.len()).map(|i| vec![i]).collect();
+        let candidates = observed_labels
+            .iter()
+            .enumerate()
+            .map(|(i, &label)| {
+                if i == 0 {
+                    vec![0]
+                } else {
+                    rooms_with_label(label)
                 }
+            })
+            .collect();
+        (classes, candidates)
+    };
+    let refinement = match Refinement::new(
+        std::slice::from_ref(plan),
+        &[observed_labels.to_vec()],
+        num_rooms,
+    ) {
+        Ok(refinement) => refinement,
+        Err(e) => {
+            println!("Refinement failed: {}", e);
+            return fallback();
+        }
+    };
+
+    let mut used = vec![false; num_rooms];
+    let mut room_of_anchor = vec![];
+    for &anchor in refinement.anchors() {
+        let label = refinement.label(anchor).unwrap();
+        let Some(room) = (0..num_rooms).find(|&r| r % 4 == label && !used[r]) else {
+            println!("Refinement: no room left for label {}", label);
+            return fallback();
+        };
+        used[room] = true;
+        room_of_anchor.push(room);
+    }
+    if room_of_anchor[0] != 0 {
+        return fallback();
+    }
+
+    let mut classes = vec![];
+    let mut candidates = vec![];
+    for class in 0..refinement.num_classes() {
+        let label = refinement.label(class).unwrap();
+        let mut rooms = vec![];
+        for &room in refinement.candidates(class) {
+            match room_of_anchor.get(room) {
+                Some(&r) => rooms.push(r),
+                // まだ観測していない部屋は、anchor に使っていない同じラベルの部屋のどれか
+                None => rooms.extend(rooms_with_label(label).into_iter().filter(|&r| !used[r])),
             }
         }
+        rooms.sort_unstable();
+        rooms.dedup();
+        if rooms.is_empty() {
+            return fallback();
+        }
+        classes.push(refinement.members(class).to_vec());
+        candidates.push(rooms);
     }
-    inequalities.into_iter().collect()
+    (classes, candidates)
+}
+
+// シグネチャ： ある長さのsuffixに対して、resultsが少しでも異なるなら、異なる部屋がわりあたるべきだ.
+// returns 複数の不等式 (common::signature で接尾辞配列を使って求める)
+fn find_signatures_ineqs(plan: &str, results: &Vec<usize>) -> Vec<(usize, usize)> {
+    let plan: Plan = plan.parse().unwrap();
+    signature_inequalities(&[plan], std::slice::from_ref(results))
+        .into_iter()
+        .map(|(_, a, _, b)| (a, b))
+        .collect()
 }
@@ -0,0 +1,52 @@
+// make_query.cpp の Rust 版 (焼きなましでクエリを作る)
+//
+// 使い方: cargo run --release -p onikubo -- --rooms 6 --layers 2 --time 60 [--steps S] [--samples 500] [--seed 0] [--out query.txt]
+// --rooms は1層あたりの部屋数。--steps のデフォルトはその問題の plan の上限。
+// 結果は1行の plan として --out に書くので、omori2 の --plan-file でそのまま使える。
+use std::{path::Path, time::Duration};
+
+use rand::{SeedableRng, rngs::StdRng};
+
+use common::plan_generator::{PlanGenerator, UniformRandom};
+use common::problem::max_door_steps;
+use common::query_optimizer::{QueryOptimizer, save_plan};
+
+/// `--name value` の value
+fn arg<T: std::str::FromStr>(name: &str) -> Option<T> {
+    let value = std::env::args().skip_while(|arg| arg != name).nth(1)?;
+    Some(
+        value
+            .parse()
+            .unwrap_or_else(|_| panic!("invalid value for {}: {}", name, value)),
+    )
+}
+
+fn main() {
+    let rooms_per_layer: usize = arg("--rooms").expect("--rooms is required");
+    let layers: usize = arg("--layers").unwrap_or(1);
+    let time_limit = Duration::from_secs_f64(arg("--time").expect("--time is required"));
+    let num_rooms = rooms_per_layer * layers;
+    let door_steps = arg("--steps").unwrap_or_else(|| max_door_steps(num_rooms, layers));
+    let out: String = arg("--out").unwrap_or_else(|| "query.txt".to_string());
+
+    let mut optimizer = QueryOptimizer::new(layers, time_limit);
+    if let Some(samples) = arg("--samples") {
+        optimizer.samples = samples;
+    }
+    let mut rng = arg("--seed").map_or_else(StdRng::from_entropy, StdRng::seed_from_u64);
+    println!(
+        "rooms: {} x {}, steps: {}, threads: {}",
+        rooms_per_layer, layers, door_steps, optimizer.threads
+    );
+
+    let initial = UniformRandom.generate(num_rooms, door_steps, &mut rng);
+    let optimized = optimizer.optimize(rooms_per_layer, &initial, &mut rng);
+    println!(
+        "score: {} ({} unvisited doors per map), iterations: {}",
+        optimized.score,
+        optimized.score as f64 / optimizer.samples as f64,
+        optimized.iterations
+    );
+    save_plan(Path::new(&out), &optimized.plan).unwrap_or_else(|e| panic!("{}", e));
+    println!("Saved to {}", out);
+}